@@ -0,0 +1,115 @@
+//! Reads the frames that `build.rs` bakes.
+//!
+//! A baked frame is `DSBK`, followed by the width and height of the frame
+//! and the x, y, width and height of the part of it that isn't transparent,
+//! all as little endian `u32`s. After that comes the non-transparent part,
+//! as a QOI image, which decodes many times faster than a PNG.
+//!
+//! Decoded frames have the same pixels as the PNGs they were baked from,
+//! except that transparent pixels outside of the visible part are always
+//! transparent black, whatever colour the PNG gave them. They're invisible
+//! either way, and frames are premultiplied before they're drawn (see
+//! `assets::resize`), which turns every transparent pixel black anyway.
+
+use failure;
+use image;
+use qoi;
+
+use failure::Error;
+use image::{ DynamicImage, RgbaImage };
+
+const HEADER: usize = 4 + 6 * 4;
+
+/// The size of a baked frame, and where its visible pixels are.
+#[derive(Debug, Copy, Clone)]
+pub struct Header {
+    pub size: (u32, u32),
+    /// The x, y, width and height of the part of the frame that isn't
+    /// transparent.
+    pub bounds: (u32, u32, u32, u32),
+}
+
+/// Reads the header of a baked frame.
+pub fn header(bytes: &[u8]) -> Result<Header, Error> {
+    if bytes.len() < HEADER || &bytes[..4] != b"DSBK" {
+        return Err(failure::format_err!("This isn't a baked frame."));
+    }
+    let value = |index: usize| {
+        let offset = 4 + index * 4;
+        u32::from_le_bytes([ bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3] ])
+    };
+    Ok(Header {
+        size: (value(0), value(1)),
+        bounds: (value(2), value(3), value(4), value(5)),
+    })
+}
+
+/// Decodes a baked frame.
+pub fn decode(bytes: &[u8]) -> Result<DynamicImage, Error> {
+    let Header { size, bounds: (x, y, w, h) } = header(bytes)?;
+    let mut frame = RgbaImage::new(size.0, size.1);
+    if w > 0 && h > 0 {
+        let (_, pixels) = qoi::decode_to_vec(&bytes[HEADER..])
+            .map_err(|error| failure::format_err!("Failed to decode a baked frame: {}", error))?;
+        let visible = RgbaImage::from_raw(w, h, pixels)
+            .ok_or_else(|| failure::format_err!("A baked frame is missing some of its pixels."))?;
+        image::imageops::replace(&mut frame, &visible, x, y);
+    }
+    Ok(DynamicImage::ImageRgba8(frame))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Instant;
+
+    use crate::assets::{ FRAMES, NUM_FRAMES };
+
+    /// Reads the PNG that the built-in frame at `index` was baked from.
+    fn png(index: usize) -> Vec<u8> {
+        let path = format!("{}/src/assets/frame{:02}.png", env!("CARGO_MANIFEST_DIR"), index + 1);
+        std::fs::read(&path).unwrap_or_else(|error| panic!("Failed to read {}: {}", path, error))
+    }
+
+    #[test]
+    fn matches_the_pngs() {
+        for (index, bytes) in FRAMES.iter().enumerate() {
+            let expected = image::load_from_memory(&png(index)).unwrap().into_rgba8();
+            let baked = decode(bytes).unwrap().into_rgba8();
+            assert_eq!(baked.dimensions(), expected.dimensions(), "frame {}", index + 1);
+
+            for (x, y, pixel) in expected.enumerate_pixels() {
+                let other = baked.get_pixel(x, y);
+                if pixel.0[3] == 0 {
+                    assert_eq!(other.0, [ 0; 4 ], "frame {} at {}, {}", index + 1, x, y);
+                } else {
+                    assert_eq!(other, pixel, "frame {} at {}, {}", index + 1, x, y);
+                }
+            }
+        }
+    }
+
+    /// Compares how long the built-in frames take to decode from PNG and
+    /// baked. Run it with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn decode_speed() {
+        let pngs = (0..NUM_FRAMES).map(png).collect::<Vec<_>>();
+
+        let start = Instant::now();
+        for bytes in pngs.iter() { image::load_from_memory(bytes).unwrap(); }
+        let png_time = start.elapsed();
+
+        let start = Instant::now();
+        for bytes in FRAMES.iter() { decode(bytes).unwrap(); }
+        let baked_time = start.elapsed();
+
+        println!(
+            "PNG: {} bytes, {:?} to decode\nBaked: {} bytes, {:?} to decode",
+            pngs.iter().map(Vec::len).sum::<usize>(),
+            png_time,
+            FRAMES.iter().map(|bytes| bytes.len()).sum::<usize>(),
+            baked_time);
+    }
+}
@@ -0,0 +1,89 @@
+//! Draws speech bubbles, for the lines companions say during events (see
+//! `calendar`).
+
+use image;
+
+use image::{ Rgba, RgbaImage };
+
+use crate::font;
+
+/// How big each pixel of the font is drawn.
+const SCALE: u32 = 2;
+
+/// The most characters there are on one line before it wraps.
+const LINE_LENGTH: usize = 20;
+
+/// The space between the text and the edge of the bubble.
+const PADDING: u32 = 6;
+
+/// How thick the outline of the bubble is.
+const BORDER: u32 = 2;
+
+/// How far the tail sticks out from the bottom of the bubble.
+const TAIL: u32 = 8;
+
+const FILL: Rgba<u8> = Rgba([ 255, 255, 255, 255 ]);
+const OUTLINE: Rgba<u8> = Rgba([ 32, 32, 32, 255 ]);
+
+/// Draws a bubble with `text` in it, with its tail pointing down from the
+/// middle of its bottom edge.
+pub fn render(text: &str) -> RgbaImage {
+    let lines = wrap(text);
+    let line_height = font::height(SCALE) + SCALE;
+    let text_width = lines.iter().map(|line| font::width(line, SCALE)).max().unwrap_or(0);
+    let text_height = lines.len() as u32 * line_height - SCALE;
+
+    let (w, h) = (text_width + 2 * (PADDING + BORDER), text_height + 2 * (PADDING + BORDER));
+    let mut image = RgbaImage::new(w, h + TAIL);
+
+    for y in 0..h {
+        for x in 0..w {
+            // Cut the corners off, so that the bubble looks rounded.
+            let corner = (x < BORDER || x >= w - BORDER) && (y < BORDER || y >= h - BORDER);
+            if corner { continue; }
+            let edge = x < BORDER || x >= w - BORDER || y < BORDER || y >= h - BORDER;
+            image.put_pixel(x, y, if edge { OUTLINE } else { FILL });
+        }
+    }
+
+    // The tail narrows down to a point, covering the outline it starts on.
+    for row in 0..TAIL + BORDER {
+        let half = TAIL - row.min(TAIL);
+        for x in (w / 2).saturating_sub(half + BORDER)..(w / 2 + half + BORDER).min(w) {
+            let edge = x < w / 2 - half || x >= w / 2 + half || row >= TAIL;
+            image.put_pixel(x, h - BORDER + row, if edge { OUTLINE } else { FILL });
+        }
+    }
+
+    for (index, line) in lines.iter().enumerate() {
+        let x = (w - font::width(line, SCALE)) / 2;
+        let y = BORDER + PADDING + index as u32 * line_height;
+        font::draw(&mut image, line, (x, y), SCALE, OUTLINE);
+    }
+
+    image
+}
+
+/// Splits `text` into lines of at most `LINE_LENGTH` characters, breaking
+/// between words where it can.
+fn wrap(text: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word = word.to_owned();
+        // Words that are too long for a line of their own get broken up.
+        while word.chars().count() > LINE_LENGTH {
+            if !line.is_empty() { lines.push(std::mem::take(&mut line)); }
+            let rest = word.chars().skip(LINE_LENGTH).collect();
+            lines.push(word.chars().take(LINE_LENGTH).collect());
+            word = rest;
+        }
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > LINE_LENGTH {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() { line.push(' '); }
+        line.push_str(&word);
+    }
+    if !line.is_empty() || lines.is_empty() { lines.push(line); }
+    lines
+}
@@ -0,0 +1,191 @@
+//! Keeps resized frames on disk, so that later launches don't have to decode
+//! and resize them again.
+//!
+//! Entries are named after a hash of everything the frames are made from,
+//! the size they were resized to and the filter that did it, so changing the
+//! pack or the settings simply misses the cache. Only the most recently
+//! written entries are kept around.
+//!
+//! Each entry is `DSFC`, the number of frames as a little endian `u32`, and
+//! then every frame as a little endian `u32` length followed by that many
+//! bytes of QOI.
+
+use image;
+use qoi;
+use sha2;
+
+use std::fs;
+use std::path::PathBuf;
+
+use image::RgbaImage;
+use image::imageops::FilterType;
+use sha2::{ Digest, Sha256 };
+
+use crate::config;
+use crate::pack::{ vector, FrameSource, Sheet };
+
+/// Bumped whenever the way frames are decoded or stored changes, so that
+/// entries written by older versions aren't used.
+const VERSION: u32 = 4;
+
+/// How many entries to keep.
+const ENTRIES: usize = 16;
+
+/// Returns the directory the cache is kept in, for example
+/// `~/.cache/desktop-sharkie/frames` on Linux.
+pub fn dir() -> PathBuf {
+    config::cache_dir().join("frames")
+}
+
+/// Returns the name of the entry for frames made from `sources` and
+/// `sheets`, each resized to fit in its size in `sizes` with `filter`.
+///
+/// It's a SHA-256 of the contents of the images and where every frame is
+/// cut out of them, rather than of their paths, so that it stays the same
+/// between builds and changes whenever a file does. Frames that are drawn
+/// from SVGs are told apart from ones that are decoded, since the same bytes
+/// come out differently.
+pub fn key(
+    sources: &[FrameSource],
+    sheets: &[Sheet],
+    sizes: &[(u32, u32)],
+    filter: FilterType,
+) -> String {
+    let mut hasher = Sha256::new();
+
+    number(&mut hasher, VERSION as u64);
+    number(&mut hasher, filter as u64);
+    number(&mut hasher, sheets.len() as u64);
+    for sheet in sheets {
+        bytes(&mut hasher, &sheet.bytes);
+        match sheet.key {
+            Some(color) => bytes(&mut hasher, &color),
+            None => number(&mut hasher, u64::MAX),
+        }
+    }
+    number(&mut hasher, sources.len() as u64);
+    for (source, bounds) in sources.iter().zip(sizes) {
+        number(&mut hasher, bounds.0 as u64);
+        number(&mut hasher, bounds.1 as u64);
+        match source {
+            FrameSource::Encoded { file, bytes: data } => {
+                number(&mut hasher, 0);
+                number(&mut hasher, vector::is_svg(file) as u64);
+                bytes(&mut hasher, data);
+            },
+            FrameSource::Baked(data) => {
+                number(&mut hasher, 1);
+                bytes(&mut hasher, data);
+            },
+            FrameSource::Tile { sheet, rect } => {
+                number(&mut hasher, 2);
+                for value in [ *sheet as u32, rect.0, rect.1, rect.2, rect.3 ].iter() {
+                    number(&mut hasher, *value as u64);
+                }
+            },
+            FrameSource::Sprite { sheet, rect, rotated, offset, size } => {
+                number(&mut hasher, 3);
+                let values = [
+                    *sheet as u32, rect.0, rect.1, rect.2, rect.3, *rotated as u32, offset.0, offset.1, size.0, size.1,
+                ];
+                for value in values.iter() {
+                    number(&mut hasher, *value as u64);
+                }
+            },
+            FrameSource::Animated { sheet, index } => {
+                number(&mut hasher, 4);
+                number(&mut hasher, *sheet as u64);
+                number(&mut hasher, *index as u64);
+            },
+        }
+    }
+
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Reads every frame in the entry called `key`, if there is one and it holds
+/// `count` frames.
+pub fn load(key: &str, count: usize) -> Option<Vec<RgbaImage>> {
+    let bytes = fs::read(dir().join(key)).ok()?;
+    if bytes.get(..4)? != b"DSFC" || read_u32(&bytes, 4)? as usize != count {
+        return None;
+    }
+
+    let mut frames = Vec::with_capacity(count);
+    let mut offset = 8;
+    for _ in 0..count {
+        let len = read_u32(&bytes, offset)? as usize;
+        let data = bytes.get(offset + 4..offset + 4 + len)?;
+        let (header, pixels) = qoi::decode_to_vec(data).ok()?;
+        if header.channels != qoi::Channels::Rgba { return None; }
+        frames.push(RgbaImage::from_raw(header.width, header.height, pixels)?);
+        offset += 4 + len;
+    }
+    Some(frames)
+}
+
+/// Encodes `frame` the way entries hold it, for `store`. Failing to isn't
+/// worth stopping for, so errors are only logged.
+pub fn encode(frame: &RgbaImage) -> Option<Vec<u8>> {
+    qoi::encode_to_vec(frame.as_raw(), frame.width(), frame.height())
+        .map_err(|error| eprintln!("Failed to cache frames: {}", error))
+        .ok()
+}
+
+/// Writes the frames in `entries`, each one as `encode` gave it, to the
+/// entry called `key`, and drops the oldest entries. Failing to write the
+/// cache isn't worth stopping for, so errors are only logged.
+pub fn store(key: &str, entries: &[Vec<u8>]) {
+    let mut bytes = b"DSFC".to_vec();
+    bytes.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    for data in entries {
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+    }
+
+    let dir = dir();
+    // Write to a temporary file first, so that another instance never reads
+    // half of an entry.
+    let temporary = dir.join(format!("{}.tmp", key));
+    let result = fs::create_dir_all(&dir)
+        .and_then(|_| fs::write(&temporary, &bytes))
+        .and_then(|_| fs::rename(&temporary, dir.join(key)));
+    if let Err(error) = result {
+        eprintln!("Failed to cache frames in {}: {}", dir.display(), error);
+        return;
+    }
+
+    prune();
+}
+
+/// Removes all but the `ENTRIES` most recently written entries.
+fn prune() {
+    let mut entries = match fs::read_dir(dir()) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+            .collect::<Vec<_>>(),
+        Err(..) => return,
+    };
+    entries.sort();
+    entries.reverse();
+    for (_, path) in entries.into_iter().skip(ENTRIES) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([ bytes[0], bytes[1], bytes[2], bytes[3] ]))
+}
+
+fn number(hasher: &mut Sha256, value: u64) {
+    hasher.update(value.to_le_bytes());
+}
+
+/// Hashes `bytes` along with how many there are, so that where one run of
+/// bytes ends and the next begins is part of the hash.
+fn bytes(hasher: &mut Sha256, bytes: &[u8]) {
+    number(hasher, bytes.len() as u64);
+    hasher.update(bytes);
+}
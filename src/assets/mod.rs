@@ -1,141 +1,796 @@
-use crossbeam_channel;
 use glium;
 use image;
 use rayon;
 
+pub mod baked;
+pub mod bubble;
+pub mod cache;
+pub mod shader;
+
+use std::cell::RefCell;
+use std::collections::{ HashMap, HashSet };
+use std::path::PathBuf;
 use std::rc::Rc;
-use std::sync::Arc;
+use std::sync::Mutex;
 use std::sync::atomic::{ AtomicBool, Ordering };
 
-use crossbeam_channel::{ self as channel, Receiver };
-use glium::Display;
-use glium::glutin::dpi::PhysicalSize;
-use glium::texture::{  SrgbTexture2d, RawImage2d };
-use image::imageops::FilterType;
-use image::{ Rgba, DynamicImage, ImageBuffer };
-use rayon::iter::IntoParallelRefIterator;
+use crossbeam_channel::{ self as channel, Receiver, Sender };
+use glium::{ Display, Program };
+use glium::texture::{ MipmapsOption, SrgbTexture2d, RawImage2d };
+use glium::uniforms::{ MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior };
+use image::imageops::{ self, FilterType };
+use image::{ DynamicImage, ImageBuffer, Rgba, RgbaImage };
+use rayon::iter::{ IntoParallelRefIterator, ParallelBridge };
 use rayon::prelude::*;
+use serde::{ Deserialize, Serialize };
+
+use crate::effects::Color;
+use crate::misc::warn;
+use crate::pack::{ AssetError, FrameSource, Pack, Sheet };
 
-pub const SIZE_FRAMES: usize = 680;
 pub const NUM_FRAMES: usize = 22;
 
+/// The built-in frames, baked by `build.rs` (see `baked`).
 pub static FRAMES: [&'static [u8]; NUM_FRAMES] = [
-    include_bytes!("frame01.png"),
-    include_bytes!("frame02.png"),
-    include_bytes!("frame03.png"),
-    include_bytes!("frame04.png"),
-    include_bytes!("frame05.png"),
-    include_bytes!("frame06.png"),
-    include_bytes!("frame07.png"),
-    include_bytes!("frame08.png"),
-    include_bytes!("frame09.png"),
-    include_bytes!("frame10.png"),
-    include_bytes!("frame11.png"),
-    include_bytes!("frame12.png"),
-    include_bytes!("frame13.png"),
-    include_bytes!("frame14.png"),
-    include_bytes!("frame15.png"),
-    include_bytes!("frame16.png"),
-    include_bytes!("frame17.png"),
-    include_bytes!("frame18.png"),
-    include_bytes!("frame19.png"),
-    include_bytes!("frame20.png"),
-    include_bytes!("frame21.png"),
-    include_bytes!("frame22.png"),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame01.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame02.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame03.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame04.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame05.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame06.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame07.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame08.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame09.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame10.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame11.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame12.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame13.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame14.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame15.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame16.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame17.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame18.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame19.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame20.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame21.baked")),
+    include_bytes!(concat!(env!("OUT_DIR"), "/frame22.baked")),
 ];
 
+/// The frames of a pack at one size, which are decoded, resized and sent
+/// over from another thread one by one, and uploaded to the GPU as they're
+/// needed. The pack's layers come after its frames, so layer `i` is
+/// `Pack::frames.len() + i`. They have premultiplied alpha (see `resize`).
 #[derive(Debug)]
 pub struct Frames {
-    completed: Vec<Rc< SrgbTexture2d>>,
-    current: usize,
     display: Display,
-    full: bool,
-    receiver: Option<Receiver<Vec<u8>>>,
-    size: PhysicalSize<u32>,
+    /// The size the frames are resized to.
+    size: (u32, u32),
+    /// How many of the slots are the pack's frames, rather than its layers.
+    frame_count: usize,
+    /// The filter they're resized with.
+    filter: Filter,
+    /// Whether they're uploaded with mipmaps. Frames are resized to the size
+    /// they're drawn at, so they only need them if the pack's motion draws
+    /// them smaller than that, and pixel art never does.
+    mipmaps: bool,
+    receiver: Receiver<(usize, Result<RgbaImage, AssetError>)>,
+    slots: Vec<Slot>,
+    /// Frames that are being loaded again after the pack was reloaded, and
+    /// haven't arrived yet.
+    pending: HashSet<usize>,
+    /// Whether any reloaded frames have arrived since `reloaded` was last
+    /// called.
+    updated: bool,
+    /// The first frame that failed to load, if any did. Layers, and frames
+    /// that fail while they're being reloaded, only warn, and don't count.
+    failure: Option<AssetError>,
+}
+
+/// Sends frames that finish in any order on in the order they're in,
+/// holding on to the ones that are done before the ones in front of them.
+struct InOrder {
+    sender: Sender<(usize, Result<RgbaImage, AssetError>)>,
+    /// Sent to once the first frame has been sent.
+    ready: Sender<()>,
+    /// The index of the next frame to send.
+    next: usize,
+    waiting: HashMap<usize, Result<RgbaImage, AssetError>>,
+}
+
+#[derive(Debug)]
+enum Slot {
+    Loading,
+    Loaded(Rc<SrgbTexture2d>),
+    Failed(AssetError),
 }
 
 impl Frames {
-    pub fn new(display: &Display, ready: Arc<AtomicBool>) -> Self {
-        let (width, height, size) = {
-            let size = display.gl_window().window().inner_size();
-            (size.width as i32, size.height as i32, size)
-        };
+    /// Starts loading the frames of `pack` at `size`, resized with
+    /// `filter`. `ready` is sent to once, as soon as the first frame is done,
+    /// so that it can be shown without waiting for the rest.
+    pub fn new(
+        display: &Display,
+        pack: &Pack,
+        size: (i32, i32),
+        filter: Filter,
+        ready: Sender<()>,
+    ) -> Self {
+        let (width, height) = (size.0.max(1) as u32, size.1.max(1) as u32);
 
         let (sender, receiver) =
-            channel::unbounded::<Vec<u8>>();
+            channel::unbounded::<(usize, Result<RgbaImage, AssetError>)>();
+
+        let (sources, sizes): (Vec<FrameSource>, Vec<(u32, u32)>) = images(pack, (width, height)).into_iter().unzip();
+        let sheets = pack.sheets.clone();
+        let count = sources.len();
 
         rayon::spawn(move || {
-            FRAMES
-                .par_iter()
-                .map(|bytes: &'_ &'_ [u8]| image::load_from_memory(bytes))
-                .filter(|result| result.is_ok())
-                .map(|result| result.unwrap())
-                .map(|image: DynamicImage| {
-                    image.resize(
-                        width as u32,
-                        height as u32,
-                        FilterType::Triangle)
-                })
-                .map(|image: DynamicImage| image.into_rgba8())
-                .map(|rgba: ImageBuffer<Rgba<u8>, Vec<u8>>| rgba.clone().into_raw())
-                .collect::<Vec<Vec<u8>>>()
+            let key = cache::key(&sources, &sheets, &sizes, filter.filter_type());
+            if let Some(frames) = cache::load(&key, sources.len()) {
+                for (index, frame) in frames.into_iter().enumerate() {
+                    let _ = sender.send((index, Ok(frame)));
+                    if index == 0 { let _ = ready.send(()); }
+                }
+                return;
+            }
+
+            // Sprite sheets and animated images are decoded first, so that
+            // frames can be cut out of them before they get resized.
+            let decoded = decode_sheets(&sheets);
+
+            // Bridging hands the frames out to the threads in order, but
+            // they can still finish out of order, so each one waits for the
+            // ones before it to be sent first. Frames are only encoded for
+            // the cache until one fails, since only complete sets are
+            // cached, so that frames that failed get another try next time.
+            let in_order = Mutex::new(InOrder::new(sender, ready));
+            let caching = AtomicBool::new(true);
+            let mut entries = sources
                 .iter()
-                .cloned()
-                .for_each(|raw: Vec<u8>| { let _ = sender.send(raw); });
-            ready.store(true, Ordering::Relaxed);
+                .enumerate()
+                .par_bridge()
+                .map(|(index, source): (usize, &'_ FrameSource)| {
+                    let frame = source
+                        .decode(&sheets, &decoded, sizes[index])
+                        .map(|image| resize(image, sizes[index], filter));
+                    let entry = match frame {
+                        Ok(ref frame) if caching.load(Ordering::Relaxed) => cache::encode(frame),
+                        _ => None,
+                    };
+                    if entry.is_none() { caching.store(false, Ordering::Relaxed); }
+                    in_order.lock().unwrap().send(index, frame);
+                    (index, entry)
+                })
+                .collect::<Vec<(usize, Option<Vec<u8>>)>>();
+
+            if caching.load(Ordering::Relaxed) {
+                entries.sort_by_key(|(index, _)| *index);
+                let entries = entries
+                    .into_iter()
+                    .map(|(_, entry)| entry)
+                    .collect::<Option<Vec<Vec<u8>>>>();
+                if let Some(entries) = entries { cache::store(&key, &entries); }
+            }
         });
 
         Frames {
-            completed: Vec::new(),
-            current: 0,
             display: display.clone(),
-            full: false,
-            size,
-            receiver: Some(receiver),
+            size: (width, height),
+            frame_count: pack.frames.len(),
+            filter,
+            mipmaps: filter != Filter::Nearest && pack.motion.shrinks(),
+            receiver,
+            slots: (0..count).map(|_| Slot::Loading).collect(),
+            pending: HashSet::new(),
+            updated: false,
+            failure: None,
+        }
+    }
+
+    /// Starts loading the frames that are different in `new` than they were
+    /// in `old`, after a pack has been reloaded. Until they arrive, the old
+    /// frames keep being shown, and if they fail, they stay that way.
+    pub fn reload(&mut self, old: &Pack, new: &Pack) {
+        let (old_images, new_images) = (images(old, self.size), images(new, self.size));
+        let changed = |index: usize| {
+            let image = &new_images[index];
+            old_images.get(index) != Some(image)
+                || image.0.sheet().is_some_and(|sheet| old.sheets.get(sheet) != new.sheets.get(sheet))
+        };
+
+        self.slots.resize_with(new_images.len(), || Slot::Loading);
+        self.frame_count = new.frames.len();
+        self.mipmaps = self.filter != Filter::Nearest && new.motion.shrinks();
+
+        // Whatever was still on its way from before gets loaded again from
+        // the new pack, since the old loads are about to be cut off, and
+        // whatever failed gets another try.
+        let indices = (0..new_images.len())
+            .filter(|index| {
+                changed(*index)
+                    || self.pending.contains(index)
+                    || !matches!(self.slots[*index], Slot::Loaded(..))
+            })
+            .collect::<Vec<usize>>();
+        self.failure = None;
+
+        let (sender, receiver) =
+            channel::unbounded::<(usize, Result<RgbaImage, AssetError>)>();
+        self.receiver = receiver;
+        self.pending = indices.iter().copied().collect();
+
+        let sheets = new.sheets.clone();
+        let filter = self.filter;
+
+        rayon::spawn(move || {
+            // Only decode the sheets that the changed frames come from.
+            let needed = indices
+                .iter()
+                .filter_map(|index| new_images[*index].0.sheet())
+                .collect::<HashSet<usize>>();
+            let decoded = sheets
+                .par_iter()
+                .enumerate()
+                .map(|(index, sheet)| if needed.contains(&index) { sheet.decode() } else { Ok(Vec::new()) })
+                .collect::<Vec<_>>();
+
+            indices.par_iter().for_each(|index| {
+                let (source, size) = &new_images[*index];
+                let frame = source
+                    .decode(&sheets, &decoded, *size)
+                    .map(|image| resize(image, *size, filter));
+                let _ = sender.send((*index, frame));
+            });
+        });
+    }
+
+    /// Returns the frame at `index`, without waiting for it. Until a frame
+    /// has loaded, the closest one before it that has is returned instead,
+    /// so that the companion keeps moving while the rest arrive. Layers
+    /// aren't stood in for, since another image would look wrong, so they
+    /// give `AssetError::Unfinished` until they're there.
+    pub fn get(&mut self, index: usize) -> Result<Rc<SrgbTexture2d>, AssetError> {
+        self.receive();
+        match self.slots.get(index) {
+            Some(Slot::Loaded(texture)) => Ok(texture.clone()),
+            Some(Slot::Failed(error)) => Err(error.clone()),
+            Some(Slot::Loading) if index < self.frame_count => self.slots[..index]
+                .iter()
+                .rev()
+                .find_map(|slot| match slot {
+                    Slot::Loaded(texture) => Some(texture.clone()),
+                    _ => None,
+                })
+                .ok_or(AssetError::Unfinished { frame: index }),
+            Some(Slot::Loading) | None => Err(AssetError::Unfinished { frame: index }),
         }
     }
+
+    /// Uploads any frames that have arrived since last time, without
+    /// waiting for the rest. Returns the first frame that failed to load, if
+    /// any did.
+    pub fn poll(&mut self) -> Option<&AssetError> {
+        self.receive();
+        self.failure.as_ref()
+    }
+
+    /// Uploads the frames that have arrived, in whatever order they finished
+    /// in.
+    fn receive(&mut self) {
+        while let Ok(arrival) = self.receiver.try_recv() {
+            self.store(arrival);
+        }
+    }
+
+    /// Returns whether any frames that were being loaded again have arrived
+    /// since last time, and need to be drawn.
+    pub fn reloaded(&mut self) -> bool {
+        std::mem::replace(&mut self.updated, false)
+    }
+
+    fn store(&mut self, (index, frame): (usize, Result<RgbaImage, AssetError>)) {
+        let reloaded = self.pending.remove(&index);
+        self.updated |= reloaded;
+        let slot = match frame.and_then(|frame| self.upload(index, frame)) {
+            Ok(texture) => Slot::Loaded(Rc::new(texture)),
+            // A frame that breaks while it's being edited keeps showing how
+            // it was before, rather than sending the companion back to the
+            // built-in pack.
+            Err(error) if reloaded => {
+                warn(format!("Frame {} couldn't be reloaded, so it was left as it was.", index), error.to_string());
+                return;
+            },
+            // Without an accessory or particle the companion still looks
+            // like itself, so it's only left out.
+            Err(error) if index >= self.frame_count => {
+                warn(format!("Layer {} couldn't be loaded, so it isn't shown.", index - self.frame_count), error.to_string());
+                Slot::Failed(error)
+            },
+            Err(error) => {
+                if self.failure.is_none() { self.failure = Some(error.clone()); }
+                Slot::Failed(error)
+            },
+        };
+        self.slots[index] = slot;
+    }
+
+    fn upload(&self, index: usize, frame: RgbaImage) -> Result<SrgbTexture2d, AssetError> {
+        let dimensions = frame.dimensions();
+        let image = RawImage2d::from_raw_rgba_reversed(&frame.into_raw(), dimensions);
+        let mipmaps = if self.mipmaps {
+            MipmapsOption::AutoGeneratedMipmaps
+        } else {
+            MipmapsOption::NoMipmap
+        };
+        SrgbTexture2d::with_mipmaps(&self.display, image, mipmaps)
+            .map_err(|error| AssetError::Upload { frame: index, reason: error.to_string() })
+    }
 }
 
-impl Iterator for Frames {
-    type Item = Rc< SrgbTexture2d>;
+/// Returns everything `Frames` loads for `pack` when its frames are resized
+/// to `size`, along with the size each of them is resized to: the frames,
+/// and then the layers, which are resized by as much as the frames are.
+fn images(pack: &Pack, size: (u32, u32)) -> Vec<(FrameSource, (u32, u32))> {
+    let scale = (
+        size.0 as f32 / pack.frame_size.0.max(1) as f32,
+        size.1 as f32 / pack.frame_size.1.max(1) as f32,
+    );
+    let frames = pack.frames.iter().map(|source| (source.clone(), size));
+    let layers = pack.layers.iter().map(|layer| {
+        let width = (layer.size.0 as f32 * scale.0).round().max(1.0) as u32;
+        let height = (layer.size.1 as f32 * scale.1).round().max(1.0) as u32;
+        (layer.source.clone(), (width, height))
+    });
+    frames.chain(layers).collect()
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if !self.full {
-            let current = self.current;
-            let value = match self.receiver {
-                Some(ref receiver) => match receiver.recv() {
-                    Ok(value) => {
-                        let image = RawImage2d::from_raw_rgba_reversed(
-                            &value,
-                            (self.size.width, self.size.height));
-                        let texture =  SrgbTexture2d::new(
-                            &self.display,
-                            image);
-                        match texture {
-                            Ok(texture) => texture,
-                            Err(..) => return None,
-                        }
-                    },
-                    Err(..) => return None,
+/// How frames are resized to the size a companion is drawn at. Packs can
+/// pick one (see `Pack::filter`), and companions can override it (see
+/// `CompanionConfig::filter`).
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Filter {
+    /// Keeps every pixel a sharp square, for pixel art. Companions drawn
+    /// with it are snapped to a whole multiple of their frames' size (see
+    /// `Filter::snap`), so that every pixel comes out the same size.
+    Nearest,
+    /// Blends neighbouring pixels together, which is quick but soft. This is
+    /// the default.
+    #[default]
+    Triangle,
+    /// Sharper than `Triangle`, for painted art.
+    CatmullRom,
+    /// The sharpest one, for painted art, but also the slowest.
+    Lanczos,
+}
+
+impl Filter {
+    fn filter_type(self) -> FilterType {
+        match self {
+            Filter::Nearest => FilterType::Nearest,
+            Filter::Triangle => FilterType::Triangle,
+            Filter::CatmullRom => FilterType::CatmullRom,
+            Filter::Lanczos => FilterType::Lanczos3,
+        }
+    }
+
+    /// Returns the size a companion with frames of `frame_size` is drawn at,
+    /// when it would be drawn at `size`. `Nearest` snaps it to the closest
+    /// whole multiple of the frames' size, or whole fraction when they're
+    /// shrunk, and the rest leave it as it is.
+    pub fn snap(self, frame_size: (u32, u32), size: (i32, i32)) -> (i32, i32) {
+        if self != Filter::Nearest { return size; }
+        let scale = size.1 as f32 / frame_size.1.max(1) as f32;
+        let scale = if scale >= 1.0 { scale.round() } else { 1.0 / (1.0 / scale).round() };
+        (
+            (frame_size.0 as f32 * scale).round().max(1.0) as i32,
+            (frame_size.1 as f32 * scale).round().max(1.0) as i32,
+        )
+    }
+
+    /// Returns how `texture`, a frame resized with this filter, is sampled.
+    /// Frames are drawn at the size they were resized to, where this makes
+    /// no difference, but frames that are drawn smaller than that are
+    /// sampled from their mipmaps, if they have any, and pixel art stays
+    /// sharp however it's drawn.
+    pub fn sampler(self, texture: &SrgbTexture2d) -> SamplerBehavior {
+        let (minify_filter, magnify_filter) = match self {
+            Filter::Nearest => (MinifySamplerFilter::Nearest, MagnifySamplerFilter::Nearest),
+            _ if texture.get_mipmap_levels() > 1 => (MinifySamplerFilter::LinearMipmapLinear, MagnifySamplerFilter::Linear),
+            _ => (MinifySamplerFilter::Linear, MagnifySamplerFilter::Linear),
+        };
+        SamplerBehavior { minify_filter, magnify_filter, ..Default::default() }
+    }
+}
+
+/// Resizes a decoded frame to fit in `size`, keeping its aspect ratio, the
+/// way every frame is before it's uploaded.
+///
+/// The frame that comes out has premultiplied alpha, which is what the
+/// shaders and the blending on screen expect. Resizing straight alpha
+/// averages the colour of transparent pixels into the edges, which leaves a
+/// dark or light fringe around the character, so the colours are
+/// premultiplied in linear light first, resized, and then stored as sRGB
+/// again. The GPU undoes the sRGB before it filters or blends anything, so
+/// it ends up with the premultiplied linear colours.
+///
+/// Frames that are already the right size, like SVGs (see `pack::vector`),
+/// are only premultiplied, so that they stay as sharp as they were drawn.
+pub fn resize(image: DynamicImage, size: (u32, u32), filter: Filter) -> RgbaImage {
+    let image = image.into_rgba8();
+    let linear = (0..=255u8).map(to_linear).collect::<Vec<f32>>();
+    let premultiplied = ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        let [ r, g, b, a ] = image.get_pixel(x, y).0;
+        let alpha = a as f32 / 255.0;
+        Rgba([ linear[r as usize] * alpha, linear[g as usize] * alpha, linear[b as usize] * alpha, alpha ])
+    });
+
+    let (width, height) = fit(image.dimensions(), size);
+    let resized: ImageBuffer<Rgba<f32>, Vec<f32>> = if (width, height) == image.dimensions() {
+        premultiplied
+    } else {
+        imageops::resize(&premultiplied, width, height, filter.filter_type())
+    };
+
+    RgbaImage::from_fn(width, height, |x, y| {
+        let [ r, g, b, a ] = resized.get_pixel(x, y).0;
+        // Filters that overshoot can leave colours brighter than their alpha
+        // allows, which would blend as if they were glowing. Where alpha
+        // itself overshoots, the colour is brought down along with it, so
+        // that the colour doesn't get lighter where it's cut off.
+        let scale = if a > 1.0 { 1.0 / a } else { 1.0 };
+        let alpha = (a.clamp(0.0, 1.0) * 255.0).round() / 255.0;
+        let channel = |value: f32| to_srgb((value * scale).clamp(0.0, alpha));
+        Rgba([ channel(r), channel(g), channel(b), (alpha * 255.0) as u8 ])
+    })
+}
+
+/// Turns a frame from `resize` back into straight alpha, for saving it to a
+/// file.
+pub fn unpremultiply(frame: &RgbaImage) -> RgbaImage {
+    RgbaImage::from_fn(frame.width(), frame.height(), |x, y| {
+        let [ r, g, b, a ] = frame.get_pixel(x, y).0;
+        if a == 0 { return Rgba([ 0, 0, 0, 0 ]); }
+        let alpha = a as f32 / 255.0;
+        let channel = |value: u8| to_srgb(to_linear(value) / alpha);
+        Rgba([ channel(r), channel(g), channel(b), a ])
+    })
+}
+
+/// Returns the size an image of `size` is resized to so that it fits in
+/// `bounds`, keeping its aspect ratio. This rounds the same way as
+/// `DynamicImage::resize`.
+pub fn fit(size: (u32, u32), bounds: (u32, u32)) -> (u32, u32) {
+    let (width, height) = (size.0.max(1) as u64, size.1.max(1) as u64);
+    let (max_width, max_height) = (bounds.0 as u64, bounds.1 as u64);
+    if max_width * height <= width * max_height {
+        (bounds.0, (height * max_width / width).max(1) as u32)
+    } else {
+        ((width * max_height / height).max(1) as u32, bounds.1)
+    }
+}
+
+/// Turns an sRGB channel into linear light.
+pub fn to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
+
+/// Turns a channel in linear light into sRGB.
+pub fn to_srgb(value: f32) -> u8 {
+    let value = if value <= 0.003_130_8 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 };
+    (value * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+/// Decodes every one of `sheets` in parallel, in order. Frames cut out of a
+/// sheet that failed to decode fail with its error.
+pub fn decode_sheets(sheets: &[Sheet]) -> Vec<Result<Vec<DynamicImage>, AssetError>> {
+    sheets
+        .par_iter()
+        .map(|sheet: &'_ Sheet| sheet.decode())
+        .collect()
+}
+
+impl InOrder {
+    fn new(sender: Sender<(usize, Result<RgbaImage, AssetError>)>, ready: Sender<()>) -> Self {
+        InOrder { sender, ready, next: 0, waiting: HashMap::new() }
+    }
+
+    /// Sends the frame at `index`, and any that were waiting on it, or
+    /// holds on to it until the frames before it have been sent.
+    fn send(&mut self, index: usize, frame: Result<RgbaImage, AssetError>) {
+        self.waiting.insert(index, frame);
+        while let Some(frame) = self.waiting.remove(&self.next) {
+            let _ = self.sender.send((self.next, frame));
+            if self.next == 0 { let _ = self.ready.send(()); }
+            self.next += 1;
+        }
+    }
+}
+
+/// The name of a pack, the size its frames are resized to and the filter
+/// they're resized with, which `FrameCache` keeps frames by.
+type FramesKey = (String, (i32, i32), Filter);
+
+/// Keeps track of the packs and frames that have been loaded, so that
+/// companions that look the same only load, decode and upload them once.
+#[derive(Debug)]
+pub struct FrameCache {
+    display: Display,
+    frames: HashMap<FramesKey, Rc<RefCell<Frames>>>,
+    packs: HashMap<String, Rc<Pack>>,
+    /// The names of the packs that failed to load, so that they're only
+    /// warned about once. They're tried again when their files change, or
+    /// when `retry` is called.
+    failed: HashSet<String>,
+    /// The compiled shaders of the packs that have one, or `None` for the
+    /// ones that failed to compile.
+    shaders: HashMap<String, Option<Rc<Program>>>,
+    ready: Sender<()>,
+}
+
+impl FrameCache {
+    /// `ready` is sent to whenever the first frame of a set of frames is
+    /// done loading.
+    pub fn new(display: &Display, ready: Sender<()>) -> Self {
+        FrameCache {
+            display: display.clone(),
+            frames: HashMap::new(),
+            packs: HashMap::new(),
+            failed: HashSet::new(),
+            shaders: HashMap::new(),
+            ready,
+        }
+    }
+
+    /// Returns the pack called `name`, loading it if no other companion is
+    /// using it yet. Packs that fail to load are replaced by the built-in
+    /// one, after warning the user about it the first time.
+    pub fn pack(&mut self, name: &str) -> Rc<Pack> {
+        if let Some(pack) = self.packs.get(name) {
+            return pack.clone();
+        }
+        if self.failed.contains(name) {
+            return self.pack(Pack::BUILTIN);
+        }
+        let pack = match Pack::find(name) {
+            Ok(pack) => Rc::new(pack),
+            Err(error) => {
+                warn_fallback(name, &error);
+                self.failed.insert(name.to_owned());
+                return self.pack(Pack::BUILTIN);
+            },
+        };
+        for warning in pack.warnings.iter() {
+            eprintln!("{}: {}", pack.name, warning);
+        }
+        self.packs.insert(name.to_owned(), pack.clone());
+        pack
+    }
+
+    /// Returns the frames of `pack` at `size`, resized with `filter`,
+    /// loading them if no other companion is using them yet.
+    pub fn frames(&mut self, pack: &Pack, size: (i32, i32), filter: Filter) -> Rc<RefCell<Frames>> {
+        let key = (pack.name.clone(), size, filter);
+        if let Some(frames) = self.frames.get(&key) {
+            return frames.clone();
+        }
+        let frames = Rc::new(RefCell::new(Frames::new(
+            &self.display,
+            pack,
+            size,
+            filter,
+            self.ready.clone())));
+        self.frames.insert(key, frames.clone());
+        frames
+    }
+
+    /// Returns the compiled shader of `pack`, compiling it if no other
+    /// companion is using it yet. Returns `None` if the pack doesn't have
+    /// one, or it failed to compile, which is logged once and leaves the
+    /// pack to be drawn with the built-in shader.
+    pub fn shader(&mut self, pack: &Pack) -> Option<Rc<Program>> {
+        let source = pack.shader.as_ref()?;
+        if let Some(program) = self.shaders.get(&pack.name) {
+            return program.clone();
+        }
+        let program = match shader::compile(&self.display, source) {
+            Ok(program) => Some(Rc::new(program)),
+            Err(error) => {
+                warn(
+                    format!("The \"{}\" pack's shader couldn't be compiled, so the built-in one is used instead.", pack.name),
+                    format!("{}: {}", source.file.display(), error));
+                None
+            },
+        };
+        self.shaders.insert(pack.name.clone(), program.clone());
+        program
+    }
+
+    /// Draws a speech bubble saying `text`, and uploads it. Speech bubbles
+    /// aren't worth stopping for, so failing to upload one is only logged.
+    ///
+    /// Every pixel of a bubble is either opaque or fully transparent black,
+    /// so it's already premultiplied like the frames are.
+    pub fn bubble(&self, text: &str) -> Option<Rc<SrgbTexture2d>> {
+        let bubble = bubble::render(text);
+        let dimensions = bubble.dimensions();
+        let image = RawImage2d::from_raw_rgba_reversed(&bubble.into_raw(), dimensions);
+        match SrgbTexture2d::new(&self.display, image) {
+            Ok(texture) => Some(Rc::new(texture)),
+            Err(error) => {
+                eprintln!("Failed to upload a speech bubble to the GPU: {}", error);
+                None
+            },
+        }
+    }
+
+    /// Uploads the colours of a palette swap (see `Effects::palette`), as a
+    /// row of the colours to replace over a row of what to replace them
+    /// with. Returns `None` if there aren't any, or they couldn't be
+    /// uploaded, which is only logged.
+    pub fn palette(&self, palette: &[(Color, Color)]) -> Option<Rc<SrgbTexture2d>> {
+        if palette.is_empty() { return None; }
+        let mut pixels = palette.iter().flat_map(|(from, _)| from.0.to_vec()).collect::<Vec<u8>>();
+        pixels.extend(palette.iter().flat_map(|(_, to)| to.0.to_vec()));
+        let image = RawImage2d::from_raw_rgba_reversed(&pixels, (palette.len() as u32, 2));
+        match SrgbTexture2d::new(&self.display, image) {
+            Ok(texture) => Some(Rc::new(texture)),
+            Err(error) => {
+                eprintln!("Failed to upload a palette to the GPU: {}", error);
+                None
+            },
+        }
+    }
+
+    /// Loads the packs in use that any of `files` belong to again, and
+    /// starts loading the frames of theirs that changed. Packs that fail to
+    /// load keep their old version, after a warning. Returns the packs that
+    /// were reloaded.
+    pub fn reload(&mut self, files: &[PathBuf]) -> Vec<Rc<Pack>> {
+        let mut reloaded = Vec::new();
+        self.failed.retain(|name| !changed(name, files));
+
+        for (name, old) in self.packs.iter_mut() {
+            if name == Pack::BUILTIN || !changed(name, files) { continue; }
+            let pack = match Pack::find(name) {
+                Ok(pack) => Rc::new(pack),
+                Err(error) => {
+                    warn(
+                        format!("The \"{}\" pack couldn't be reloaded, so the old version is still shown.", name),
+                        format!("{}", error));
+                    continue;
                 },
-                None => return None,
             };
-            self.completed.push(Rc::new(value));
-            self.current += 1;
-            if self.current >= NUM_FRAMES {
-                self.current = 0;
-                self.full = true;
-                self.receiver = None;
+            for warning in pack.warnings.iter() {
+                eprintln!("{}: {}", pack.name, warning);
             }
-            match self.completed.get(current) {
-                Some(completed) => Some(completed.clone()),
-                None => None,
+            for ((frames_name, _, _), frames) in self.frames.iter() {
+                if frames_name == name { frames.borrow_mut().reload(old, &pack); }
+            }
+            // The shader is compiled again the next time it's asked for,
+            // whether or not it changed.
+            self.shaders.remove(name);
+            *old = pack.clone();
+            reloaded.push(pack);
+        }
+
+        reloaded
+    }
+
+    /// Forgets which packs failed to load, so that they're tried again the
+    /// next time they're asked for.
+    pub fn retry(&mut self) {
+        self.failed.clear();
+    }
+
+    /// Drops any packs and frames that no companion is using anymore.
+    pub fn prune(&mut self) {
+        self.frames.retain(|_, frames| Rc::strong_count(frames) > 1);
+        self.packs.retain(|_, pack| Rc::strong_count(pack) > 1);
+        let packs = &self.packs;
+        self.shaders.retain(|name, _| packs.contains_key(name));
+    }
+}
+
+/// Returns whether any of `files` belong to the installed pack called
+/// `name`.
+pub fn changed(name: &str, files: &[PathBuf]) -> bool {
+    match Pack::path(name) {
+        Some(path) => files.iter().any(|file| file.starts_with(&path)),
+        None => false,
+    }
+}
+
+/// Lets the user know that the pack called `name` couldn't be used because
+/// of `error`, and that the built-in one is shown instead.
+pub fn warn_fallback(name: &str, error: &AssetError) {
+    warn(
+        format!("The \"{}\" pack couldn't be loaded, so {} is shown instead.", name, Pack::BUILTIN),
+        format!("{}", error));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [Filter; 4] = [ Filter::Nearest, Filter::Triangle, Filter::CatmullRom, Filter::Lanczos ];
+    const COLOR: [u8; 3] = [ 200, 80, 40 ];
+
+    /// Returns a frame that's `COLOR` on its left half, and transparent with
+    /// the colour `behind` on its right half, so that a fringe of `behind`
+    /// shows up if it bleeds into the edge.
+    fn hard_edge(behind: [u8; 3]) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, _| {
+            let ([ r, g, b ], a) = if x < 32 { (COLOR, 255) } else { (behind, 0) };
+            Rgba([ r, g, b, a ])
+        }))
+    }
+
+    #[test]
+    fn resizing_leaves_no_fringe() {
+        for &behind in [ [ 0, 0, 0 ], [ 255, 255, 255 ] ].iter() {
+            for &filter in FILTERS.iter() {
+                for &size in [ 7, 16, 23, 48, 100, 150 ].iter() {
+                    let frame = resize(hard_edge(behind), (size, size), filter);
+                    for (pixel, straight) in frame.pixels().zip(unpremultiply(&frame).pixels()) {
+                        let alpha = pixel.0[3] as f32 / 255.0;
+                        for (channel, &color) in COLOR.iter().enumerate() {
+                            // Blended over something else, the edge has to
+                            // look like the colour faded out, and nothing
+                            // else.
+                            let expected = to_srgb(to_linear(color) * alpha);
+                            assert!(
+                                (pixel.0[channel] as i32 - expected as i32).abs() <= 2,
+                                "{:?} at {}px over {:?}: {:?}", filter, size, behind, pixel);
+                            // Very faint texels don't have the precision to
+                            // hold their colour exactly, but they're hardly
+                            // visible either.
+                            if pixel.0[3] >= 32 {
+                                assert!(
+                                    (straight.0[channel] as i32 - color as i32).abs() <= 1,
+                                    "{:?} at {}px over {:?}: {:?}", filter, size, behind, straight);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unpremultiplying_undoes_resizing() {
+        let image = RgbaImage::from_fn(256, 64, |x, y| {
+            Rgba([ x as u8, (y * 4) as u8, 255 - x as u8, (x as u8).wrapping_mul(7) ])
+        });
+        for &filter in FILTERS.iter() {
+            let frame = unpremultiply(&resize(DynamicImage::ImageRgba8(image.clone()), (256, 64), filter));
+            for (before, after) in image.pixels().zip(frame.pixels()) {
+                let alpha = before.0[3];
+                assert_eq!(after.0[3], alpha);
+                if alpha < 32 { continue; }
+                // Premultiplied colours are rounded to a whole level, and
+                // unpremultiplying scales that up by 255 / alpha.
+                let tolerance = (255 / alpha as i32).max(1);
+                for channel in 0..3 {
+                    assert!(
+                        (before.0[channel] as i32 - after.0[channel] as i32).abs() <= tolerance,
+                        "{:?}: {:?} came back as {:?}", filter, before, after);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fits_like_resizing_does() {
+        for &size in [ (680, 680), (20, 10), (10, 20), (33, 7), (1, 1), (0, 5) ].iter() {
+            for &bounds in [ (100, 100), (40, 40), (7, 23), (1000, 3), (1, 1) ].iter() {
+                let image = DynamicImage::new_rgba8(size.0.max(1), size.1.max(1));
+                let resized = image.resize(bounds.0, bounds.1, FilterType::Nearest).into_rgba8();
+                assert_eq!(fit(size, bounds), resized.dimensions(), "{:?} in {:?}", size, bounds);
             }
-        } else {
-            let value = self.completed.get(self.current).unwrap();
-            self.current += 1;
-            if self.current >= NUM_FRAMES { self.current = 0; }
-            Some(value.clone())
         }
+        assert_eq!(fit((20, 10), (40, 40)), (40, 20));
+        assert_eq!(fit((680, 680), (100, 100)), (100, 100));
     }
 }
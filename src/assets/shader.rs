@@ -0,0 +1,76 @@
+//! Compiles the fragment shaders that packs can come with (see
+//! `pack::Shader`).
+
+use glium;
+
+use glium::{ Display, Program };
+use glium::uniforms::UniformType;
+
+use crate::pack::{ AnimationKind, Shader };
+
+/// Compiles `shader` along with the built-in vertex shader for its version
+/// of GLSL, and checks that it only uses the uniforms it declared, as the
+/// types they're given to it as.
+pub fn compile(display: &Display, shader: &Shader) -> Result<Program, String> {
+    let version = shader.version().ok_or_else(|| "It doesn't start with a #version line.".to_owned())?;
+
+    let vertex = match version {
+        "100" => include_str!("../vertex.100.glsl"),
+        "110" => include_str!("../vertex.110.glsl"),
+        "140" => include_str!("../vertex.140.glsl"),
+        other => return Err(format!(
+            "It's written for GLSL version {}, but only versions {} can be used.",
+            other,
+            Shader::VERSIONS.join(", "))),
+    };
+
+    let program = Program::from_source(display, vertex, &shader.source, None)
+        .map_err(|error| format!("{}", error))?;
+
+    for (name, uniform) in program.uniforms() {
+        // The vertex shader's own uniform, and the frame itself.
+        let expected = match name.as_str() {
+            "matrix" => Some("mat4"),
+            "tex" => Some("sampler2D"),
+            _ if shader.uniforms.contains(name) => Shader::UNIFORMS
+                .iter()
+                .find(|(other, _)| other == name)
+                .map(|(_, ty)| *ty),
+            _ => None,
+        };
+        let expected = match expected {
+            Some(expected) => expected,
+            None => return Err(format!(
+                "It uses uniform \"{}\", which isn't listed in the manifest's shader uniforms.",
+                name)),
+        };
+        if glsl_type(expected) != Some(uniform.ty) {
+            return Err(format!("Uniform \"{}\" has to be declared as a {}.", name, expected));
+        }
+    }
+
+    Ok(program)
+}
+
+/// Returns the value of the `state` uniform while an animation of `kind` is
+/// playing.
+pub fn state(kind: AnimationKind) -> i32 {
+    match kind {
+        AnimationKind::Ground => 0,
+        AnimationKind::Fall => 1,
+        AnimationKind::Drag => 2,
+    }
+}
+
+/// Returns the type glium reports for uniforms declared as `name` in GLSL.
+fn glsl_type(name: &str) -> Option<UniformType> {
+    match name {
+        "float" => Some(UniformType::Float),
+        "int" => Some(UniformType::Int),
+        "vec2" => Some(UniformType::FloatVec2),
+        "vec4" => Some(UniformType::FloatVec4),
+        "mat4" => Some(UniformType::FloatMat4),
+        "sampler2D" => Some(UniformType::Sampler2d),
+        _ => None,
+    }
+}
@@ -0,0 +1,193 @@
+use crate::misc::Rng;
+use crate::pack::{ AnimationKind, Pack };
+
+/// Plays the animations of a pack for one companion, picking what to play
+/// next whenever an animation finishes.
+#[derive(Debug, Clone)]
+pub struct Behaviour {
+    /// The index of the animation that is playing, in `Pack::animations`.
+    pub animation: usize,
+    /// The index of the frame that is showing, in `Animation::frames`.
+    pub step: usize,
+    /// How long the current frame has been showing for, in milliseconds.
+    elapsed: f32,
+    rng: Rng,
+}
+
+impl Behaviour {
+    pub fn new(pack: &Pack, rng: Rng) -> Self {
+        let mut behaviour = Behaviour {
+            animation: 0,
+            step: 0,
+            elapsed: 0.0,
+            rng,
+        };
+        behaviour.animation = behaviour.pick(pack, None);
+        behaviour
+    }
+
+    /// Returns the index of the frame that is showing, in `Pack::frames`.
+    pub fn frame(&self, pack: &Pack) -> usize {
+        pack.animations[self.animation].frames[self.step].frame
+    }
+
+    /// Returns how fast the companion should be moving right now, in pixels
+    /// of the original frames per second.
+    pub fn velocity(&self, pack: &Pack) -> (f32, f32) {
+        pack.animations[self.animation].frames[self.step].velocity
+    }
+
+    /// Returns where the point that sits on the ground is in the frame that
+    /// is showing, in pixels of the original frames.
+    pub fn anchor(&self, pack: &Pack) -> (f32, f32) {
+        pack.animations[self.animation].frames[self.step].anchor.unwrap_or(pack.anchor)
+    }
+
+    /// Returns what kind of animation is playing.
+    pub fn kind(&self, pack: &Pack) -> AnimationKind {
+        pack.animations[self.animation].kind
+    }
+
+    /// Moves time forward by `dt` seconds, moving on to the next frame or
+    /// animation if it's time to. Returns whether the frame changed.
+    pub fn update(&mut self, pack: &Pack, dt: f32) -> bool {
+        let mut changed = false;
+        self.elapsed += dt * 1000.0;
+        loop {
+            let animation = &pack.animations[self.animation];
+            let duration = animation.frames[self.step].duration.max(1) as f32;
+            if self.elapsed < duration { break; }
+            self.elapsed -= duration;
+            self.step += 1;
+            if self.step >= animation.frames.len() {
+                self.animation = self.pick(pack, Some(self.animation));
+                self.step = 0;
+            }
+            changed = true;
+        }
+        changed
+    }
+
+    /// Starts playing an animation of the given kind from the beginning, if
+    /// the pack has one and one isn't already playing. Returns whether it
+    /// switched.
+    pub fn play_kind(&mut self, pack: &Pack, kind: AnimationKind) -> bool {
+        if self.kind(pack) == kind { return false; }
+        match pack.animations.iter().position(|animation| animation.kind == kind) {
+            Some(animation) => {
+                self.play(animation);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Starts playing the animation called `name` from the beginning, if the
+    /// pack has one. Returns whether it did.
+    pub fn play_animation(&mut self, pack: &Pack, name: &str) -> bool {
+        match pack.animation(name) {
+            Some(animation) if !pack.animations[animation].frames.is_empty() => {
+                self.play(animation);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Switches to one of the animations the current one lists for running
+    /// into the edge of the screen, if there are any. Returns whether it
+    /// switched.
+    pub fn hit_border(&mut self, pack: &Pack) -> bool {
+        let choices = resolve(pack, &pack.animations[self.animation].border);
+        match self.weighted(&choices) {
+            Some(animation) => {
+                self.play(animation);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Moves on from the current animation early, as if it had finished.
+    pub fn finish(&mut self, pack: &Pack) {
+        let animation = self.pick(pack, Some(self.animation));
+        self.play(animation);
+    }
+
+    /// Carries on after `old` has been reloaded as `new`, playing the same
+    /// animation from the same frame if it's still there, and otherwise
+    /// moving on to another one.
+    pub fn reload(&mut self, old: &Pack, new: &Pack) {
+        let name = &old.animations[self.animation].name;
+        match new.animation(name) {
+            Some(animation) if !new.animations[animation].frames.is_empty() => {
+                self.animation = animation;
+                self.step = self.step.min(new.animations[animation].frames.len() - 1);
+            },
+            _ => {
+                let animation = self.pick(new, None);
+                self.play(animation);
+            },
+        }
+    }
+
+    fn play(&mut self, animation: usize) {
+        self.animation = animation;
+        self.step = 0;
+        self.elapsed = 0.0;
+    }
+
+    /// Picks the animation to play after `current`. This is one of the
+    /// animations `current` lists as next if there are any, and otherwise
+    /// any animation on the ground, by weight.
+    fn pick(&mut self, pack: &Pack, current: Option<usize>) -> usize {
+        let next = current
+            .map(|current| resolve(pack, &pack.animations[current].next))
+            .unwrap_or_default();
+
+        if let Some(animation) = self.weighted(&next) {
+            return animation;
+        }
+
+        let ground = pack.animations
+            .iter()
+            .enumerate()
+            .filter(|(_, animation)| animation.kind == AnimationKind::Ground)
+            .map(|(index, animation)| (index, animation.weight))
+            .collect::<Vec<_>>();
+
+        if let Some(animation) = self.weighted(&ground) {
+            return animation;
+        }
+
+        // If none of them have a weight, they're all as likely as each other.
+        if !ground.is_empty() {
+            let index = self.rng.range(0, ground.len() as i32) as usize;
+            return ground[index].0;
+        }
+
+        current.unwrap_or(0)
+    }
+
+    /// Picks one of `choices` at random by weight. Returns `None` if none of
+    /// them have any weight.
+    fn weighted(&mut self, choices: &[(usize, u32)]) -> Option<usize> {
+        let total = choices.iter().map(|(_, weight)| *weight as u64).sum::<u64>();
+        if total == 0 { return None; }
+        let mut roll = self.rng.next_u64() % total;
+        for (choice, weight) in choices {
+            if roll < *weight as u64 { return Some(*choice); }
+            roll -= *weight as u64;
+        }
+        None
+    }
+}
+
+/// Turns a list of animation names and weights into indices and weights,
+/// leaving out any names the pack doesn't have.
+fn resolve(pack: &Pack, names: &[(String, u32)]) -> Vec<(usize, u32)> {
+    names
+        .iter()
+        .filter_map(|(name, weight)| Some((pack.animation(name)?, *weight)))
+        .collect()
+}
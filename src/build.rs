@@ -2,7 +2,69 @@ fn env(var: &'static str) -> String {
     std::env::var(var).unwrap_or("".to_owned())
 }
 
+/// Bakes the built-in frames into `OUT_DIR`, so that they don't have to be
+/// decoded from PNG every time desktop sharkie starts.
+///
+/// Each baked frame is `DSBK`, followed by the width and height of the frame
+/// and the x, y, width and height of the part of it that isn't transparent,
+/// all as little endian `u32`s. After that comes the non-transparent part,
+/// as a QOI image. See `assets::baked` for the other side of this.
+fn bake_frames() {
+    let out_dir = std::path::PathBuf::from(env("OUT_DIR"));
+    let mut paths = std::fs::read_dir("src/assets")
+        .expect("Failed to read src/assets.")
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+            name.starts_with("frame") && name.ends_with(".png")
+        })
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    for path in paths {
+        let image = image::open(&path)
+            .unwrap_or_else(|error| panic!("Failed to decode {}: {}", path.display(), error))
+            .into_rgba8();
+        let (width, height) = image.dimensions();
+
+        // Find the smallest rectangle that holds all of the visible pixels.
+        let (mut x0, mut y0, mut x1, mut y1) = (width, height, 0, 0);
+        for (x, y, pixel) in image.enumerate_pixels() {
+            if pixel.0[3] != 0 {
+                x0 = x0.min(x);
+                y0 = y0.min(y);
+                x1 = x1.max(x + 1);
+                y1 = y1.max(y + 1);
+            }
+        }
+        if x1 <= x0 { x0 = 0; y0 = 0; x1 = 0; y1 = 0; }
+
+        let mut baked = b"DSBK".to_vec();
+        for value in [ width, height, x0, y0, x1 - x0, y1 - y0 ].iter() {
+            baked.extend_from_slice(&value.to_le_bytes());
+        }
+
+        // Frames that are completely transparent don't need any pixels.
+        if x1 > x0 {
+            let visible = image::imageops::crop_imm(&image, x0, y0, x1 - x0, y1 - y0).to_image();
+            baked.extend(qoi::encode_to_vec(visible.as_raw(), x1 - x0, y1 - y0)
+                .unwrap_or_else(|error| panic!("Failed to encode {}: {}", path.display(), error)));
+        }
+
+        let out = out_dir.join(path.with_extension("baked").file_name().unwrap());
+        std::fs::write(&out, baked)
+            .unwrap_or_else(|error| panic!("Failed to write {}: {}", out.display(), error));
+    }
+}
+
 fn main() {
+    println!("cargo:rerun-if-changed=src/build.rs");
+    println!("cargo:rerun-if-changed=src/resource.rc");
+    println!("cargo:rerun-if-changed=src/assets");
+
+    bake_frames();
+
     if env("CARGO_CFG_TARGET_FAMILY") == "unix"
     && env("CARGO_CFG_TARGET_OS") != "macos"
     && env("CARGO_CFG_TARGET_OS") != "android"
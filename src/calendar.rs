@@ -0,0 +1,335 @@
+//! Special events on certain dates, like a birthday outfit or a New Year's
+//! animation at midnight.
+//!
+//! Events are listed in the config file (see `Config::events`), which
+//! applies them to every companion, or in a pack's manifest, which applies
+//! them to the companions drawn with it:
+//!
+//! ```toml
+//! [[event]]
+//! name = "new year"
+//! date = "01-01"
+//! time = "00:00"
+//! minutes = 10
+//! animation = "dance"
+//! say = "Happy new year!"
+//!
+//! [[event]]
+//! name = "winter"
+//! date = "12-01"
+//! until = "12-26"
+//! accessories = ["scarf"]
+//! ```
+//!
+//! Dates and times are in the local timezone, and are read from a `Clock` so
+//! that they can be faked.
+
+use chrono;
+use serde::{ Deserialize, Serialize };
+
+use std::convert::TryFrom;
+use std::fmt::Debug;
+
+use chrono::{ Datelike, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, Timelike };
+
+/// Where the current date and time come from.
+pub trait Clock: Debug {
+    /// Returns the local date and time.
+    fn now(&self) -> NaiveDateTime;
+}
+
+/// The computer's clock, in its timezone.
+#[derive(Debug)]
+pub struct LocalClock;
+
+impl Clock for LocalClock {
+    fn now(&self) -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+}
+
+/// A clock that was set to some other time, and keeps ticking from there.
+/// This is what `--date` uses, for trying out events without waiting for
+/// them.
+#[derive(Debug)]
+pub struct SetClock {
+    offset: Duration,
+}
+
+impl SetClock {
+    pub fn new(time: NaiveDateTime) -> Self {
+        SetClock { offset: time - Local::now().naive_local() }
+    }
+}
+
+impl Clock for SetClock {
+    fn now(&self) -> NaiveDateTime {
+        Local::now().naive_local() + self.offset
+    }
+}
+
+/// Something that happens on certain days, or at a certain time on them.
+/// Every rule that's given has to match for the event to be on.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Event {
+    pub name: String,
+    /// The day the event is on, either every year like `"04-01"`, or once
+    /// like `"2030-04-01"`. If this is unset, it's on every day.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<Day>,
+    /// The last day of events that are on for several days, in the same
+    /// format as `date`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<Day>,
+    /// The days of the week the event is on, like `["saturday", "sunday"]`.
+    /// If this is empty, it's on any day of the week.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub weekdays: Vec<Weekday>,
+    /// When the event starts on the days it's on, like `"23:30"`. If this is
+    /// unset, it starts at midnight.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<TimeOfDay>,
+    /// How many minutes the event lasts, which can run on past midnight. If
+    /// this is unset, it lasts until the end of the day.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minutes: Option<u32>,
+    /// The animation to play when the event starts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub animation: Option<String>,
+    /// The accessories to wear while the event is on.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub accessories: Vec<String>,
+    /// What to say when the event starts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub say: Option<String>,
+}
+
+/// A day of the year, and which year if it only happens once.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Day {
+    pub year: Option<i32>,
+    pub month: u32,
+    pub day: u32,
+}
+
+/// A day of the week.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Weekday(pub chrono::Weekday);
+
+/// A time of day, to the minute.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeOfDay(pub NaiveTime);
+
+/// The events a set of companions know about, and the clock they check them
+/// against.
+#[derive(Debug)]
+pub struct Calendar {
+    clock: Box<dyn Clock>,
+    /// The events from the config file, which every companion celebrates.
+    pub events: Vec<Event>,
+}
+
+impl Calendar {
+    pub fn new(clock: Box<dyn Clock>, events: Vec<Event>) -> Self {
+        Calendar { clock, events }
+    }
+
+    /// Returns the local date and time.
+    pub fn now(&self) -> NaiveDateTime {
+        self.clock.now()
+    }
+}
+
+impl Event {
+    /// Returns whether the event is on at `now`. Events that last for a
+    /// number of minutes can run on past midnight, so they're on if they
+    /// started on an earlier day and haven't finished yet, even if today
+    /// isn't one of their days.
+    pub fn active(&self, now: NaiveDateTime) -> bool {
+        // Events without a time start at midnight, which is the default time.
+        let start = self.time.map(|time| time.0).unwrap_or_default();
+        let minutes = match self.minutes {
+            Some(minutes) => Duration::minutes(minutes as i64),
+            None => return self.on(now.date()) && now.time() >= start,
+        };
+        let days = minutes.num_days() + 1;
+        (0..=days).any(|days_ago| {
+            let date = now.date() - Duration::days(days_ago);
+            let start = date.and_time(start);
+            self.on(date) && start <= now && now < start + minutes
+        })
+    }
+
+    /// Returns whether the event starts on `date`.
+    fn on(&self, date: NaiveDate) -> bool {
+        if !self.weekdays.is_empty() && !self.weekdays.contains(&Weekday(date.weekday())) {
+            return false;
+        }
+
+        let from = match self.date {
+            Some(from) => from,
+            None => return true,
+        };
+        let until = self.until.unwrap_or(from);
+        let today = (date.year(), date.month(), date.day());
+        let (from, until) = (from.on(date.year()), until.on(date.year()));
+        // Events like "12-30" until "01-02" go over the end of the year.
+        if from <= until {
+            from <= today && today <= until
+        } else {
+            from <= today || today <= until
+        }
+    }
+}
+
+impl Day {
+    /// Returns the day as a year, month and day, taking `year` as the year
+    /// for days that happen every year.
+    fn on(self, year: i32) -> (i32, u32, u32) {
+        (self.year.unwrap_or(year), self.month, self.day)
+    }
+}
+
+impl TryFrom<String> for Day {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        let invalid = || format!("\"{}\" isn't a date. Use \"MM-DD\" for every year, or \"YYYY-MM-DD\" for once.", text);
+        let parts = text
+            .split('-')
+            .map(|part| part.trim().parse::<u32>().map_err(|_| invalid()))
+            .collect::<Result<Vec<u32>, String>>()?;
+        let day = match parts[..] {
+            [ month, day ] => Day { year: None, month, day },
+            [ year, month, day ] => Day { year: Some(year as i32), month, day },
+            _ => return Err(invalid()),
+        };
+        // Leap days only exist in some years, but are still days that can
+        // come around.
+        if chrono::NaiveDate::from_ymd_opt(day.year.unwrap_or(2000), day.month, day.day).is_none() {
+            return Err(invalid());
+        }
+        Ok(day)
+    }
+}
+
+impl From<Day> for String {
+    fn from(day: Day) -> String {
+        match day.year {
+            Some(year) => format!("{:04}-{:02}-{:02}", year, day.month, day.day),
+            None => format!("{:02}-{:02}", day.month, day.day),
+        }
+    }
+}
+
+impl TryFrom<String> for Weekday {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        text.trim()
+            .parse()
+            .map(Weekday)
+            .map_err(|_| format!("\"{}\" isn't a day of the week.", text))
+    }
+}
+
+impl From<Weekday> for String {
+    fn from(weekday: Weekday) -> String {
+        format!("{:?}", weekday.0).to_lowercase()
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        NaiveTime::parse_from_str(text.trim(), "%H:%M")
+            .map(TimeOfDay)
+            .map_err(|_| format!("\"{}\" isn't a time. Use \"HH:MM\", like \"23:30\".", text))
+    }
+}
+
+impl From<TimeOfDay> for String {
+    fn from(time: TimeOfDay) -> String {
+        format!("{:02}:{:02}", time.0.hour(), time.0.minute())
+    }
+}
+
+/// Reads a date and time like `2030-12-31T23:59`, or just a date, which is
+/// taken to be midnight. This is the format `--date` takes.
+pub fn parse_date_time(text: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M")
+        .ok()
+        .or_else(|| chrono::NaiveDate::parse_from_str(text, "%Y-%m-%d").ok().and_then(|date| date.and_hms_opt(0, 0, 0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(text: &str) -> Event {
+        toml::from_str(&format!("name = \"test\"\n{}", text)).unwrap()
+    }
+
+    /// Returns whether `event` is on by a clock set to `time`.
+    fn active_at(event: &Event, time: &str) -> bool {
+        let calendar = Calendar::new(Box::new(SetClock::new(parse_date_time(time).unwrap())), Vec::new());
+        event.active(calendar.now())
+    }
+
+    #[test]
+    fn runs_past_midnight() {
+        let event = event("date = \"12-31\"\ntime = \"23:55\"\nminutes = 10");
+        assert!(!active_at(&event, "2029-12-31T23:54"));
+        assert!(active_at(&event, "2029-12-31T23:55"));
+        assert!(active_at(&event, "2030-01-01T00:04"));
+        assert!(!active_at(&event, "2030-01-01T00:05"));
+        assert!(!active_at(&event, "2030-01-01T23:56"), "it starts on the 31st, not the 1st");
+    }
+
+    #[test]
+    fn wraps_around_the_end_of_the_year() {
+        let event = event("date = \"12-30\"\nuntil = \"01-02\"");
+        assert!(!active_at(&event, "2029-12-29T23:59"));
+        assert!(active_at(&event, "2029-12-30T00:00"));
+        assert!(active_at(&event, "2030-01-01T12:00"));
+        assert!(active_at(&event, "2030-01-02T23:59"));
+        assert!(!active_at(&event, "2030-01-03T00:00"));
+    }
+
+    #[test]
+    fn happens_once_in_the_given_year() {
+        let event = event("date = \"2030-04-01\"");
+        assert!(active_at(&event, "2030-04-01T08:00"));
+        assert!(!active_at(&event, "2031-04-01T08:00"));
+    }
+
+    #[test]
+    fn only_starts_on_its_weekdays() {
+        // 2030-01-05 is a Saturday.
+        let event = event("weekdays = [\"saturday\"]\ntime = \"23:30\"\nminutes = 60");
+        assert!(!active_at(&event, "2030-01-04T23:45"));
+        assert!(active_at(&event, "2030-01-05T23:45"));
+        assert!(active_at(&event, "2030-01-06T00:15"), "it carries on into Sunday");
+        assert!(!active_at(&event, "2030-01-06T23:45"));
+    }
+
+    #[test]
+    fn lasts_until_the_end_of_the_day_without_minutes() {
+        let event = event("time = \"18:00\"");
+        assert!(!active_at(&event, "2030-06-01T17:59"));
+        assert!(active_at(&event, "2030-06-01T23:59"));
+        assert!(!active_at(&event, "2030-06-02T00:01"));
+    }
+
+    #[test]
+    fn can_last_for_days() {
+        let event = event("date = \"2030-06-01\"\ntime = \"12:00\"\nminutes = 2880");
+        assert!(active_at(&event, "2030-06-03T11:59"));
+        assert!(!active_at(&event, "2030-06-03T12:00"));
+    }
+}
@@ -0,0 +1,50 @@
+//! Subcommands for pack authors, like `desktop-sharkie validate <pack>`.
+//! These run instead of the companions, without opening a window, and exit
+//! with their own status code.
+
+pub mod packs;
+pub mod preview;
+pub mod validate;
+
+use std::path::Path;
+
+use crate::pack::{ AssetError, Pack };
+
+/// Runs the subcommand named by the first command line argument, if there
+/// is one. Returns the status code to exit with, or `None` if desktop
+/// sharkie should start up as usual.
+pub fn run() -> Option<i32> {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let command = match args.first().map(String::as_str) {
+        Some("archive") => packs::archive,
+        Some("install") => packs::install,
+        Some("list") => packs::list,
+        Some("preview") => preview::run,
+        Some("remove") => packs::remove,
+        Some("validate") => validate::run,
+        _ => return None,
+    };
+
+    // Desktop sharkie doesn't get a console of its own on Windows, so borrow
+    // the one it was started from to print to.
+    #[cfg(platform_windows)] {
+        crate::platform::windows::attach_console();
+    }
+
+    Some(command(&args[1..]))
+}
+
+/// Opens a pack by name, or from a path so that packs can be checked before
+/// they're installed.
+pub fn open(pack: &str) -> Result<Pack, AssetError> {
+    let path = Path::new(pack);
+    if pack != Pack::BUILTIN && path.exists() {
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| pack.to_owned());
+        Pack::load(&name, path)
+    } else {
+        Pack::find(pack)
+    }
+}
@@ -0,0 +1,212 @@
+//! Commands for managing the packs in `Pack::dir`:
+//!
+//! - `desktop-sharkie install [--unpack] <archive>` installs a pack archive,
+//!   named after its file. Archives are kept as they are and loaded straight
+//!   from the archive, unless `--unpack` is given.
+//! - `desktop-sharkie list` lists the packs that can be used.
+//! - `desktop-sharkie remove <pack>` deletes an installed pack.
+//! - `desktop-sharkie archive [--out <file>] <directory>` packs a directory
+//!   into an archive for sharing.
+
+use std::fs;
+use std::path::{ Path, PathBuf };
+
+use crate::pack::{ archive, Pack };
+
+pub fn install(args: &[String]) -> i32 {
+    let unpack = args.iter().any(|arg| arg == "--unpack");
+    let files = args.iter().filter(|arg| *arg != "--unpack").collect::<Vec<_>>();
+
+    let file = match files.as_slice() {
+        [file] => Path::new(file.as_str()),
+        _ => {
+            eprintln!("Usage: desktop-sharkie install [--unpack] <archive>");
+            return 2;
+        },
+    };
+
+    if !archive::is_archive(file) {
+        eprintln!("{} isn't a pack archive. Pack archives end in .{}.", file.display(), archive::EXTENSION);
+        return 1;
+    }
+
+    let name = match file.file_stem() {
+        Some(stem) => stem.to_string_lossy().into_owned(),
+        None => {
+            eprintln!("{} doesn't have a name to install it as.", file.display());
+            return 1;
+        },
+    };
+
+    if name == Pack::BUILTIN || Pack::path(&name).is_some() {
+        eprintln!("There is already a pack called \"{}\". Remove it first to replace it.", name);
+        return 1;
+    }
+
+    // Loading the pack checks the archive against its manifest, and makes
+    // sure it's a pack at all, before anything gets copied.
+    if let Err(error) = Pack::load(&name, file) {
+        eprintln!("{}", error);
+        return 1;
+    }
+
+    if let Err(error) = fs::create_dir_all(Pack::dir()) {
+        eprintln!("Failed to create {}: {}", Pack::dir().display(), error);
+        return 1;
+    }
+
+    let installed = if unpack {
+        let dir = Pack::dir().join(&name);
+        archive::extract(file, &dir).map(|_| dir).map_err(|error| error.to_string())
+    } else {
+        let copy = Pack::dir().join(format!("{}.{}", name, archive::EXTENSION));
+        fs::copy(file, &copy)
+            .map(|_| copy)
+            .map_err(|error| format!("Failed to copy {}: {}", file.display(), error))
+    };
+
+    match installed {
+        Ok(path) => {
+            println!("Installed \"{}\" to {}.", name, path.display());
+            0
+        },
+        Err(error) => {
+            eprintln!("{}", error);
+            1
+        },
+    }
+}
+
+pub fn list(args: &[String]) -> i32 {
+    if !args.is_empty() {
+        eprintln!("Usage: desktop-sharkie list");
+        return 2;
+    }
+
+    println!("{} (built-in)", Pack::BUILTIN);
+
+    for (name, path) in installed() {
+        let kind = if archive::is_archive(&path) {
+            "archive"
+        } else if path.is_dir() {
+            "directory"
+        } else {
+            "file"
+        };
+        println!("{} ({})", name, kind);
+    }
+
+    0
+}
+
+pub fn remove(args: &[String]) -> i32 {
+    let name = match args {
+        [name] => name.as_str(),
+        _ => {
+            eprintln!("Usage: desktop-sharkie remove <pack>");
+            return 2;
+        },
+    };
+
+    if name == Pack::BUILTIN {
+        eprintln!("The built-in pack can't be removed.");
+        return 1;
+    }
+
+    let path = match Pack::path(name) {
+        Some(path) => path,
+        None => {
+            eprintln!("There is no pack called \"{}\" in {}.", name, Pack::dir().display());
+            return 1;
+        },
+    };
+
+    // `Pack::path` only takes plain names, but this is about to delete a
+    // whole directory, so make sure.
+    if path.parent() != Some(Pack::dir().as_path()) {
+        eprintln!("{} isn't in {}, so it wasn't removed.", path.display(), Pack::dir().display());
+        return 1;
+    }
+
+    let removed = if path.is_dir() { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+
+    match removed {
+        Ok(()) => {
+            println!("Removed \"{}\".", name);
+            0
+        },
+        Err(error) => {
+            eprintln!("Failed to remove {}: {}", path.display(), error);
+            1
+        },
+    }
+}
+
+pub fn archive(args: &[String]) -> i32 {
+    let mut out = None;
+    let mut dirs = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => out = args.next().map(PathBuf::from),
+            _ => dirs.push(Path::new(arg)),
+        }
+    }
+
+    let dir = match dirs.as_slice() {
+        [dir] => *dir,
+        _ => {
+            eprintln!("Usage: desktop-sharkie archive [--out <file>] <directory>");
+            return 2;
+        },
+    };
+
+    // Refuse to share something that wouldn't load anyway.
+    let name = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "pack".to_owned());
+    if let Err(error) = Pack::load(&name, dir) {
+        eprintln!("{}", error);
+        return 1;
+    }
+
+    let out = out.unwrap_or_else(|| PathBuf::from(format!("{}.{}", name, archive::EXTENSION)));
+
+    match archive::create(dir, &out) {
+        Ok(()) => {
+            println!("Wrote {}.", out.display());
+            0
+        },
+        Err(error) => {
+            eprintln!("{}", error);
+            1
+        },
+    }
+}
+
+/// Returns the name and path of every pack in `Pack::dir`, sorted by name.
+fn installed() -> Vec<(String, PathBuf)> {
+    let entries = match fs::read_dir(Pack::dir()) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut packs = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter_map(|path| {
+            if path.is_dir() {
+                let name = path.file_name()?.to_string_lossy().into_owned();
+                return Some((name, path));
+            }
+            let extension = path.extension()?.to_string_lossy().into_owned();
+            if !Pack::EXTENSIONS.contains(&extension.as_str()) { return None; }
+            let name = path.file_stem()?.to_string_lossy().into_owned();
+            Some((name, path))
+        })
+        .collect::<Vec<(String, PathBuf)>>();
+
+    packs.sort();
+    packs
+}
@@ -0,0 +1,326 @@
+//! `desktop-sharkie preview [--size <px>] [--out <dir>] [--gif] [--apng]
+//! <pack>` renders a pack to files, so that changes to its art can be
+//! reviewed without running it.
+//!
+//! It always writes a contact sheet, `<pack>-sheet.png`, with a labelled row
+//! of frames for every animation, and any frames that no animation shows at
+//! the end. `--gif` and `--apng` also write every animation on its own, as
+//! `<pack>-<animation>.gif` or `.apng`, playing at its real timing.
+//!
+//! Frames are decoded and resized by the same code as `assets::Frames`, with
+//! the pack's filter, at `--size` pixels tall, or as close to it as pixel art
+//! snaps to (see `assets::Filter::snap`). On screen, they're uploaded as sRGB
+//! textures with premultiplied alpha and drawn one texel per pixel, so the
+//! colours that come out are the ones that went in, and only blending
+//! happens in linear light. The contact sheet does the same when it draws
+//! frames over its backdrop, and the animations are turned back into
+//! straight alpha, which is what image files expect.
+//!
+//! Everything is drawn on the CPU, so that previews can be made where
+//! there's no display or GPU, like in CI. That means they show the frames
+//! themselves, but not what the GPU adds on top of them: pack shaders,
+//! accessories, particles and motion (squashing, bobbing and tilting) are
+//! all left out.
+
+use image;
+use rayon;
+
+use std::fs;
+use std::path::{ Path, PathBuf };
+
+use image::{ Rgba, RgbaImage };
+use rayon::prelude::*;
+
+use crate::assets::{ self, Filter };
+use crate::font;
+use crate::pack::{ animated, AssetError, Pack };
+
+/// How tall frames are drawn by default, which is how big a companion is on
+/// a 900px tall screen.
+const DEFAULT_SIZE: u32 = 180;
+
+/// The most frames there are in a row before it wraps.
+const COLUMNS: usize = 10;
+
+const MARGIN: u32 = 16;
+const GAP: u32 = 8;
+const TEXT_SCALE: u32 = 2;
+
+const BACKGROUND: Rgba<u8> = Rgba([ 255, 255, 255, 255 ]);
+const TEXT: Rgba<u8> = Rgba([ 32, 32, 32, 255 ]);
+const FAILED: Rgba<u8> = Rgba([ 220, 40, 40, 255 ]);
+/// The checkerboard behind frames, so that transparent parts stand out.
+const CHECKER: [Rgba<u8>; 2] = [ Rgba([ 236, 236, 236, 255 ]), Rgba([ 204, 204, 204, 255 ]) ];
+const CHECKER_SIZE: u32 = 8;
+
+/// One row of the contact sheet: a title, and the frames under it along with
+/// how long each of them is shown for.
+struct Row {
+    title: String,
+    frames: Vec<(usize, Option<u32>)>,
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let mut size = DEFAULT_SIZE;
+    let mut out = PathBuf::from(".");
+    let mut gif = false;
+    let mut apng = false;
+    let mut pack = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--size" => match args.next().and_then(|size| size.parse::<u32>().ok()) {
+                Some(value) if value > 0 => size = value,
+                _ => return usage(),
+            },
+            "--out" => match args.next() {
+                Some(dir) => out = PathBuf::from(dir),
+                None => return usage(),
+            },
+            "--gif" => gif = true,
+            "--apng" => apng = true,
+            _ if pack.is_none() && !arg.starts_with("--") => pack = Some(arg.clone()),
+            _ => return usage(),
+        }
+    }
+
+    let pack = match pack {
+        Some(pack) => pack,
+        None => return usage(),
+    };
+
+    let pack = match super::open(&pack) {
+        Ok(pack) => pack,
+        Err(error) => {
+            eprintln!("{}", error);
+            return 1;
+        },
+    };
+
+    if let Err(error) = fs::create_dir_all(&out) {
+        eprintln!("Failed to create {}: {}", out.display(), error);
+        return 1;
+    }
+
+    let filter = pack.filter.unwrap_or_default();
+    let cell = filter.snap(pack.frame_size, (
+        ((size as u64 * pack.frame_size.0 as u64) / pack.frame_size.1.max(1) as u64).max(1) as i32,
+        size as i32,
+    ));
+    let cell = (cell.0 as u32, cell.1 as u32);
+    let frames = render(&pack, cell, filter);
+
+    let mut status = 0;
+    for (index, frame) in frames.iter().enumerate() {
+        if let Err(error) = frame {
+            eprintln!("Frame {}: {}", index, error);
+            status = 1;
+        }
+    }
+
+    let mut files = vec![ (file_name(&out, &pack.name, "sheet", "png"), Ok(contact_sheet(&pack, &frames, cell))) ];
+
+    for animation in pack.animations.iter() {
+        // Every frame of a preview has to be the same size, so frames that
+        // failed to load are left blank.
+        let preview = animation.frames
+            .iter()
+            .map(|frame| animated::Frame {
+                image: frames
+                    .get(frame.frame)
+                    .and_then(|image| image.as_ref().ok())
+                    .map(|image| place(&assets::unpremultiply(image), cell))
+                    .unwrap_or_else(|| RgbaImage::new(cell.0, cell.1)),
+                delay: frame.duration,
+            })
+            .collect::<Vec<animated::Frame>>();
+        if preview.is_empty() { continue; }
+        if gif {
+            files.push((file_name(&out, &pack.name, &animation.name, "gif"), animated::encode_gif(&preview)));
+        }
+        if apng {
+            files.push((file_name(&out, &pack.name, &animation.name, "apng"), animated::encode_png(&preview)));
+        }
+    }
+
+    for (path, bytes) in files {
+        let written = bytes.and_then(|bytes| Ok(fs::write(&path, bytes)?));
+        match written {
+            Ok(()) => println!("Wrote {}", path.display()),
+            Err(error) => {
+                eprintln!("Failed to write {}: {}", path.display(), error);
+                status = 1;
+            },
+        }
+    }
+
+    status
+}
+
+fn usage() -> i32 {
+    eprintln!("Usage: desktop-sharkie preview [--size <px>] [--out <dir>] [--gif] [--apng] <pack>");
+    2
+}
+
+/// Decodes and resizes every frame of `pack` to fit in `size` with
+/// `filter`, the same way `assets::Frames` does.
+fn render(pack: &Pack, size: (u32, u32), filter: Filter) -> Vec<Result<RgbaImage, AssetError>> {
+    let decoded = assets::decode_sheets(&pack.sheets);
+    pack.frames
+        .par_iter()
+        .map(|source| source
+            .decode(&pack.sheets, &decoded, size)
+            .map(|image| assets::resize(image, size, filter)))
+        .collect()
+}
+
+/// Returns the path to write a preview of `name` to, keeping only the
+/// characters that are safe in file names.
+fn file_name(out: &Path, pack: &str, name: &str, extension: &str) -> PathBuf {
+    let clean = |name: &str| name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect::<String>();
+    out.join(format!("{}-{}.{}", clean(pack), clean(name), extension))
+}
+
+/// Puts `frame` on a transparent image of `size`, centered and standing on
+/// the bottom edge, like a companion standing on the ground.
+fn place(frame: &RgbaImage, size: (u32, u32)) -> RgbaImage {
+    let mut image = RgbaImage::new(size.0, size.1);
+    let x = size.0.saturating_sub(frame.width()) / 2;
+    let y = size.1.saturating_sub(frame.height());
+    image::imageops::replace(&mut image, frame, x, y);
+    image
+}
+
+fn contact_sheet(pack: &Pack, frames: &[Result<RgbaImage, AssetError>], cell: (u32, u32)) -> Vec<u8> {
+    let mut rows = pack.animations
+        .iter()
+        .map(|animation| {
+            let total = animation.frames.iter().map(|frame| frame.duration).sum::<u32>();
+            Row {
+                title: format!("{} ({} frames, {} ms)", animation.name, animation.frames.len(), total),
+                frames: animation.frames
+                    .iter()
+                    .map(|frame| (frame.frame, Some(frame.duration)))
+                    .collect(),
+            }
+        })
+        .collect::<Vec<Row>>();
+
+    let unused = (0..pack.frames.len())
+        .filter(|index| !pack.animations
+            .iter()
+            .any(|animation| animation.frames.iter().any(|frame| frame.frame == *index)))
+        .map(|index| (index, None))
+        .collect::<Vec<_>>();
+    if !unused.is_empty() {
+        rows.push(Row { title: format!("Unused ({} frames)", unused.len()), frames: unused });
+    }
+
+    let header = format!(
+        "{}: {} frames at {}x{}, drawn at {}x{}",
+        pack.name,
+        pack.frames.len(),
+        pack.frame_size.0,
+        pack.frame_size.1,
+        cell.0,
+        cell.1);
+
+    let title_height = font::height(TEXT_SCALE) + GAP;
+    let label_height = font::height(TEXT_SCALE) + GAP;
+    let columns = rows.iter().map(|row| row.frames.len()).max().unwrap_or(0).clamp(1, COLUMNS) as u32;
+
+    let width = rows
+        .iter()
+        .map(|row| font::width(&row.title, TEXT_SCALE))
+        .chain(Some(font::width(&header, TEXT_SCALE)))
+        .chain(Some(columns * (cell.0 + GAP) - GAP))
+        .max()
+        .unwrap_or(0) + MARGIN * 2;
+    let height = MARGIN * 2 + title_height + rows
+        .iter()
+        .map(|row| {
+            let lines = ((row.frames.len().max(1) - 1) / COLUMNS + 1) as u32;
+            GAP + title_height + lines * (cell.1 + label_height)
+        })
+        .sum::<u32>();
+
+    let mut sheet = RgbaImage::from_pixel(width, height, BACKGROUND);
+    font::draw(&mut sheet, &header, (MARGIN, MARGIN), TEXT_SCALE, TEXT);
+
+    let mut y = MARGIN + title_height;
+    for row in rows.iter() {
+        y += GAP;
+        font::draw(&mut sheet, &row.title, (MARGIN, y), TEXT_SCALE, TEXT);
+        y += title_height;
+
+        for line in row.frames.chunks(COLUMNS) {
+            for (column, &(index, duration)) in line.iter().enumerate() {
+                let x = MARGIN + column as u32 * (cell.0 + GAP);
+                checker(&mut sheet, (x, y), cell);
+                match frames.get(index) {
+                    Some(Ok(frame)) => blend(&mut sheet, &place(frame, cell), (x, y)),
+                    _ => cross(&mut sheet, (x, y), cell),
+                }
+                let label = match duration {
+                    Some(duration) => format!("#{} {}ms", index, duration),
+                    None => format!("#{}", index),
+                };
+                // Small frames get small labels, so they don't run into the
+                // next one.
+                let scale = if font::width(&label, TEXT_SCALE) > cell.0 { 1 } else { TEXT_SCALE };
+                font::draw(&mut sheet, &label, (x, y + cell.1 + GAP / 2), scale, TEXT);
+            }
+            y += cell.1 + label_height;
+        }
+        if row.frames.is_empty() {
+            font::draw(&mut sheet, "no frames", (MARGIN, y), TEXT_SCALE, FAILED);
+            y += cell.1 + label_height;
+        }
+    }
+
+    let mut bytes = Vec::new();
+    // Encoding to memory can't fail.
+    let _ = image::DynamicImage::ImageRgba8(sheet).write_to(&mut bytes, image::ImageOutputFormat::Png);
+    bytes
+}
+
+fn checker(sheet: &mut RgbaImage, pos: (u32, u32), size: (u32, u32)) {
+    for y in 0..size.1 {
+        for x in 0..size.0 {
+            let square = ((x / CHECKER_SIZE) + (y / CHECKER_SIZE)) as usize % 2;
+            sheet.put_pixel(pos.0 + x, pos.1 + y, CHECKER[square]);
+        }
+    }
+}
+
+/// Marks a frame that failed to load.
+fn cross(sheet: &mut RgbaImage, pos: (u32, u32), size: (u32, u32)) {
+    let steps = size.0.max(size.1);
+    for step in 0..steps {
+        let x = step * size.0 / steps;
+        let y = step * size.1 / steps;
+        sheet.put_pixel(pos.0 + x, pos.1 + y, FAILED);
+        sheet.put_pixel(pos.0 + size.0 - 1 - x, pos.1 + y, FAILED);
+    }
+}
+
+/// Draws `frame`, which has premultiplied alpha, over `sheet` at `pos`,
+/// blending in linear light like the GPU does when it draws into an sRGB
+/// framebuffer.
+fn blend(sheet: &mut RgbaImage, frame: &RgbaImage, pos: (u32, u32)) {
+    let linear = (0..=255u8).map(assets::to_linear).collect::<Vec<f32>>();
+    for (x, y, source) in frame.enumerate_pixels() {
+        let alpha = source.0[3] as f32 / 255.0;
+        if alpha == 0.0 { continue; }
+        let target = sheet.get_pixel_mut(pos.0 + x, pos.1 + y);
+        for channel in 0..3 {
+            let value = linear[source.0[channel] as usize]
+                + linear[target.0[channel] as usize] * (1.0 - alpha);
+            target.0[channel] = assets::to_srgb(value);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! `desktop-sharkie validate [--json] <pack>...` loads packs the same way the
+//! companions do, and reports anything that would make them look wrong or
+//! fail to load. Packs can be given by name, or by path before they're
+//! installed.
+//!
+//! Errors are problems that stop frames or animations from showing at all,
+//! and make the command exit with status 1. Warnings are things that load,
+//! but probably aren't what the pack's author meant.
+
+use image;
+use rayon;
+use serde::Serialize;
+use serde_json;
+
+use std::collections::HashSet;
+
+use image::{ DynamicImage, GenericImageView };
+use rayon::prelude::*;
+
+use crate::assets;
+use crate::pack::{ Pack, Shader };
+
+/// Frames bigger than this in either direction are slow to load, and use a
+/// lot of memory for something that gets shrunk down to a companion anyway.
+const MAX_DIMENSION: u32 = 2048;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Level {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Problem {
+    level: Level,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frame: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    animation: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Report {
+    pack: String,
+    frames: usize,
+    animations: usize,
+    problems: Vec<Problem>,
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let json = args.iter().any(|arg| arg == "--json");
+    let packs = args.iter().filter(|arg| *arg != "--json").collect::<Vec<_>>();
+
+    if packs.is_empty() {
+        eprintln!("Usage: desktop-sharkie validate [--json] <pack>...");
+        return 2;
+    }
+
+    let reports = packs.iter().map(|pack| validate(pack)).collect::<Vec<Report>>();
+
+    if json {
+        match serde_json::to_string_pretty(&reports) {
+            Ok(text) => println!("{}", text),
+            Err(error) => {
+                eprintln!("Failed to write the report: {}", error);
+                return 2;
+            },
+        }
+    } else {
+        for report in reports.iter() { print(report); }
+    }
+
+    let failed = reports
+        .iter()
+        .flat_map(|report| report.problems.iter())
+        .any(|problem| problem.level == Level::Error);
+
+    if failed { 1 } else { 0 }
+}
+
+fn validate(name: &str) -> Report {
+    let mut report = Report {
+        pack: name.to_owned(),
+        frames: 0,
+        animations: 0,
+        problems: Vec::new(),
+    };
+
+    let pack = match super::open(name) {
+        Ok(pack) => pack,
+        Err(error) => {
+            report.problems.push(problem(Level::Error, error));
+            return report;
+        },
+    };
+
+    report.frames = pack.frames.len();
+    report.animations = pack.animations.len();
+
+    for warning in pack.warnings.iter() {
+        report.problems.push(problem(Level::Warning, warning));
+    }
+
+    check_animations(&pack, &mut report.problems);
+    check_frames(&pack, &mut report.problems);
+    check_shader(&pack, &mut report.problems);
+
+    report
+}
+
+/// Shaders can only be compiled once there's a window to draw in, so this
+/// only catches the ones that are written for a version of GLSL that can't
+/// be used. Either way, the companion is drawn with the built-in shader.
+fn check_shader(pack: &Pack, problems: &mut Vec<Problem>) {
+    let shader = match pack.shader {
+        Some(ref shader) => shader,
+        None => return,
+    };
+    match shader.version() {
+        Some(version) if Shader::VERSIONS.contains(&version) => (),
+        Some(version) => problems.push(problem(Level::Warning, format!(
+            "{} is written for GLSL version {}, but only versions {} can be used, so the built-in shader will be used instead.",
+            shader.file.display(),
+            version,
+            Shader::VERSIONS.join(", ")))),
+        None => problems.push(problem(Level::Warning, format!(
+            "{} doesn't start with a #version line, so the built-in shader will be used instead.",
+            shader.file.display()))),
+    }
+}
+
+fn check_animations(pack: &Pack, problems: &mut Vec<Problem>) {
+    if pack.animations.is_empty() {
+        problems.push(problem(Level::Error, "There aren't any animations."));
+    }
+
+    let outside = |(x, y): (f32, f32)| {
+        x < 0.0 || y < 0.0 || x > pack.frame_size.0 as f32 || y > pack.frame_size.1 as f32
+    };
+
+    if outside(pack.anchor) {
+        problems.push(problem(Level::Warning, format!(
+            "The anchor at {},{} is outside of the frames, so the companion will float or sink.",
+            pack.anchor.0,
+            pack.anchor.1)));
+    }
+
+    for animation in pack.animations.iter() {
+        let mut add = |level, message: String| problems.push(Problem {
+            animation: Some(animation.name.clone()),
+            ..problem(level, message)
+        });
+
+        if animation.frames.is_empty() {
+            add(Level::Error, format!("Animation \"{}\" doesn't have any frames.", animation.name));
+        }
+
+        for frame in animation.frames.iter().filter(|frame| frame.frame >= pack.frames.len()) {
+            add(Level::Error, format!(
+                "Animation \"{}\" shows frame {}, but there are only {}.",
+                animation.name,
+                frame.frame,
+                pack.frames.len()));
+        }
+
+        for (step, frame) in animation.frames.iter().enumerate() {
+            let anchor = match frame.anchor {
+                Some(anchor) if outside(anchor) => anchor,
+                _ => continue,
+            };
+            add(Level::Warning, format!(
+                "Step {} of animation \"{}\" has its anchor at {},{}, which is outside of the frame.",
+                step,
+                animation.name,
+                anchor.0,
+                anchor.1));
+        }
+
+        for (next, _) in animation.next.iter().chain(animation.border.iter()) {
+            if pack.animation(next).is_none() {
+                add(Level::Error, format!(
+                    "Animation \"{}\" can be followed by \"{}\", which doesn't exist.",
+                    animation.name,
+                    next));
+            }
+        }
+    }
+}
+
+fn check_frames(pack: &Pack, problems: &mut Vec<Problem>) {
+    let decoded = assets::decode_sheets(&pack.sheets);
+    let frames = pack.frames
+        .par_iter()
+        // SVGs are drawn at the pack's frame size, so they can only be the
+        // wrong size if their aspect ratio is different.
+        .map(|source| source.decode(&pack.sheets, &decoded, pack.frame_size))
+        .collect::<Vec<_>>();
+
+    // Every frame of a sheet that failed to decode fails the same way, so
+    // only mention each failure once.
+    let mut seen = HashSet::new();
+
+    for (index, frame) in frames.into_iter().enumerate() {
+        let mut add = |level, message: String| problems.push(Problem {
+            frame: Some(index),
+            ..problem(level, message)
+        });
+
+        let frame: DynamicImage = match frame {
+            Ok(frame) => frame,
+            Err(error) => {
+                let message = error.to_string();
+                if seen.insert(message.clone()) { add(Level::Error, message); }
+                continue;
+            },
+        };
+
+        let (width, height) = frame.dimensions();
+
+        if (width, height) != pack.frame_size {
+            add(Level::Warning, format!(
+                "Frame {} is {}x{}, but the pack's frames are {}x{}, so it won't line up with the others.",
+                index,
+                width,
+                height,
+                pack.frame_size.0,
+                pack.frame_size.1));
+        }
+
+        if width > MAX_DIMENSION || height > MAX_DIMENSION {
+            add(Level::Warning, format!(
+                "Frame {} is {}x{}, which is bigger than it needs to be. Try keeping it under {}x{}.",
+                index,
+                width,
+                height,
+                MAX_DIMENSION,
+                MAX_DIMENSION));
+        }
+
+        if frame.pixels().all(|(_, _, pixel)| pixel.0[3] == 0) {
+            add(Level::Warning, format!("Frame {} is fully transparent.", index));
+        }
+    }
+}
+
+fn problem<M: ToString>(level: Level, message: M) -> Problem {
+    Problem {
+        level,
+        message: message.to_string(),
+        frame: None,
+        animation: None,
+    }
+}
+
+fn print(report: &Report) {
+    println!("{}: {} frames, {} animations", report.pack, report.frames, report.animations);
+
+    for problem in report.problems.iter() {
+        let level = match problem.level {
+            Level::Error => "error",
+            Level::Warning => "warning",
+        };
+        println!("  {}: {}", level, problem.message);
+    }
+
+    let errors = report.problems.iter().filter(|problem| problem.level == Level::Error).count();
+    let warnings = report.problems.len() - errors;
+    println!("  {} errors, {} warnings", errors, warnings);
+}
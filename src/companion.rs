@@ -0,0 +1,730 @@
+use glium;
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use glium::Program;
+use glium::texture::SrgbTexture2d;
+
+use crate::assets::{ self, Filter, FrameCache, Frames };
+use crate::behaviour::Behaviour;
+use crate::calendar::{ Calendar, Event };
+use crate::config::CompanionConfig;
+use crate::effects::MAX_PALETTE;
+use crate::measurements::Measurements;
+use crate::misc::Rng;
+use crate::motion::{ Pose, Transform };
+use crate::pack::{ AnimationKind, AssetError, Facing, Pack, Trigger };
+use crate::particles::{ Particles, Placement, MAX_PARTICLES };
+
+/// How fast companions fall when their fall animation doesn't say, in
+/// companion heights per second.
+const FALL_SPEED: f32 = 2.0;
+
+/// How long speech bubbles stay up, in seconds.
+const SPEECH_TIME: f32 = 5.0;
+
+/// A single character walking around the screen.
+#[derive(Debug)]
+pub struct Companion {
+    pub id: usize,
+    pub config: CompanionConfig,
+    pub pack: Rc<Pack>,
+    pub frames: Rc<RefCell<Frames>>,
+    pub behaviour: Behaviour,
+    pub pos: (i32, i32),
+    pub size: (i32, i32),
+    /// How the companion's frames are resized and sampled.
+    pub filter: Filter,
+    pub go_right: bool,
+    /// The names of the accessories the companion is wearing.
+    pub accessories: BTreeSet<String>,
+    /// The events that are on for the companion right now.
+    pub events: Vec<Event>,
+    /// The speech bubble the companion is showing, if any, and how many more
+    /// seconds it stays up for.
+    speech: Option<(Rc<SrgbTexture2d>, f32)>,
+    /// The colours of `Effects::palette`, uploaded for the shaders.
+    pub palette: Option<Rc<SrgbTexture2d>>,
+    /// The pack's own shader, if it has one that compiled.
+    pub shader: Option<Rc<Program>>,
+    /// How far the companion has moved past `pos`, but not by a whole pixel
+    /// yet.
+    remainder: (f32, f32),
+    /// How it's squashing, stretching, bobbing and tilting (see `motion`).
+    pose: Pose,
+    /// The particles it's given off that are still around.
+    particles: Particles,
+    /// Whether it's been petted since the last tick.
+    petted: bool,
+}
+
+impl Companion {
+    /// Returns how big a companion with `config` is drawn.
+    pub fn scaled_size(
+        config: &CompanionConfig,
+        measurements: &Measurements,
+    ) -> (i32, i32) {
+        let (w, h) = measurements.shark_size;
+        (
+            ((w as f32) * config.size).round().max(1.0) as i32,
+            ((h as f32) * config.size).round().max(1.0) as i32,
+        )
+    }
+
+    fn overlaps(&self, x: i32, w: i32) -> bool {
+        x < self.pos.0 + self.size.0 && self.pos.0 < x + w
+    }
+
+    /// Returns what is currently on screen, back to front: the particles
+    /// the companion has given off, the accessories it's wearing behind its
+    /// frame, the frame, the ones it wears on top, and its speech bubble.
+    /// Layers that are still loading are left out until they arrive, and
+    /// ones that failed to load are left out altogether.
+    pub fn sprites(&self) -> Result<Vec<Sprite>, AssetError> {
+        let frame = self.behaviour.frame(&self.pack);
+        let (x, y) = self.draw_pos();
+        let (scale_x, scale_y) = scale(&self.pack, self.size);
+        let mut frames = self.frames.borrow_mut();
+
+        let mut behind = Vec::new();
+        let mut front = Vec::new();
+
+        // Particles are left where they were given off, so they aren't
+        // squashed or tilted along with the companion, and its effects
+        // don't apply to them.
+        for particle in self.particles.list.iter() {
+            let texture = match frames.get(self.pack.frames.len() + particle.layer) {
+                Ok(texture) => texture,
+                Err(..) => continue,
+            };
+            behind.push(Sprite {
+                texture,
+                pos: (particle.pos.0.round() as i32, particle.pos.1.round() as i32),
+                size: particle.size,
+                mirrored: particle.mirrored,
+                effects: false,
+                opacity: particle.opacity(),
+            });
+        }
+
+        let worn = self.pack.accessories
+            .iter()
+            .filter(|accessory| {
+                self.accessories.contains(&accessory.name)
+                    || self.events.iter().any(|event| event.accessories.contains(&accessory.name))
+            });
+
+        for accessory in worn {
+            let (layer, (offset_x, offset_y)) = match accessory.placements.get(frame) {
+                Some(Some(placement)) => *placement,
+                _ => continue,
+            };
+            let (w, h) = self.pack.layers[layer].size;
+            // Mirrored accessories have to end up on the same side of the
+            // mirrored frame.
+            let offset_x = if self.mirrored() { self.pack.frame_size.0 as f32 - offset_x - w as f32 } else { offset_x };
+            let texture = match frames.get(self.pack.frames.len() + layer) {
+                Ok(texture) => texture,
+                Err(..) => continue,
+            };
+            let sprite = Sprite {
+                texture,
+                pos: (
+                    x + (offset_x * scale_x).round() as i32,
+                    y + (offset_y * scale_y).round() as i32,
+                ),
+                size: (
+                    ((w as f32) * scale_x).round().max(1.0) as i32,
+                    ((h as f32) * scale_y).round().max(1.0) as i32,
+                ),
+                mirrored: self.mirrored(),
+                effects: true,
+                opacity: 1.0,
+            };
+            if accessory.behind { behind.push(sprite); } else { front.push(sprite); }
+        }
+
+        behind.push(Sprite {
+            texture: frames.get(frame)?,
+            pos: (x, y),
+            size: self.size,
+            mirrored: self.mirrored(),
+            effects: true,
+            opacity: 1.0,
+        });
+        behind.extend(front);
+
+        // Speech bubbles sit just above the companion, and read the same
+        // whichever way it's heading.
+        if let Some((ref bubble, _)) = self.speech {
+            let size = (bubble.width() as i32, bubble.height() as i32);
+            behind.push(Sprite {
+                texture: bubble.clone(),
+                pos: (x + (self.size.0 - size.0) / 2, y - size.1),
+                size,
+                mirrored: false,
+                effects: false,
+                opacity: 1.0,
+            });
+        }
+
+        Ok(behind)
+    }
+
+    /// Puts on the accessory called `name` if `worn` is true, takes it off if
+    /// it's false, and switches it if it's `None`. Returns whether the pack
+    /// has an accessory by that name.
+    pub fn wear(&mut self, name: &str, worn: Option<bool>) -> bool {
+        if self.pack.accessory(name).is_none() { return false; }
+        if worn.unwrap_or(!self.accessories.contains(name)) {
+            self.accessories.insert(name.to_owned());
+        } else {
+            self.accessories.remove(name);
+        }
+        true
+    }
+
+    /// Pets the companion, which sets off its pack's emitters for being
+    /// petted on the next tick. Windows let clicks through to what's behind
+    /// them, so this is done with the `pet` command (see `control`).
+    pub fn pet(&mut self) {
+        self.petted = true;
+    }
+
+    /// Returns where the frame on screen is, for giving off particles from.
+    fn placement(&self) -> Placement {
+        let (x, y) = self.draw_pos();
+        Placement {
+            pos: (x as f32, y as f32),
+            scale: scale(&self.pack, self.size),
+            mirrored: self.mirrored(),
+            go_right: self.go_right,
+        }
+    }
+
+    /// Returns whether the frames need to be mirrored, because the companion
+    /// is heading the other way from the one they're drawn facing.
+    pub fn mirrored(&self) -> bool {
+        match self.pack.facing {
+            Facing::Left => self.go_right,
+            Facing::Right => !self.go_right,
+            Facing::Either => false,
+        }
+    }
+
+    /// Returns where the top of the companion is when it's standing on the
+    /// ground, which is where its pack's anchor is on the ground.
+    fn ground(&self, measurements: &Measurements) -> i32 {
+        measurements.ground() - feet(&self.pack, self.size)
+    }
+
+    /// Returns where the frame that is currently on screen is drawn. This is
+    /// `pos`, moved so that the frame's anchor lands where the pack's anchor
+    /// is. Mirrored frames are flipped around their anchor, so that the
+    /// companion doesn't jump sideways when it turns around.
+    pub fn draw_pos(&self) -> (i32, i32) {
+        let (scale_x, scale_y) = scale(&self.pack, self.size);
+        let (x, y) = self.pack.anchor;
+        let (frame_x, frame_y) = self.behaviour.anchor(&self.pack);
+        let frame_x = if self.mirrored() { self.pack.frame_size.0 as f32 - frame_x } else { frame_x };
+        (
+            self.pos.0 + ((x - frame_x) * scale_x).round() as i32,
+            self.pos.1 + ((y - frame_y) * scale_y).round() as i32,
+        )
+    }
+
+    /// Returns where the window showing just this companion goes, and how
+    /// big it is, when not in overlay mode. That's the frame on screen, grown
+    /// to cover every accessory on every frame, since they can be put on at
+    /// any time. It's grown by the same amount on both sides, so that it
+    /// doesn't change size when the companion turns around.
+    pub fn window(&self) -> ((i32, i32), (i32, i32)) {
+        let (scale_x, scale_y) = scale(&self.pack, self.size);
+        let (width, height) = (self.pack.frame_size.0 as f32, self.pack.frame_size.1 as f32);
+        let (mut side, mut top, mut bottom) = (0.0f32, 0.0f32, 0.0f32);
+        for accessory in self.pack.accessories.iter() {
+            for &(layer, (x, y)) in accessory.placements.iter().flatten() {
+                let (w, h) = self.pack.layers[layer].size;
+                side = side.max(-x).max(x + w as f32 - width);
+                top = top.max(-y);
+                bottom = bottom.max(y + h as f32 - height);
+            }
+        }
+        let side = (side * scale_x).ceil() as i32;
+        let (top, bottom) = ((top * scale_y).ceil() as i32, (bottom * scale_y).ceil() as i32);
+        let (x, y) = self.draw_pos();
+        ((x - side, y - top), (self.size.0 + side * 2, self.size.1 + top + bottom))
+    }
+
+    /// Returns the transform the companion's frames and accessories are
+    /// drawn with, which squashes and tilts them around its feet.
+    pub fn transform(&self) -> Transform {
+        let (scale_x, scale_y) = scale(&self.pack, self.size);
+        let pivot = (
+            self.pos.0 as f32 + self.pack.anchor.0 * scale_x,
+            self.pos.1 as f32 + self.pack.anchor.1 * scale_y,
+        );
+        self.pose.transform(&self.pack.motion, pivot, self.size.1 as f32)
+    }
+
+    /// Returns where the shadow under the companion is drawn, and how big
+    /// it is. It stays on the ground under the pack's anchor, even while the
+    /// companion is falling.
+    pub fn shadow(&self, measurements: &Measurements) -> ((i32, i32), (i32, i32)) {
+        let x = self.pos.0 + (self.pack.anchor.0 * scale(&self.pack, self.size).0).round() as i32;
+        let size = ((self.size.0 / 2).max(1), (self.size.0 / 10).max(1));
+        ((x - size.0 / 2, measurements.ground() - size.1 / 2), size)
+    }
+
+    /// Moves `pos` by `delta` pixels, keeping track of any fractions of a
+    /// pixel for next time.
+    fn nudge(&mut self, delta: (f32, f32)) {
+        self.remainder.0 += delta.0;
+        self.remainder.1 += delta.1;
+        let whole = (self.remainder.0.trunc(), self.remainder.1.trunc());
+        self.pos.0 += whole.0 as i32;
+        self.pos.1 += whole.1 as i32;
+        self.remainder.0 -= whole.0;
+        self.remainder.1 -= whole.1;
+    }
+}
+
+/// One of the textures that make up how a companion looks, and where on
+/// screen it's drawn.
+#[derive(Debug)]
+pub struct Sprite {
+    pub texture: Rc<SrgbTexture2d>,
+    pub pos: (i32, i32),
+    pub size: (i32, i32),
+    /// Whether the texture needs to be mirrored when it's drawn.
+    pub mirrored: bool,
+    /// Whether the companion's effects apply to it. Speech bubbles and
+    /// particles are drawn as they are.
+    pub effects: bool,
+    /// How opaque it's drawn, from 0 to 1, for particles that fade out.
+    pub opacity: f32,
+}
+
+/// All of the companions that are currently on screen.
+#[derive(Debug)]
+pub struct Companions {
+    cache: FrameCache,
+    calendar: Calendar,
+    list: Vec<Companion>,
+    next_id: usize,
+    /// Whether emitters give off particles. Without overlay mode, the window
+    /// is only as big as the one companion, and particles would drift out of
+    /// it and get cut off, so they're left off.
+    particles: bool,
+}
+
+impl Companions {
+    pub fn new(cache: FrameCache, calendar: Calendar, particles: bool) -> Self {
+        Companions {
+            cache,
+            calendar,
+            list: Vec::new(),
+            next_id: 0,
+            particles,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Companion> {
+        self.list.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Replaces the events from the config file that every companion
+    /// celebrates.
+    pub fn set_events(&mut self, events: Vec<Event>) {
+        self.calendar.events = events;
+    }
+
+    /// Returns the companion with the given id, if there is one.
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Companion> {
+        self.list.iter_mut().find(|companion| companion.id == id)
+    }
+
+    /// Spawns a new companion, and returns its id. If its pack can't be
+    /// loaded, it uses the built-in one instead.
+    ///
+    /// The first companion starts out at the left edge of the screen. The
+    /// rest pick a random free spot on the ground, so that they don't start
+    /// out on top of each other.
+    pub fn spawn(
+        &mut self,
+        config: CompanionConfig,
+        measurements: &Measurements,
+    ) -> usize {
+        let pack = self.cache.pack(&config.pack);
+        let (filter, size) = sizing(&config, &pack, measurements);
+        let frames = self.cache.frames(&pack, size, filter);
+        let mut rng = config.seed.map(Rng::new).unwrap_or_else(Rng::from_time);
+        let particles = Particles::new(Rng::new(rng.next_u64()));
+
+        let mut pos = measurements.spawn_pos(size, feet(&pack, size));
+        let mut go_right = true;
+
+        if !self.list.is_empty() {
+            // Give up after a few tries, in case the screen is simply full.
+            for _ in 0..32 {
+                let x = rng.range(
+                    measurements.area_min_pos.0,
+                    measurements.area_max_pos.0 - size.0);
+                if !self.list.iter().any(|other| other.overlaps(x, size.0)) {
+                    pos.0 = x;
+                    break;
+                }
+            }
+            go_right = rng.next_f32() < 0.5;
+        }
+
+        // Companions wear what their pack puts on them, unless their config
+        // says otherwise.
+        let accessories = match config.accessories {
+            Some(ref names) => {
+                // The built-in pack is standing in for one that didn't load,
+                // so it not having them isn't worth mentioning.
+                if pack.name == config.pack {
+                    for name in names.iter().filter(|name| pack.accessory(name).is_none()) {
+                        eprintln!("{}: There is no accessory called \"{}\".", pack.name, name);
+                    }
+                }
+                names.iter().cloned().collect()
+            },
+            None => pack.accessories
+                .iter()
+                .filter(|accessory| accessory.worn)
+                .map(|accessory| accessory.name.clone())
+                .collect(),
+        };
+
+        if config.effects.palette.len() > MAX_PALETTE {
+            eprintln!(
+                "Palettes can only swap {} colours, so the rest of companion {}'s are left out.",
+                MAX_PALETTE,
+                self.next_id);
+        }
+        let palette = self.cache.palette(&config.effects.palette[..config.effects.palette.len().min(MAX_PALETTE)]);
+        let shader = self.cache.shader(&pack);
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.list.push(Companion {
+            id,
+            config,
+            behaviour: Behaviour::new(&pack, rng),
+            pack,
+            frames,
+            pos,
+            size,
+            filter,
+            go_right,
+            accessories,
+            events: Vec::new(),
+            speech: None,
+            palette,
+            shader,
+            remainder: (0.0, 0.0),
+            pose: Pose::default(),
+            particles,
+            petted: false,
+        });
+
+        id
+    }
+
+    /// Removes the companion with the given id. Returns whether there was one.
+    pub fn despawn(&mut self, id: usize) -> bool {
+        let len = self.list.len();
+        self.list.retain(|companion| companion.id != id);
+        self.cache.prune();
+        self.list.len() != len
+    }
+
+    /// Spawns and removes companions until they match `configs`. Companions
+    /// that are already on screen and still in `configs` are left alone.
+    /// Packs that failed to load before are tried again for the new ones.
+    pub fn sync(&mut self, configs: &[CompanionConfig], measurements: &Measurements) {
+        self.cache.retry();
+        let mut wanted = configs.iter().collect::<Vec<_>>();
+
+        self.list.retain(|companion| {
+            match wanted.iter().position(|config| **config == companion.config) {
+                Some(index) => { wanted.remove(index); true },
+                None => false,
+            }
+        });
+        self.cache.prune();
+
+        for config in wanted {
+            self.spawn(config.clone(), measurements);
+        }
+    }
+
+    /// Resizes every companion for `measurements`, like after the screen
+    /// they're on changed its scale factor. Their frames are loaded again at
+    /// the new size, which draws SVG frames again too, so they stay sharp.
+    pub fn resize(&mut self, measurements: &Measurements) {
+        for companion in self.list.iter_mut() {
+            let (filter, size) = sizing(&companion.config, &companion.pack, measurements);
+            if (filter, size) == (companion.filter, companion.size) { continue; }
+            companion.filter = filter;
+            companion.size = size;
+            companion.frames = self.cache.frames(&companion.pack, size, filter);
+            companion.particles.clear();
+        }
+
+        self.cache.prune();
+    }
+
+    /// Reloads the packs that any of `files` belong to, leaving every
+    /// companion where it is and doing what it was doing. Companions that
+    /// fell back to the built-in pack get their own pack back, if it loads
+    /// now.
+    pub fn reload(&mut self, files: &[PathBuf], measurements: &Measurements) {
+        let reloaded = self.cache.reload(files);
+
+        for companion in self.list.iter_mut() {
+            if companion.pack.name != companion.config.pack {
+                if !assets::changed(&companion.config.pack, files) { continue; }
+                let pack = self.cache.pack(&companion.config.pack);
+                if pack.name != companion.config.pack { continue; }
+                let rng = companion.config.seed.map(Rng::new).unwrap_or_else(Rng::from_time);
+                let (filter, size) = sizing(&companion.config, &pack, measurements);
+                companion.filter = filter;
+                companion.size = size;
+                companion.frames = self.cache.frames(&pack, size, filter);
+                companion.behaviour = Behaviour::new(&pack, rng);
+                companion.shader = self.cache.shader(&pack);
+                companion.particles.clear();
+                companion.pack = pack;
+            } else if let Some(pack) = reloaded.iter().find(|pack| pack.name == companion.pack.name) {
+                // A new filter or frame size means a new set of frames,
+                // rather than reloading the ones that changed.
+                let (filter, size) = sizing(&companion.config, pack, measurements);
+                if (filter, size) != (companion.filter, companion.size) {
+                    companion.filter = filter;
+                    companion.size = size;
+                    companion.frames = self.cache.frames(pack, size, filter);
+                }
+                companion.behaviour.reload(&companion.pack, pack);
+                companion.shader = self.cache.shader(pack);
+                companion.particles.clear();
+                companion.pack = pack.clone();
+            }
+        }
+
+        self.cache.prune();
+    }
+
+    /// Switches companions whose frames failed to load over to the built-in
+    /// pack, so that they don't turn invisible. Returns whether any were.
+    fn fall_back(&mut self, measurements: &Measurements) -> bool {
+        let mut changed = false;
+
+        for companion in self.list.iter_mut() {
+            if companion.pack.name == Pack::BUILTIN { continue; }
+            let error = match companion.frames.borrow_mut().poll() {
+                Some(error) => error.clone(),
+                None => continue,
+            };
+            assets::warn_fallback(&companion.pack.name, &error);
+            let pack = self.cache.pack(Pack::BUILTIN);
+            let rng = companion.config.seed.map(Rng::new).unwrap_or_else(Rng::from_time);
+            let (filter, size) = sizing(&companion.config, &pack, measurements);
+            companion.filter = filter;
+            companion.size = size;
+            companion.frames = self.cache.frames(&pack, size, filter);
+            companion.behaviour = Behaviour::new(&pack, rng);
+            companion.shader = self.cache.shader(&pack);
+            companion.particles.clear();
+            companion.pack = pack;
+            changed = true;
+        }
+
+        if changed { self.cache.prune(); }
+        changed
+    }
+
+    /// Moves time forward by `dt` seconds for every companion, playing their
+    /// animations and moving them around. Companions turn around at the
+    /// edges of the screen or when they'd walk into each other. Returns
+    /// whether anything on screen changed.
+    pub fn update(&mut self, measurements: &Measurements, dt: f32) -> bool {
+        let spans = self.list
+            .iter()
+            .map(|companion| (companion.id, companion.pos.0, companion.size.0))
+            .collect::<Vec<_>>();
+
+        let mut changed = self.fall_back(measurements);
+        let now = self.calendar.now();
+        let mut budget = MAX_PARTICLES.saturating_sub(
+            self.list.iter().map(|companion| companion.particles.list.len()).sum()
+        );
+
+        for companion in self.list.iter_mut() {
+            let pack = companion.pack.clone();
+            let ground = companion.ground(measurements);
+
+            if companion.behaviour.update(&pack, dt) { changed = true; }
+            if companion.frames.borrow_mut().reloaded() { changed = true; }
+
+            // Shaders that change over time need redrawing even while
+            // nothing else does.
+            if companion.shader.is_some() && pack.shader.as_ref().is_some_and(|shader| shader.animated()) {
+                changed = true;
+            }
+
+            // Events play their animation and say their line when they
+            // start, and keep their accessories on for as long as they last.
+            let events = self.calendar.events
+                .iter()
+                .chain(pack.events.iter())
+                .filter(|event| event.active(now))
+                .cloned()
+                .collect::<Vec<Event>>();
+            for event in events.iter() {
+                if companion.events.contains(event) { continue; }
+                if let Some(ref animation) = event.animation {
+                    changed |= companion.behaviour.play_animation(&pack, animation);
+                }
+                if let Some(ref line) = event.say {
+                    companion.speech = self.cache.bubble(line).map(|bubble| (bubble, SPEECH_TIME));
+                    changed = true;
+                }
+            }
+            if events != companion.events {
+                companion.events = events;
+                changed = true;
+            }
+
+            if let Some((_, ref mut remaining)) = companion.speech {
+                *remaining -= dt;
+                if *remaining <= 0.0 {
+                    companion.speech = None;
+                    changed = true;
+                }
+            }
+
+            // Companions above the ground fall back down to it.
+            if companion.pos.1 < ground {
+                changed |= companion.behaviour.play_kind(&pack, AnimationKind::Fall);
+            }
+
+            // Velocities are in pixels of the original frames, so scale them
+            // to the size the companion is drawn at.
+            let scale = scale(&pack, companion.size).1;
+            let (mut dx, mut dy) = companion.behaviour.velocity(&pack);
+            dx *= scale * companion.config.speed * dt;
+            dy *= scale * companion.config.speed * dt;
+            if companion.behaviour.kind(&pack) == AnimationKind::Fall && dy <= 0.0 {
+                dy = FALL_SPEED * (companion.size.1 as f32) * dt;
+            }
+            if !companion.go_right { dx = -dx; }
+
+            let (pos, go_right) = (companion.pos, companion.go_right);
+            companion.nudge((dx, dy));
+
+            let mut landed = false;
+            if companion.pos.1 >= ground {
+                companion.pos.1 = ground;
+                companion.remainder.1 = 0.0;
+                if companion.behaviour.kind(&pack) == AnimationKind::Fall {
+                    companion.behaviour.finish(&pack);
+                    landed = true;
+                    changed = true;
+                }
+            } else if companion.pos.1 < measurements.area_min_pos.1 {
+                companion.pos.1 = measurements.area_min_pos.1;
+            }
+
+            let kind = companion.behaviour.kind(&pack);
+            changed |= companion.pose.update(&pack.motion, kind, (dx, dy), landed, dt);
+
+            // Particles that run out make room for new ones, from this
+            // companion or any other.
+            let alive = companion.particles.list.len();
+            companion.particles.update(dt);
+            budget += alive - companion.particles.list.len();
+
+            let petted = std::mem::replace(&mut companion.petted, false);
+            let placement = companion.placement();
+            let emitters = if self.particles { &pack.emitters[..] } else { &[] };
+            for (index, emitter) in emitters.iter().enumerate() {
+                let count = match emitter.trigger {
+                    Trigger::Moving if kind == AnimationKind::Ground && dx != 0.0 => emitter.rate * dt,
+                    Trigger::Landing if landed => emitter.rate,
+                    Trigger::Petted if petted => emitter.rate,
+                    _ => continue,
+                };
+                companion.particles.emit(&pack, index, count, &placement, &mut budget);
+            }
+            if !companion.particles.list.is_empty() { changed = true; }
+
+            let at_border = if companion.go_right {
+                companion.pos.0 > measurements.area_max_pos.0
+            } else {
+                companion.pos.0 < measurements.area_min_pos.0 - companion.size.0
+            };
+
+            if at_border {
+                companion.go_right = !companion.go_right;
+                companion.behaviour.hit_border(&pack);
+            }
+
+            // Only turn around when heading towards the other companion, so
+            // that two companions that already overlap walk away from each
+            // other instead of getting stuck.
+            let blocked = dx != 0.0 && spans.iter().any(|&(id, x, w)| {
+                id != companion.id
+                    && companion.overlaps(x, w)
+                    && (x > companion.pos.0) == companion.go_right
+            });
+
+            if blocked { companion.go_right = !companion.go_right; }
+
+            if companion.pos != pos || companion.go_right != go_right {
+                changed = true;
+            }
+        }
+
+        changed
+    }
+}
+
+/// Returns the filter a companion with `config` is drawn with, when it's
+/// drawn with `pack`.
+fn filter(config: &CompanionConfig, pack: &Pack) -> Filter {
+    config.filter.or(pack.filter).unwrap_or_default()
+}
+
+/// Returns the filter a companion with `config` resizes `pack`'s frames
+/// with, and the size it draws them at, which pixel art snaps to a whole
+/// multiple of (see `Filter::snap`).
+fn sizing(config: &CompanionConfig, pack: &Pack, measurements: &Measurements) -> (Filter, (i32, i32)) {
+    let filter = filter(config, pack);
+    (filter, filter.snap(pack.frame_size, Companion::scaled_size(config, measurements)))
+}
+
+/// Returns how many pixels on screen one pixel of `pack`'s frames is, when
+/// they're drawn at `size`.
+fn scale(pack: &Pack, size: (i32, i32)) -> (f32, f32) {
+    (
+        (size.0 as f32) / (pack.frame_size.0.max(1) as f32),
+        (size.1 as f32) / (pack.frame_size.1.max(1) as f32),
+    )
+}
+
+/// Returns how far down from the top of a companion drawn with `pack` at
+/// `size` the ground is.
+fn feet(pack: &Pack, size: (i32, i32)) -> i32 {
+    (pack.anchor.1 * scale(pack, size).1).round() as i32
+}
@@ -0,0 +1,181 @@
+use chrono;
+use dirs;
+use serde::Deserialize;
+use toml;
+
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::NaiveDateTime;
+
+use crate::assets::Filter;
+use crate::calendar::{ self, Event };
+use crate::effects::Effects;
+use crate::misc::fatal;
+
+/// Settings that change how desktop sharkie runs, taken from the config file
+/// and the command line.
+///
+/// The config file lives at `config.toml` inside of desktop sharkie's config
+/// directory (see `config_dir`), and looks like this:
+///
+/// ```toml
+/// overlay = true
+///
+/// [[companion]]
+/// pack = "gura"
+///
+/// [[companion]]
+/// pack = "gura"
+/// size = 0.5
+/// speed = 2.0
+/// seed = 1234
+/// accessories = ["hat"]
+/// filter = "nearest"
+///
+/// [companion.effects]
+/// outline = "#ffffff"
+///
+/// [[event]]
+/// name = "birthday"
+/// date = "04-01"
+/// say = "Happy birthday!"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// When set, a single click-through window covers the whole work area
+    /// and the shark is drawn at its position inside of it, instead of moving
+    /// a small window around the screen. Moving the shark then only costs a
+    /// redraw.
+    ///
+    /// This is always turned on when there is more than one companion, since
+    /// they all share the one window.
+    pub overlay: bool,
+    /// When set, the packs in `Pack::dir` are watched for changes, and packs
+    /// that are on screen are reloaded as soon as any of their files change,
+    /// for seeing edits to a pack right away.
+    pub watch: bool,
+    /// The companions to spawn. If this is empty, a single Gura is spawned.
+    #[serde(rename = "companion")]
+    pub companions: Vec<CompanionConfig>,
+    /// Special days that every companion celebrates (see `calendar`).
+    #[serde(rename = "event")]
+    pub events: Vec<Event>,
+    /// Pretends that it's this date and time instead of now, for trying out
+    /// events. This can only be set from the command line, with
+    /// `--date=2030-12-31T23:59`.
+    #[serde(skip)]
+    pub date: Option<NaiveDateTime>,
+}
+
+/// The settings of a single companion.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompanionConfig {
+    /// The name of the pack to draw this companion with.
+    pub pack: String,
+    /// How big this companion is, relative to the default size.
+    pub size: f32,
+    /// How fast this companion moves, relative to the default speed.
+    pub speed: f32,
+    /// Seeds the random choices this companion makes. If this is unset, a
+    /// random seed is used.
+    pub seed: Option<u64>,
+    /// The names of the accessories this companion wears. If this is unset,
+    /// it wears the ones its pack puts on by default.
+    pub accessories: Option<Vec<String>>,
+    /// How the shaders draw this companion (see `effects`).
+    pub effects: Effects,
+    /// How this companion's frames are resized (see `assets::Filter`). If
+    /// this is unset, its pack picks.
+    pub filter: Option<Filter>,
+}
+
+impl Default for CompanionConfig {
+    fn default() -> Self {
+        CompanionConfig {
+            pack: "gura".to_owned(),
+            size: 1.0,
+            speed: 1.0,
+            seed: None,
+            accessories: None,
+            effects: Effects::default(),
+            filter: None,
+        }
+    }
+}
+
+/// Returns the directory desktop sharkie keeps its config in, for example
+/// `~/.config/desktop-sharkie` on Linux.
+pub fn config_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("desktop-sharkie")
+}
+
+/// Returns the directory desktop sharkie keeps its data, like packs, in. For
+/// example `~/.local/share/desktop-sharkie` on Linux.
+pub fn data_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("desktop-sharkie")
+}
+
+/// Returns the directory desktop sharkie caches things it can make again in.
+/// For example `~/.cache/desktop-sharkie` on Linux, which follows
+/// `$XDG_CACHE_HOME`.
+pub fn cache_dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("desktop-sharkie")
+}
+
+/// Returns the path of the config file.
+pub fn config_path() -> PathBuf {
+    config_dir().join("config.toml")
+}
+
+impl Config {
+    /// Reads the config file, if there is one, and then applies any command
+    /// line arguments on top of it.
+    pub fn load() -> Self {
+        let mut config = match Config::from_file() {
+            Ok(config) => config,
+            Err(error) => fatal("Failed to read the config file.", error),
+        };
+
+        for arg in std::env::args().skip(1) {
+            match arg.as_str() {
+                "--overlay" => config.overlay = true,
+                "--watch" => config.watch = true,
+                _ if arg.starts_with("--date=") => {
+                    match calendar::parse_date_time(&arg["--date=".len()..]) {
+                        Some(date) => config.date = Some(date),
+                        None => fatal("Dates look like 2030-12-31T23:59, or just 2030-12-31.", arg),
+                    }
+                },
+                _ => fatal("Unrecognized command line argument.", arg),
+            }
+        }
+
+        if config.companions.is_empty() {
+            config.companions.push(CompanionConfig::default());
+        }
+
+        if config.companions.len() > 1 { config.overlay = true; }
+
+        config
+    }
+
+    /// Reads the config file, without looking at the command line. A missing
+    /// config file is treated as an empty one.
+    pub fn from_file() -> Result<Self, String> {
+        let path = config_path();
+        if !path.exists() { return Ok(Config::default()); }
+        let text = fs::read_to_string(&path)
+            .map_err(|error| format!("{}: {}", path.display(), error))?;
+        toml::from_str(&text)
+            .map_err(|error| format!("{}: {}", path.display(), error))
+    }
+}
@@ -0,0 +1,221 @@
+use glium;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{ self, BufRead };
+use std::path::{ Path, PathBuf };
+use std::thread;
+use std::time::{ Duration, SystemTime };
+
+use glium::glutin::event_loop::EventLoopProxy;
+
+use crate::config::{ self, CompanionConfig };
+use crate::misc::UserEvent;
+use crate::pack::Pack;
+
+/// Checks the config file for changes every second, and sends
+/// `UserEvent::Reload` whenever it does.
+pub fn watch_config(event_loop_proxy: EventLoopProxy<UserEvent>) {
+    thread::spawn(move || {
+        let modified = || fs::metadata(config::config_path())
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let mut last = modified();
+        loop {
+            thread::sleep(Duration::from_secs(1));
+            let current = modified();
+            if current != last {
+                last = current;
+                if event_loop_proxy.send_event(UserEvent::Reload).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+}
+
+/// Checks the files in `Pack::dir` for changes every second, and sends
+/// `UserEvent::PacksChanged` with the ones that changed whenever any did.
+pub fn watch_packs(event_loop_proxy: EventLoopProxy<UserEvent>) {
+    thread::spawn(move || {
+        let mut last = snapshot(&Pack::dir());
+        loop {
+            thread::sleep(Duration::from_secs(1));
+            let current = snapshot(&Pack::dir());
+            if current == last { continue; }
+            let changed = current
+                .iter()
+                .filter(|(path, stamp)| last.get(*path) != Some(stamp))
+                .map(|(path, _)| path)
+                .chain(last.keys().filter(|path| !current.contains_key(*path)))
+                .cloned()
+                .collect();
+            last = current;
+            if event_loop_proxy.send_event(UserEvent::PacksChanged(changed)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Returns when every file in `dir` and the directories inside of it was
+/// last modified, and how big it is. Files that can't be read are left out.
+fn snapshot(dir: &Path) -> BTreeMap<PathBuf, (Option<SystemTime>, u64)> {
+    let mut files = BTreeMap::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(..) => return files,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => files.extend(snapshot(&path)),
+            Ok(metadata) => { files.insert(path, (metadata.modified().ok(), metadata.len())); },
+            Err(..) => (),
+        }
+    }
+    files
+}
+
+/// Reads commands from stdin, one per line:
+///
+/// - `add [pack] [size] [speed] [seed]` spawns a new companion.
+/// - `remove <id>` removes the companion with the given id.
+/// - `accessory <id> <name> [on|off]` puts on or takes off one of the
+///   companion's accessories, or switches it if neither is given.
+/// - `pet <id>` pets the companion with the given id.
+///
+/// Companions added this way are forgotten when the config file is reloaded.
+pub fn listen_for_commands(event_loop_proxy: EventLoopProxy<UserEvent>) {
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(..) => break,
+            };
+            let event = match parse_command(&line) {
+                Ok(Some(event)) => event,
+                Ok(None) => continue,
+                Err(error) => {
+                    eprintln!("{}", error);
+                    continue;
+                },
+            };
+            if event_loop_proxy.send_event(event).is_err() { break; }
+        }
+    });
+}
+
+fn parse_command(line: &str) -> Result<Option<UserEvent>, String> {
+    let mut words = line.split_whitespace();
+    match words.next() {
+        Some("add") => {
+            let mut companion = CompanionConfig::default();
+            if let Some(pack) = words.next() {
+                companion.pack = pack.to_owned();
+            }
+            if let Some(size) = words.next() {
+                companion.size = size.parse()
+                    .map_err(|_| format!("Invalid size: {}", size))?;
+            }
+            if let Some(speed) = words.next() {
+                companion.speed = speed.parse()
+                    .map_err(|_| format!("Invalid speed: {}", speed))?;
+            }
+            if let Some(seed) = words.next() {
+                companion.seed = Some(seed.parse()
+                    .map_err(|_| format!("Invalid seed: {}", seed))?);
+            }
+            Ok(Some(UserEvent::Spawn(companion)))
+        },
+        Some("remove") => {
+            let id = words.next()
+                .ok_or_else(|| "Usage: remove <id>".to_owned())?;
+            let id = id.parse()
+                .map_err(|_| format!("Invalid id: {}", id))?;
+            Ok(Some(UserEvent::Despawn(id)))
+        },
+        Some("accessory") => {
+            let usage = || "Usage: accessory <id> <name> [on|off]".to_owned();
+            let id = words.next().ok_or_else(usage)?;
+            let id = id.parse()
+                .map_err(|_| format!("Invalid id: {}", id))?;
+            let name = words.next().ok_or_else(usage)?;
+            let worn = match words.next() {
+                Some("on") => Some(true),
+                Some("off") => Some(false),
+                Some(..) => return Err(usage()),
+                None => None,
+            };
+            Ok(Some(UserEvent::Wear(id, name.to_owned(), worn)))
+        },
+        Some("pet") => {
+            let id = words.next()
+                .ok_or_else(|| "Usage: pet <id>".to_owned())?;
+            let id = id.parse()
+                .map_err(|_| format!("Invalid id: {}", id))?;
+            Ok(Some(UserEvent::Pet(id)))
+        },
+        Some(command) => Err(format!("Unknown command: {}", command)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        match parse_command("add") {
+            Ok(Some(UserEvent::Spawn(companion))) => assert_eq!(companion, CompanionConfig::default()),
+            other => panic!("{:?}", other),
+        }
+        match parse_command("  add  mascot 1.5 2 42 ") {
+            Ok(Some(UserEvent::Spawn(companion))) => {
+                assert_eq!(companion.pack, "mascot");
+                assert_eq!((companion.size, companion.speed, companion.seed), (1.5, 2.0, Some(42)));
+            },
+            other => panic!("{:?}", other),
+        }
+        match parse_command("remove 3") {
+            Ok(Some(UserEvent::Despawn(3))) => (),
+            other => panic!("{:?}", other),
+        }
+        match parse_command("accessory 0 party-hat") {
+            Ok(Some(UserEvent::Wear(0, name, None))) => assert_eq!(name, "party-hat"),
+            other => panic!("{:?}", other),
+        }
+        match parse_command("accessory 1 party-hat off") {
+            Ok(Some(UserEvent::Wear(1, _, Some(false)))) => (),
+            other => panic!("{:?}", other),
+        }
+        match parse_command("pet 2") {
+            Ok(Some(UserEvent::Pet(2))) => (),
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(parse_command(""), Ok(None)));
+        assert!(matches!(parse_command("   "), Ok(None)));
+    }
+
+    #[test]
+    fn refuses_malformed_commands() {
+        let error = |line: &str| match parse_command(line) {
+            Err(error) => error,
+            other => panic!("{:?} parsed as {:?}", line, other),
+        };
+        assert_eq!(error("jump 1"), "Unknown command: jump");
+        assert_eq!(error("Add"), "Unknown command: Add");
+        assert_eq!(error("remove"), "Usage: remove <id>");
+        assert_eq!(error("remove gura"), "Invalid id: gura");
+        assert_eq!(error("remove -1"), "Invalid id: -1");
+        assert_eq!(error("pet"), "Usage: pet <id>");
+        assert_eq!(error("accessory"), "Usage: accessory <id> <name> [on|off]");
+        assert_eq!(error("accessory 1"), "Usage: accessory <id> <name> [on|off]");
+        assert_eq!(error("accessory x hat"), "Invalid id: x");
+        assert_eq!(error("accessory 1 hat maybe"), "Usage: accessory <id> <name> [on|off]");
+        assert_eq!(error("add gura big"), "Invalid size: big");
+        assert_eq!(error("add gura 1 fast"), "Invalid speed: fast");
+        assert_eq!(error("add gura 1 1 -5"), "Invalid seed: -5");
+    }
+}
@@ -0,0 +1,122 @@
+//! Effects the shaders can draw companions with, like an outline that keeps
+//! them visible on busy wallpapers.
+//!
+//! Each companion has its own, in its config:
+//!
+//! ```toml
+//! [[companion]]
+//! pack = "gura"
+//!
+//! [companion.effects]
+//! outline = "#ffffff"
+//! outline_width = 2.0
+//! shadow = 0.4
+//! tint = "#ffe0e0"
+//! palette = [ ["#3a6fd8", "#d83a6f"], ["#a7c5ff", "#ffa7c5"] ]
+//! ```
+
+use serde::Deserialize;
+
+use std::convert::TryFrom;
+
+use crate::assets::to_linear;
+
+/// The most colours a palette swap can have, which is as many as the
+/// shaders look through.
+pub const MAX_PALETTE: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Effects {
+    /// Draws a line of this colour around the companion.
+    pub outline: Option<Color>,
+    /// How thick the outline is, in pixels on screen.
+    pub outline_width: f32,
+    /// How dark the soft shadow on the ground under the companion is, from
+    /// 0 for none to 1 for black.
+    pub shadow: f32,
+    /// Multiplies the companion's colours by this one, to tint it.
+    pub tint: Option<Color>,
+    /// Pairs of colours, where the first one is drawn as the second one
+    /// instead, for recolouring a pack without editing it.
+    pub palette: Vec<(Color, Color)>,
+}
+
+/// A colour, written like `"#ff8000"`, or `"#ff800080"` with an alpha
+/// channel.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color(pub [u8; 4]);
+
+/// The values of the shaders' uniforms for effects, other than the palette
+/// itself, which is a texture.
+#[derive(Debug, Copy, Clone)]
+pub struct Uniforms {
+    pub tint: [f32; 4],
+    pub outline_color: [f32; 4],
+    /// How thick the outline is, as a fraction of the sprite's size.
+    pub outline_size: [f32; 2],
+    pub palette_size: i32,
+}
+
+impl Default for Effects {
+    fn default() -> Self {
+        Effects {
+            outline: None,
+            outline_width: 2.0,
+            shadow: 0.0,
+            tint: None,
+            palette: Vec::new(),
+        }
+    }
+}
+
+impl Effects {
+    /// Returns the uniforms for drawing a sprite of `size` with these
+    /// effects.
+    pub fn uniforms(&self, size: (i32, i32)) -> Uniforms {
+        Uniforms {
+            tint: self.tint.map_or([ 1.0; 4 ], Color::linear),
+            outline_color: self.outline.map_or([ 0.0; 4 ], Color::linear),
+            outline_size: [
+                self.outline_width / size.0.max(1) as f32,
+                self.outline_width / size.1.max(1) as f32,
+            ],
+            palette_size: self.palette.len().min(MAX_PALETTE) as i32,
+        }
+    }
+}
+
+impl Uniforms {
+    /// Returns the uniforms that draw a sprite as it is.
+    pub fn none() -> Self {
+        Uniforms {
+            tint: [ 1.0; 4 ],
+            outline_color: [ 0.0; 4 ],
+            outline_size: [ 0.0; 2 ],
+            palette_size: 0,
+        }
+    }
+}
+
+impl Color {
+    /// Returns the colour in linear light, which is what the shaders work
+    /// in, since frames are uploaded as sRGB textures.
+    pub fn linear(self) -> [f32; 4] {
+        let [ r, g, b, a ] = self.0;
+        [ to_linear(r), to_linear(g), to_linear(b), a as f32 / 255.0 ]
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        let invalid = || format!("\"{}\" isn't a colour. Use \"#rrggbb\" or \"#rrggbbaa\".", text);
+        let hex = text.trim().strip_prefix('#').ok_or_else(invalid)?;
+        if (hex.len() != 6 && hex.len() != 8) || !hex.is_ascii() { return Err(invalid()); }
+        let channel = |index: usize| u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).map_err(|_| invalid());
+        let alpha = if hex.len() == 8 { channel(3)? } else { 255 };
+        Ok(Color([ channel(0)?, channel(1)?, channel(2)?, alpha ]))
+    }
+}
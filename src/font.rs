@@ -0,0 +1,102 @@
+//! A tiny 5x7 pixel font, for labelling previews and drawing speech bubbles
+//! without shipping a font file. Letters are drawn in upper case, and characters it doesn't have are
+//! drawn as `?`.
+
+use image;
+
+use image::{ Rgba, RgbaImage };
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+
+/// Returns the rows of `c`, top to bottom, with the leftmost pixel in the
+/// highest of the five bits.
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        'A' => [ 0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11 ],
+        'B' => [ 0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E ],
+        'C' => [ 0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E ],
+        'D' => [ 0x1E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1E ],
+        'E' => [ 0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F ],
+        'F' => [ 0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10 ],
+        'G' => [ 0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F ],
+        'H' => [ 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11 ],
+        'I' => [ 0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E ],
+        'J' => [ 0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C ],
+        'K' => [ 0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11 ],
+        'L' => [ 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F ],
+        'M' => [ 0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11 ],
+        'N' => [ 0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11 ],
+        'O' => [ 0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E ],
+        'P' => [ 0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10 ],
+        'Q' => [ 0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D ],
+        'R' => [ 0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11 ],
+        'S' => [ 0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E ],
+        'T' => [ 0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04 ],
+        'U' => [ 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E ],
+        'V' => [ 0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04 ],
+        'W' => [ 0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A ],
+        'X' => [ 0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11 ],
+        'Y' => [ 0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04 ],
+        'Z' => [ 0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F ],
+        '0' => [ 0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E ],
+        '1' => [ 0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E ],
+        '2' => [ 0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F ],
+        '3' => [ 0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E ],
+        '4' => [ 0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02 ],
+        '5' => [ 0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E ],
+        '6' => [ 0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E ],
+        '7' => [ 0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08 ],
+        '8' => [ 0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E ],
+        '9' => [ 0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C ],
+        ' ' => [ 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00 ],
+        '-' => [ 0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00 ],
+        '_' => [ 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F ],
+        '.' => [ 0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C ],
+        ',' => [ 0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08 ],
+        ':' => [ 0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00 ],
+        '#' => [ 0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A ],
+        '/' => [ 0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00 ],
+        '(' => [ 0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02 ],
+        ')' => [ 0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08 ],
+        '+' => [ 0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00 ],
+        '=' => [ 0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00 ],
+        '!' => [ 0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04 ],
+        '\'' => [ 0x04, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00 ],
+        '"' => [ 0x0A, 0x0A, 0x00, 0x00, 0x00, 0x00, 0x00 ],
+        _ => [ 0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04 ],
+    }
+}
+
+/// Returns how tall text is when drawn at `scale`.
+pub fn height(scale: u32) -> u32 {
+    GLYPH_HEIGHT * scale
+}
+
+/// Returns how wide `text` is when drawn at `scale`.
+pub fn width(text: &str, scale: u32) -> u32 {
+    let count = text.chars().count() as u32;
+    if count == 0 { 0 } else { (count * (GLYPH_WIDTH + 1) - 1) * scale }
+}
+
+/// Draws `text` with its top left corner at `pos`, with each pixel of the
+/// font drawn as a `scale` by `scale` square. Anything that doesn't fit is
+/// cut off.
+pub fn draw(image: &mut RgbaImage, text: &str, pos: (u32, u32), scale: u32, color: Rgba<u8>) {
+    for (index, c) in text.chars().enumerate() {
+        let left = pos.0 + index as u32 * (GLYPH_WIDTH + 1) * scale;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (0x10 >> column) == 0 { continue; }
+                let (x, y) = (left + column * scale, pos.1 + row as u32 * scale);
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        if x + dx < image.width() && y + dy < image.height() {
+                            image.put_pixel(x + dx, y + dy, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
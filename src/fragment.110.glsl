@@ -5,9 +5,68 @@
 
 uniform sampler2D tex;
 
+// Effects (see effects.rs). Each of these does nothing at its default.
+uniform vec4 tint;
+uniform vec4 outline_color;
+uniform vec2 outline_size;
+uniform sampler2D palette;
+uniform int palette_size;
+uniform float shadow;
+
 varying vec2 v_tex_coords;
 
-void main() {
-	gl_FragColor = texture2D(tex, v_tex_coords);
+// Samples tex, with everything outside of it transparent.
+vec4 inside(vec2 coords) {
+	if (coords.x < 0.0 || coords.y < 0.0 || coords.x > 1.0 || coords.y > 1.0) {
+		return vec4(0.0);
+	}
+	return texture2D(tex, coords);
 }
 
+void main() {
+	// Shadows are a soft ellipse, without a texture.
+	if (shadow > 0.0) {
+		float reach = length(v_tex_coords * 2.0 - 1.0);
+		gl_FragColor = vec4(0.0, 0.0, 0.0, shadow * (1.0 - smoothstep(0.2, 1.0, reach)));
+		return;
+	}
+
+	vec4 color = texture2D(tex, v_tex_coords);
+
+	// The palette is a row of colours to replace, over a row of what to
+	// replace them with. Colours that are close to one get moved by as much
+	// as it does, so that shading around it is kept. Frames have
+	// premultiplied alpha, so it's taken out to compare them.
+	if (color.a > 0.0) {
+		vec3 straight = color.rgb / color.a;
+		for (int i = 0; i < 16; i++) {
+			if (i >= palette_size) break;
+			float x = (float(i) + 0.5) / float(palette_size);
+			vec3 from = texture2D(palette, vec2(x, 0.75)).rgb;
+			vec3 to = texture2D(palette, vec2(x, 0.25)).rgb;
+			if (distance(straight, from) < 0.08) {
+				color.rgb = clamp(straight + to - from, 0.0, 1.0) * color.a;
+				break;
+			}
+		}
+	}
+
+	color.rgb *= tint.rgb * tint.a;
+	color.a *= tint.a;
+
+	// The outline shows through wherever the frame isn't fully opaque, as
+	// strongly as the most opaque pixel around it.
+	if (outline_color.a > 0.0 && color.a < 1.0) {
+		float around = 0.0;
+		for (int i = 0; i < 8; i++) {
+			float angle = float(i) * 0.785398;
+			vec2 offset = vec2(cos(angle), sin(angle)) * outline_size;
+			around = max(around, inside(v_tex_coords + offset).a);
+			around = max(around, inside(v_tex_coords + offset * 0.5).a);
+		}
+		float alpha = outline_color.a * around;
+		color += vec4(outline_color.rgb * alpha, alpha) * (1.0 - color.a);
+	}
+
+	gl_FragColor = color;
+}
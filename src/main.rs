@@ -4,30 +4,49 @@ use failure;
 use glium;
 
 pub mod assets;
+pub mod behaviour;
+pub mod calendar;
+pub mod commands;
+pub mod companion;
+pub mod config;
+pub mod control;
+pub mod effects;
+pub mod font;
 pub mod measurements;
 pub mod misc;
+pub mod motion;
+pub mod pack;
+pub mod particles;
 pub mod platform;
 
-use std::sync::Arc;
-use std::sync::atomic::{ AtomicBool, Ordering };
 use std::thread;
-use std::time::Duration;
+use std::time::{ Duration, Instant };
 
 use failure::Error;
-use glium::draw_parameters::{ DrawParameters, Blend };
+use glium::draw_parameters::{ Blend, BlendingFunction, DrawParameters, LinearBlendingFactor };
 use glium::glutin::ContextBuilder;
 use glium::glutin::dpi::{ PhysicalSize, PhysicalPosition };
 use glium::glutin::event::{ Event, WindowEvent };
 use glium::glutin::event_loop::{ ControlFlow, EventLoop };
 use glium::glutin::window::WindowBuilder;
 use glium::index::PrimitiveType;
+use glium::uniforms::{ MagnifySamplerFilter, MinifySamplerFilter, Sampler };
 use glium::{ Display, program, Surface };
 
-use assets::Frames;
+use assets::{ shader, FrameCache };
+use calendar::{ Calendar, Clock, LocalClock, SetClock };
+use companion::{ Companion, Companions };
+use config::Config;
+use effects::Uniforms;
 use measurements::Measurements;
 use misc::{ fatal, UserEvent };
 
 fn main() -> Result<(), Error> {
+    // Subcommands like `validate` run without opening a window.
+    if let Some(status) = commands::run() { std::process::exit(status); }
+
+    let config = Config::load();
+
     // Create the event loop. This is what takes in events like keypresses and
     // clicks from the operating system.
     let event_loop = EventLoop::<UserEvent>::with_user_event();
@@ -41,6 +60,18 @@ fn main() -> Result<(), Error> {
     // Create a "display". A display is an abstraction provided by the glium
     // crate that lets us easily render to an OpenGL canvas without all the
     // headaches associated with it.
+    //
+    // In overlay mode, the window covers the whole area and stays put, and
+    // the companions are drawn at their positions inside of it. Otherwise,
+    // the window is the size of the one companion and gets moved around
+    // instead.
+    let (window_pos, window_size) = if config.overlay {
+        (measurements.area_min_pos, measurements.area_size)
+    } else {
+        let size = Companion::scaled_size(&config.companions[0], &measurements);
+        (measurements.spawn_pos(size, size.1), size)
+    };
+
     let display = {
         let window_builder = WindowBuilder::new()
             .with_title("a")
@@ -49,8 +80,8 @@ fn main() -> Result<(), Error> {
             .with_always_on_top(true)
             .with_decorations(false)
             .with_inner_size(PhysicalSize {
-                width: measurements.shark_size.0,
-                height: measurements.shark_size.1,
+                width: window_size.0,
+                height: window_size.1,
             });
 
         let context_builder = ContextBuilder::new()
@@ -65,8 +96,8 @@ fn main() -> Result<(), Error> {
 
     // Move the window to where it should be.
     display.gl_window().window().set_outer_position(PhysicalPosition {
-        x: measurements.shark_pos.0,
-        y: measurements.shark_pos.1,
+        x: window_pos.0,
+        y: window_pos.1,
     });
     
     // If you're on windows, theres a few more steps to do. Namely, hide the
@@ -78,6 +109,11 @@ fn main() -> Result<(), Error> {
         platform::windows::configure_tray();
     }
 
+    // On Linux, we only need to make clicks pass through the window.
+    #[cfg(platform_unix)] {
+        platform::unix::configure_window(&display);
+    }
+
     let vertex_buffer = {
         #[repr(C)]
         #[derive(Debug, Copy, Clone)]
@@ -118,40 +154,44 @@ fn main() -> Result<(), Error> {
             fragment: include_str!("fragment.100.glsl"),
         })?;
     
-    // This stores whether or not the frames are done loading.
+    // This is sent to as soon as the first frame is done loading, so that
+    // the companions can start moving while the rest of their frames load.
+    let (ready, ready_receiver) = crossbeam_channel::unbounded::<()>();
+
+    // Spawn the companions. Each of them has a handle to its loaded frames.
+    // Note that they're not actually loaded yet. Frames are loaded roughly
+    // in order, and until one arrives, the Frames type hands out the closest
+    // one before it that has, so drawing never has to wait.
+    // Companions that use the same pack at the same size share their frames.
+    // Display is passed so that the frames can be uploaded to the GPU.
     //
-    // Arc<AtomicBool> allows us to create multiple references to the same
-    // value that all update when one of them is changed.
-    let ready = Arc::new(AtomicBool::new(false));
-
-    // Create a handle to the loaded frames. Note that they're not actually
-    // loaded yet, but this Frames type blocks until the next frame has fully
-    // loaded when a frame is requested.
-    // Display is passed so that it can resize the images to fit the window.
-    let mut frames = Frames::new(&display, ready.clone());
- 
-    let mut go_right = true;
-
-    // On Windows, transparent windows are repainted whenever the content
-    // below them changes. So, when the window is moved, the content below it
-    // is different. Since we move the window at twice the speed of the frames,
-    // we need to keep track of the current frame number, so that we can check
-    // if this is an even frame when repainting the window.
-    #[allow(unused_mut)] let mut frame_count: usize = 0;
-    
-    // Start up a thread that moves the window periodically.
+    // They celebrate events by the local clock, unless --date set it to
+    // some other time.
+    let clock: Box<dyn Clock> = match config.date {
+        Some(date) => Box::new(SetClock::new(date)),
+        None => Box::new(LocalClock),
+    };
+    let calendar = Calendar::new(clock, config.events.clone());
+    let mut companions = Companions::new(FrameCache::new(&display, ready.clone()), calendar, config.overlay);
+
+    // Packs that can't be loaded are replaced by the built-in one, so this
+    // always gets something on screen.
+    for companion in config.companions.iter() {
+        companions.spawn(companion.clone(), &measurements);
+    }
+    if !config.overlay { fit_window(&display, &companions); }
+
+    // Start up a thread that periodically tells the companions to move and
+    // animate. Frames are timed by how long has actually passed between
+    // ticks, so this doesn't need to be exact.
     {
         let event_loop_proxy = event_loop.create_proxy();
-        let ready = ready.clone();
         thread::spawn(move || {
-            // Wait for the assets to be ready.
-            loop {
-                if ready.load(Ordering::Relaxed) { break; }
-                thread::sleep(Duration::from_millis(100));
-            }
-            // Send an event to move the window every 50ms.
+            // Wait for the first frame to be ready.
+            let _ = ready_receiver.recv();
+            // Send an event to tick every 50ms.
             loop {
-                match event_loop_proxy.send_event(UserEvent::Move) {
+                match event_loop_proxy.send_event(UserEvent::Tick) {
                     Ok(()) => (),
                     Err(error) => fatal("An unexpected runtime error occured.", format!("{}", error)),
                 }
@@ -160,86 +200,214 @@ fn main() -> Result<(), Error> {
         });
     }
 
-    // See the comment above where `frame_count` is defined.
-    #[cfg(not(platform_windows))] {
-        let event_loop_proxy = event_loop.create_proxy();
-        let ready = ready.clone();
-        thread::spawn(move || {
-            // Wait for the assets to be ready.
-            loop {
-                if ready.load(Ordering::Relaxed) { break; }
-                thread::sleep(Duration::from_millis(100));
-            }
-            // Send an event to advance the frame every 100ms.
-            loop {
-                match event_loop_proxy.send_event(UserEvent::Frame) {
-                    Ok(()) => (),
-                    Err(error) => fatal("An unexpected runtime error occured.", format!("{}", error)),
-                }
-                thread::sleep(Duration::from_millis(100));
-            }
-        });
-    }
+    let mut last_tick = Instant::now();
+
+    // Pack shaders are given how long we've been running, for effects that
+    // move on their own.
+    let started = Instant::now();
 
-    event_loop.run(move |event, _, control_flow| {
+    // Let companions be added and removed while we're running, either by
+    // editing the config file or by typing commands into stdin.
+    control::watch_config(event_loop.create_proxy());
+    control::listen_for_commands(event_loop.create_proxy());
+    if config.watch { control::watch_packs(event_loop.create_proxy()); }
+
+    event_loop.run(move |event, window_target, control_flow| {
         *control_flow = ControlFlow::Wait;
         match event {
-            Event::UserEvent(UserEvent::Move) => {
-                if go_right {
-                    measurements.shark_pos.0 += 3;
-                    display.gl_window().window().set_outer_position(PhysicalPosition {
-                        x: measurements.shark_pos.0,
-                        y: measurements.shark_pos.1,
-                    });
-
-                    let bound = measurements.area_max_pos.0;
-                    
-                    if measurements.shark_pos.0 > bound { go_right = false; }
-                } else {
-                    measurements.shark_pos.0 -= 3; 
-                    display.gl_window().window().set_outer_position(PhysicalPosition {
-                        x: measurements.shark_pos.0,
-                        y: measurements.shark_pos.1,
-                    });
-
-                    let bound = measurements.area_min_pos.0 - measurements.shark_size.0;
-                    
-                    if measurements.shark_pos.0 < bound { go_right = true; }
+            Event::UserEvent(UserEvent::Tick) => {
+                // Don't let the companions teleport if we weren't ticked for a
+                // while, like after the computer wakes up from sleeping.
+                let now = Instant::now();
+                let dt = now.duration_since(last_tick).as_secs_f32().min(0.25);
+                last_tick = now;
+
+                let changed = companions.update(&measurements, dt);
+
+                if !config.overlay {
+                    if let Some(companion) = companions.iter().next() {
+                        let ((x, y), _) = companion.window();
+                        display.gl_window().window().set_outer_position(PhysicalPosition { x, y });
+                    }
+                }
+
+                if changed { display.gl_window().window().request_redraw(); }
+            },
+            Event::UserEvent(UserEvent::Spawn(companion)) => {
+                // Without overlay mode, there's only room for one companion
+                // in the window.
+                if !config.overlay && !companions.is_empty() {
+                    eprintln!("Running more than one companion requires --overlay.");
+                    return;
+                }
+                let id = companions.spawn(companion, &measurements);
+                if !config.overlay { fit_window(&display, &companions); }
+                println!("Spawned companion {}.", id);
+            },
+            Event::UserEvent(UserEvent::Despawn(id)) => {
+                if !companions.despawn(id) {
+                    eprintln!("There is no companion {}.", id);
+                }
+                display.gl_window().window().request_redraw();
+            },
+            Event::UserEvent(UserEvent::Wear(id, name, worn)) => {
+                match companions.get_mut(id) {
+                    Some(companion) => if !companion.wear(&name, worn) {
+                        eprintln!("Companion {} doesn't have an accessory called \"{}\".", id, name);
+                    },
+                    None => eprintln!("There is no companion {}.", id),
+                }
+                display.gl_window().window().request_redraw();
+            },
+            Event::UserEvent(UserEvent::Pet(id)) => {
+                match companions.get_mut(id) {
+                    Some(companion) => companion.pet(),
+                    None => eprintln!("There is no companion {}.", id),
+                }
+                display.gl_window().window().request_redraw();
+            },
+            Event::UserEvent(UserEvent::Reload) => {
+                let (mut wanted, events) = match Config::from_file() {
+                    Ok(wanted) => (wanted.companions, wanted.events),
+                    Err(error) => {
+                        eprintln!("Failed to reload the config file: {}", error);
+                        return;
+                    },
+                };
+                companions.set_events(events);
+                if wanted.is_empty() { wanted.push(Default::default()); }
+                if !config.overlay && wanted.len() > 1 {
+                    eprintln!("Running more than one companion requires --overlay.");
+                    wanted.truncate(1);
                 }
+                companions.sync(&wanted, &measurements);
+                if !config.overlay { fit_window(&display, &companions); }
+                display.gl_window().window().request_redraw();
             },
-            Event::UserEvent(UserEvent::Frame) => {
+            Event::UserEvent(UserEvent::PacksChanged(files)) => {
+                companions.reload(&files, &measurements);
+                if !config.overlay { fit_window(&display, &companions); }
                 display.gl_window().window().request_redraw();
             },
             Event::RedrawRequested(..) => {
-                // See the comment above where `frame_count` is defined.
-                if cfg!(not(platform_windows)) || frame_count % 2 == 0 {
-                    let frame = frames.next().unwrap();
-                    let uniform = glium::uniform! {
-                        matrix: [
-                            [ 1.0, 0.0, 0.0, 0.0 ],
-                            [ 0.0, 1.0, 0.0, 0.0 ],
-                            [ 0.0, 0.0, 1.0, 0.0 ],
-                            [ 0.0, 0.0, 0.0, 1.0f32 ],
-                        ],
-                        tex: &*frame,
+                // On Windows, transparent windows are repainted whenever the
+                // content below them changes, like when the window is moved.
+                // Frames used to advance on repaints, so only every other one
+                // was drawn. Animations are timed by the ticks now, so extra
+                // repaints just draw the same frame again.
+                let mut target = display.draw();
+                target.clear_color(0.0, 0.0, 0.0, 0.0);
+                let time = started.elapsed().as_secs_f32();
+                for companion in companions.iter() {
+                    // Frames that failed to load are left out until the
+                    // next tick switches the companion to the built-in pack.
+                    let sprites = match companion.sprites() {
+                        Ok(sprites) => sprites,
+                        Err(..) => continue,
                     };
-                    let mut display = display.draw();
-                    display.clear_color(0.0, 0.0, 0.0, 0.0);
-                    let _ = display.draw(
-                        &vertex_buffer,
-                        &index_buffer,
-                        &program,
-                        &uniform,
-                        &DrawParameters {
-                            blend: Blend::alpha_blending(),
-                            ..Default::default()
-                        });
-                    let _ = display.finish();
-                }
-                #[cfg(platform_windows)] {
-                    frame_count += 1;
-                    if frame_count == usize::MAX { frame_count = 0; }
+                    // Without overlay mode, the window is wherever the
+                    // companion is.
+                    let window = if config.overlay {
+                        (measurements.area_min_pos, measurements.area_size)
+                    } else {
+                        companion.window()
+                    };
+                    // Frames have premultiplied alpha (see
+                    // `assets::resize`), and so does what the shaders draw.
+                    let premultiplied = BlendingFunction::Addition {
+                        source: LinearBlendingFactor::One,
+                        destination: LinearBlendingFactor::OneMinusSourceAlpha,
+                    };
+                    let parameters = DrawParameters {
+                        blend: Blend { color: premultiplied, alpha: premultiplied, ..Default::default() },
+                        ..Default::default()
+                    };
+                    let effects = &companion.config.effects;
+                    let direction = if companion.go_right { 1.0f32 } else { -1.0f32 };
+                    let state = shader::state(companion.behaviour.kind(&companion.pack));
+                    let transform = companion.transform();
+
+                    // The shadow goes under everything else. It doesn't use
+                    // a texture, but one still has to be bound.
+                    if effects.shadow > 0.0 {
+                        let (pos, size) = companion.shadow(&measurements);
+                        let texture = &*sprites[0].texture;
+                        let uniform = glium::uniform! {
+                            matrix: measurements::matrix(pos, size, window),
+                            tex: texture,
+                            tint: [ 1.0f32; 4 ],
+                            outline_color: [ 0.0f32; 4 ],
+                            outline_size: [ 0.0f32; 2 ],
+                            palette: texture,
+                            palette_size: 0,
+                            shadow: effects.shadow.min(1.0),
+                        };
+                        let _ = target.draw(&vertex_buffer, &index_buffer, &program, &uniform, &parameters);
+                    }
+
+                    for sprite in sprites.iter() {
+                        let mut matrix = measurements::matrix(sprite.pos, sprite.size, window);
+                        // Flipping the X axis mirrors the quad around its
+                        // center.
+                        if sprite.mirrored { matrix[0][0] = -matrix[0][0]; }
+                        // What the companion wears squashes and tilts along
+                        // with it, but its speech bubble stays put.
+                        if sprite.effects { matrix = transform.apply(matrix, window); }
+                        let mut values = if sprite.effects { effects.uniforms(sprite.size) } else { Uniforms::none() };
+                        values.tint[3] *= sprite.opacity;
+                        // Palette colours have to be looked up exactly, so
+                        // they aren't blended with their neighbours.
+                        let palette = companion.palette.as_deref().unwrap_or(&*sprite.texture)
+                            .sampled()
+                            .magnify_filter(MagnifySamplerFilter::Nearest)
+                            .minify_filter(MinifySamplerFilter::Nearest);
+                        // The pack's shader draws the companion and what it
+                        // wears, but speech bubbles always look the same.
+                        let program = match companion.shader {
+                            Some(ref shader) if sprite.effects => &**shader,
+                            _ => &program,
+                        };
+                        let uniform = glium::uniform! {
+                            matrix: matrix,
+                            tex: Sampler(&*sprite.texture, companion.filter.sampler(&sprite.texture)),
+                            tint: values.tint,
+                            outline_color: values.outline_color,
+                            outline_size: values.outline_size,
+                            palette: palette,
+                            palette_size: values.palette_size,
+                            shadow: 0.0f32,
+                            time: time,
+                            direction: direction,
+                            state: state,
+                            size: [ sprite.size.0 as f32, sprite.size.1 as f32 ],
+                        };
+                        let _ = target.draw(&vertex_buffer, &index_buffer, program, &uniform, &parameters);
+                    }
                 }
+                let _ = target.finish();
+            },
+            Event::WindowEvent {
+                event: WindowEvent::ScaleFactorChanged { new_inner_size, .. },
+                ..
+            } => {
+                // Everything is measured in physical pixels, so measure the
+                // screen(s) again and resize the companions to match, which
+                // draws SVG frames again at their new size. The window is
+                // sized to match them rather than being scaled up.
+                measurements = Measurements::new(window_target);
+                companions.resize(&measurements);
+                let size = if config.overlay {
+                    let (x, y) = measurements.area_min_pos;
+                    display.gl_window().window().set_outer_position(PhysicalPosition { x, y });
+                    Some(measurements.area_size)
+                } else {
+                    companions.iter().next().map(|companion| companion.window().1)
+                };
+                *new_inner_size = match size {
+                    Some((width, height)) => PhysicalSize { width: width as u32, height: height as u32 },
+                    None => display.gl_window().window().inner_size(),
+                };
+                display.gl_window().window().request_redraw();
             },
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
@@ -253,3 +421,14 @@ fn main() -> Result<(), Error> {
 
 }
 
+/// Resizes the window to the size of the one companion it shows, when not in
+/// overlay mode. Companions aren't always the size their config asks for,
+/// since pixel art is snapped to a whole multiple of its frames' size (see
+/// `assets::Filter::snap`), and the window has room for their accessories
+/// too (see `Companion::window`).
+fn fit_window(display: &Display, companions: &Companions) {
+    if let Some(companion) = companions.iter().next() {
+        let (_, (width, height)) = companion.window();
+        display.gl_window().window().set_inner_size(PhysicalSize { width, height });
+    }
+}
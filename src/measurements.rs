@@ -1,9 +1,7 @@
 #[cfg(platform_windows)] use winapi;
 use glium;
 
-use std::ops::Sub;
-
-use glium::glutin::event_loop::EventLoop;
+use glium::glutin::event_loop::EventLoopWindowTarget;
 use glium::glutin::monitor::MonitorHandle;
 
 use crate::UserEvent;
@@ -15,12 +13,14 @@ pub struct Measurements {
     pub area_size: (i32, i32),
     pub monitor_size: (i32, i32),
     pub taskbar_height: i32,
-    pub shark_pos: (i32, i32),
     pub shark_size: (i32, i32),
 }
 
 impl Measurements {
-    pub fn new(event_loop: &EventLoop<UserEvent>) -> Self {
+    /// Measures the screen(s) as they are now. This is done again whenever
+    /// their scale factor changes, since that usually means they changed
+    /// resolution too.
+    pub fn new(event_loop: &EventLoopWindowTarget<UserEvent>) -> Self {
         let (area_min_pos, area_max_pos, area_size) = {
             let (mnx, mny, mxx, mxy) = event_loop.available_monitors()
                 .fold(None, |a, b| match (a, b) {
@@ -115,34 +115,55 @@ impl Measurements {
             (shark_w, shark_w)
         };
 
-        let shark_pos = {
-            #[cfg(platform_windows)] {
-                (
-                    area_min_pos.0 - shark_size.0,
-                    ((area_size.1 as f32)
-                        .sub((taskbar_height as f32) - (((shark_size.1 as f32) / 4.0) * 0.5))
-                        .sub(shark_size.1 as f32))
-                        .round() as i32,
-                )
-            } #[cfg(not(platform_windows))] {
-                (
-                    area_min_pos.0,
-                    ((area_size.1 as f32)
-                        .sub((taskbar_height as f32) * (3.5 / 4.0))
-                        .sub(shark_size.1 as f32))
-                        .round() as i32,
-                )
-            }
-        };
-
         Measurements {
             area_min_pos,
             area_max_pos,
             area_size,
             monitor_size,
             taskbar_height,
-            shark_pos,
             shark_size,
         }
     }
+
+    /// Returns the Y coordinate of the top of the taskbar, which is what
+    /// companions stand on.
+    pub fn ground(&self) -> i32 {
+        self.area_min_pos.1 + self.area_size.1 - self.taskbar_height
+    }
+
+    /// Returns where a sprite of `size` should start out, so that a point
+    /// `feet` pixels down from its top sits on the ground at the left edge of
+    /// the screen.
+    pub fn spawn_pos(&self, size: (i32, i32), feet: i32) -> (i32, i32) {
+        // On Windows, companions walk in from just past the edge.
+        let x = if cfg!(platform_windows) { self.area_min_pos.0 - size.0 } else { self.area_min_pos.0 };
+        (x, self.ground() - feet)
+    }
+}
+
+/// Returns the matrix that draws a sprite of `size` at `pos` on screen, when
+/// rendering into a window that's at `window.0` and is `window.1` big. In
+/// overlay mode (see `Config::overlay`), the window covers the whole area.
+pub fn matrix(pos: (i32, i32), size: (i32, i32), window: ((i32, i32), (i32, i32))) -> [[f32; 4]; 4] {
+    let ((window_x, window_y), (window_w, window_h)) = window;
+    let (window_w, window_h) = (window_w.max(1) as f32, window_h.max(1) as f32);
+    let (w, h) = (size.0 as f32, size.1 as f32);
+    let x = (pos.0 - window_x) as f32;
+    let y = (pos.1 - window_y) as f32;
+
+    // The quad we draw fills the whole window, so first shrink it down to
+    // the size of the sprite, and then move its center to the center of
+    // the sprite. OpenGL's Y axis points up, while the screens points
+    // down, hence the `1.0 -`.
+    [
+        [ w / window_w, 0.0, 0.0, 0.0 ],
+        [ 0.0, h / window_h, 0.0, 0.0 ],
+        [ 0.0, 0.0, 1.0, 0.0 ],
+        [
+            ((x + (w / 2.0)) / window_w) * 2.0 - 1.0,
+            1.0 - ((y + (h / 2.0)) / window_h) * 2.0,
+            0.0,
+            1.0,
+        ],
+    ]
 }
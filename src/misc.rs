@@ -1,9 +1,66 @@
 #[cfg(platform_windows)] use winapi;
 
-#[derive(Debug, Copy, Clone)]
+use std::path::PathBuf;
+
+use crate::config::CompanionConfig;
+
+#[derive(Debug, Clone)]
 pub enum UserEvent {
-    Move,
-    Frame,
+    /// Time to play the companions' animations and move them around.
+    Tick,
+    /// The config file changed on disk, and should be reloaded.
+    Reload,
+    /// Spawn a new companion.
+    Spawn(CompanionConfig),
+    /// Remove the companion with the given id.
+    Despawn(usize),
+    /// Put on, take off or switch an accessory of the companion with the
+    /// given id (see `Companion::wear`).
+    Wear(usize, String, Option<bool>),
+    /// Pet the companion with the given id (see `Companion::pet`).
+    Pet(usize),
+    /// These files in the packs directory were changed, added or removed.
+    PacksChanged(Vec<PathBuf>),
+}
+
+/// A tiny xorshift random number generator. Good enough for deciding where a
+/// shark goes, without pulling in a whole crate for it.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Xorshift gets stuck on zero forever, so nudge it off of it.
+        Rng(if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed })
+    }
+
+    /// Seeds a generator from the current time, for when the user didn't
+    /// pick a seed.
+    pub fn from_time() -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_nanos() as u64)
+            .unwrap_or(0);
+        Rng::new(nanos)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a random number in `0.0..1.0`.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Returns a random number in `min..max`.
+    pub fn range(&mut self, min: i32, max: i32) -> i32 {
+        if max <= min { return min; }
+        min + (self.next_u64() % ((max - min) as u64)) as i32
+    }
 }
 
 pub fn fatal<A, B>(line_1: A, line_2: B) -> !
@@ -12,21 +69,11 @@ where
     B: AsRef<str>,
 {
     #[cfg(platform_windows)] {
-        // Allocate and format a string in safe rust.
-        let formatted = format!("{}\n{}\0",
-            line_1.as_ref(),
-            line_2.as_ref());
-        
-        // ... Then, pass it down to unsafe rust as a pointer.
-        unsafe {
-            use winapi::um::winuser::*;
-            
-            MessageBoxA(
-                std::ptr::null_mut(),
-                formatted.as_str().as_bytes().as_ptr() as *const i8,
-                b"Desktop Sharkie - Fatal error\0".as_ptr() as *const i8,
-                MB_ICONERROR);
-        }
+        message_box(
+            format!("{}\n{}", line_1.as_ref(), line_2.as_ref()),
+            b"Desktop Sharkie - Fatal error\0",
+            winapi::um::winuser::MB_ICONERROR,
+            true);
     } #[cfg(any(platform_unix, platform_macos))] {
         println!("\n{}\n\x1b[1m{}\x1b[0m\n",
             line_1.as_ref(),
@@ -36,4 +83,45 @@ where
     std::process::exit(1);
 }
 
+/// Like `fatal`, but for problems that desktop sharkie can carry on from.
+/// On Windows, the message box is shown from its own thread, so that the
+/// companions keep moving while it's open.
+pub fn warn<A, B>(line_1: A, line_2: B)
+where
+    A: AsRef<str>,
+    B: AsRef<str>,
+{
+    #[cfg(platform_windows)] {
+        message_box(
+            format!("{}\n{}", line_1.as_ref(), line_2.as_ref()),
+            b"Desktop Sharkie - Warning\0",
+            winapi::um::winuser::MB_ICONWARNING,
+            false);
+    } #[cfg(any(platform_unix, platform_macos))] {
+        eprintln!("\n{}\n\x1b[1m{}\x1b[0m\n",
+            line_1.as_ref(),
+            line_2.as_ref());
+    }
+}
+
+/// Shows `text` in a message box with `title`, which has to end in a nul,
+/// and `icon`. If `wait` is false, it's shown from its own thread instead of
+/// waiting for it to be closed.
+#[cfg(platform_windows)]
+fn message_box(text: String, title: &'static [u8], icon: u32, wait: bool) {
+    // Allocate and format a string in safe rust.
+    let formatted = format!("{}\0", text);
 
+    // ... Then, pass it down to unsafe rust as a pointer.
+    let show = move || unsafe {
+        use winapi::um::winuser::*;
+
+        MessageBoxA(
+            std::ptr::null_mut(),
+            formatted.as_str().as_bytes().as_ptr() as *const i8,
+            title.as_ptr() as *const i8,
+            icon);
+    };
+
+    if wait { show(); } else { std::thread::spawn(show); }
+}
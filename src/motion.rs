@@ -0,0 +1,374 @@
+//! Squashing, stretching, bobbing and tilting, which packs can ask for to
+//! make companions move less stiffly without drawing any extra frames.
+//!
+//! Packs turn them on in their manifest (see `pack::manifest`):
+//!
+//! ```toml
+//! [motion]
+//! squash = 0.2
+//! stretch = 0.1
+//! bob = 0.03
+//! bob_speed = 1.5
+//! tilt = 15.0
+//! ```
+//!
+//! They're worked out from what the companion is doing every tick (see
+//! `Pose`), and applied to the matrix its sprites are drawn with (see
+//! `Transform`), so they cost nothing but a few multiplications.
+
+use serde::{ Deserialize, Serialize };
+
+use std::f32::consts::PI;
+
+use crate::pack::AnimationKind;
+
+/// How long the squash after landing takes to settle, in seconds.
+const LAND_TIME: f32 = 0.4;
+
+/// How quickly stretching, bobbing and tilting fade in and out, per second.
+const EASE: f32 = 8.0;
+
+/// How many times a second companions sway while they're being dragged.
+const SWAY_SPEED: f32 = 1.5;
+
+/// How much a pack's companions squash, stretch, bob and tilt. Everything is
+/// off unless the pack's manifest turns it on.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Motion {
+    /// How much the companion squashes when it lands, as a fraction of its
+    /// height. It springs back up, overshooting a little on the way.
+    pub squash: f32,
+    /// How much the companion stretches while it's moving up, as a fraction
+    /// of its height.
+    pub stretch: f32,
+    /// How far the companion bobs up and down while it's moving along the
+    /// ground, as a fraction of its height.
+    pub bob: f32,
+    /// How many times a second the companion bobs.
+    pub bob_speed: f32,
+    /// The most the companion tilts, in degrees, when it's moving up or down
+    /// a slope, and how far it sways while it's being dragged.
+    pub tilt: f32,
+}
+
+/// Where a companion is in its squashing, stretching, bobbing and tilting.
+/// Each companion has its own.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Pose {
+    /// How long ago the companion landed, in seconds, while it's still
+    /// squashing from it.
+    landed: Option<f32>,
+    /// How much it's stretching, from 0 to 1.
+    stretch: f32,
+    /// How much it's bobbing, from 0 to 1.
+    bob: f32,
+    /// How far it is through bobbing, in bobs.
+    phase: f32,
+    /// How far it's tilted, in radians clockwise.
+    tilt: f32,
+    /// How long it's been swaying for, in seconds.
+    sway: f32,
+}
+
+/// A squash, stretch, bob and tilt to draw a companion's sprites with.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    /// The point the companion is squashed and tilted around, in pixels on
+    /// screen. This is where its feet are, so that they stay on the ground.
+    pub pivot: (f32, f32),
+    pub scale: (f32, f32),
+    /// How far it's tilted, in radians clockwise.
+    pub angle: f32,
+    /// How far it's moved down, in pixels.
+    pub offset: f32,
+}
+
+impl Default for Motion {
+    fn default() -> Self {
+        Motion {
+            squash: 0.0,
+            stretch: 0.0,
+            bob: 0.0,
+            bob_speed: 1.0,
+            tilt: 0.0,
+        }
+    }
+}
+
+impl Motion {
+    /// Returns whether any of it is turned on.
+    pub fn any(&self) -> bool {
+        self.squash != 0.0 || self.stretch != 0.0 || self.bob != 0.0 || self.tilt != 0.0
+    }
+
+    /// Returns whether it ever draws the companion smaller than its frames
+    /// are, in either direction. Squashing and stretching both do, since
+    /// the companion gets thinner as it gets taller.
+    pub fn shrinks(&self) -> bool {
+        self.squash != 0.0 || self.stretch != 0.0
+    }
+}
+
+impl Pose {
+    /// Moves time forward by `dt` seconds for a companion that is playing an
+    /// animation of `kind`, and moving by `velocity` on screen. `landed` is
+    /// whether it just landed. Returns whether the pose changed.
+    pub fn update(
+        &mut self,
+        motion: &Motion,
+        kind: AnimationKind,
+        velocity: (f32, f32),
+        landed: bool,
+        dt: f32,
+    ) -> bool {
+        if !motion.any() { return false; }
+        let before = *self;
+
+        self.landed = if landed {
+            Some(0.0)
+        } else {
+            self.landed.map(|time| time + dt).filter(|time| *time < LAND_TIME)
+        };
+
+        let (dx, dy) = velocity;
+        let ease = 1.0 - (-EASE * dt).exp();
+        let approach = |value: f32, target: f32| {
+            let value = value + (target - value) * ease;
+            if (target - value).abs() < 0.001 { target } else { value }
+        };
+
+        self.stretch = approach(self.stretch, if dy < 0.0 { 1.0 } else { 0.0 });
+
+        let moving = kind == AnimationKind::Ground && dx != 0.0;
+        self.bob = approach(self.bob, if moving { 1.0 } else { 0.0 });
+        if self.bob > 0.0 {
+            self.phase = (self.phase + motion.bob_speed * dt).fract();
+        } else {
+            self.phase = 0.0;
+        }
+
+        let most = motion.tilt.to_radians();
+        let tilt = match kind {
+            AnimationKind::Drag => {
+                self.sway += dt;
+                most * (self.sway * SWAY_SPEED * 2.0 * PI).sin()
+            },
+            // Heading right and up a slope tilts the companion's front up,
+            // which is anticlockwise, and heading left does the opposite.
+            AnimationKind::Ground if dx != 0.0 && dy != 0.0 => (dy / dx).atan().max(-most).min(most),
+            _ => 0.0,
+        };
+        if kind != AnimationKind::Drag { self.sway = 0.0; }
+        self.tilt = approach(self.tilt, tilt);
+
+        *self != before
+    }
+
+    /// Returns the transform to draw a companion of `height` pixels in this
+    /// pose with, squashing and tilting it around `pivot`.
+    pub fn transform(&self, motion: &Motion, pivot: (f32, f32), height: f32) -> Transform {
+        // Squashing is positive, and stretching negative. Landing squashes
+        // the companion, and it springs back past where it started a couple
+        // of times while the squash dies down.
+        let landing = self.landed.map_or(0.0, |time| {
+            let time = time / LAND_TIME;
+            (1.0 - time).powi(2) * (time * 3.0 * PI).cos()
+        });
+        let squash = motion.squash * landing - motion.stretch * self.stretch;
+        // It gets as much wider as it gets shorter, so that it looks like it
+        // keeps its volume.
+        let scale_y = (1.0 - squash).max(0.1);
+
+        Transform {
+            pivot,
+            scale: (1.0 / scale_y, scale_y),
+            angle: self.tilt,
+            offset: motion.bob * height * self.bob * (self.phase * 2.0 * PI).sin(),
+        }
+    }
+}
+
+impl Transform {
+    /// Applies the transform to `matrix`, which draws a sprite in `window`
+    /// (see `measurements::matrix`).
+    pub fn apply(&self, matrix: [[f32; 4]; 4], window: ((i32, i32), (i32, i32))) -> [[f32; 4]; 4] {
+        let ((window_x, window_y), (window_w, window_h)) = window;
+        let (window_w, window_h) = (window_w.max(1) as f32, window_h.max(1) as f32);
+        let pivot = (self.pivot.0 - window_x as f32, self.pivot.1 - window_y as f32);
+
+        // The transform is in pixels, with the Y axis pointing down, so it
+        // has to be turned into one that works on OpenGL's coordinates,
+        // which go from -1 to 1 across the window with the Y axis pointing
+        // up.
+        let (sin, cos) = self.angle.sin_cos();
+        let linear = [
+            [ cos * self.scale.0, -sin * self.scale.1 ],
+            [ sin * self.scale.0, cos * self.scale.1 ],
+        ];
+        let center = (window_w / 2.0 - pivot.0, window_h / 2.0 - pivot.1);
+        let moved = (
+            pivot.0 + linear[0][0] * center.0 + linear[0][1] * center.1,
+            pivot.1 + linear[1][0] * center.0 + linear[1][1] * center.1 + self.offset,
+        );
+        let transform = [
+            [ linear[0][0], -linear[1][0] * window_w / window_h, 0.0, 0.0 ],
+            [ -linear[0][1] * window_h / window_w, linear[1][1], 0.0, 0.0 ],
+            [ 0.0, 0.0, 1.0, 0.0 ],
+            [ moved.0 / window_w * 2.0 - 1.0, 1.0 - moved.1 / window_h * 2.0, 0.0, 1.0 ],
+        ];
+
+        let mut result = [ [ 0.0; 4 ]; 4 ];
+        for (column, values) in result.iter_mut().enumerate() {
+            for (row, value) in values.iter_mut().enumerate() {
+                *value = (0..4).map(|index| transform[index][row] * matrix[column][index]).sum();
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY: [[f32; 4]; 4] = [
+        [ 1.0, 0.0, 0.0, 0.0 ],
+        [ 0.0, 1.0, 0.0, 0.0 ],
+        [ 0.0, 0.0, 1.0, 0.0 ],
+        [ 0.0, 0.0, 0.0, 1.0 ],
+    ];
+
+    const WINDOW: ((i32, i32), (i32, i32)) = ((100, 50), (400, 200));
+
+    fn motion() -> Motion {
+        Motion { squash: 0.2, stretch: 0.1, bob: 0.03, bob_speed: 1.5, tilt: 15.0 }
+    }
+
+    /// Returns where the pixel at `point` on screen is in OpenGL's
+    /// coordinates, when drawing into `WINDOW`.
+    fn clip(point: (f32, f32)) -> [f32; 4] {
+        let ((x, y), (w, h)) = WINDOW;
+        [ (point.0 - x as f32) / w as f32 * 2.0 - 1.0, 1.0 - (point.1 - y as f32) / h as f32 * 2.0, 0.0, 1.0 ]
+    }
+
+    fn multiply(matrix: [[f32; 4]; 4], vector: [f32; 4]) -> [f32; 4] {
+        let mut result = [ 0.0; 4 ];
+        for (row, value) in result.iter_mut().enumerate() {
+            *value = (0..4).map(|column| matrix[column][row] * vector[column]).sum();
+        }
+        result
+    }
+
+    fn assert_near(actual: [f32; 4], expected: [f32; 4]) {
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert!((a - e).abs() < 1e-4, "{:?} isn't {:?}", actual, expected);
+        }
+    }
+
+    /// Checks that `transform` moves the pixel `offset` away from its pivot
+    /// to `expected` away from it.
+    fn assert_moves(transform: &Transform, offset: (f32, f32), expected: (f32, f32)) {
+        let (x, y) = transform.pivot;
+        let matrix = transform.apply(IDENTITY, WINDOW);
+        assert_near(multiply(matrix, clip((x + offset.0, y + offset.1))), clip((x + expected.0, y + expected.1)));
+    }
+
+    #[test]
+    fn does_nothing_when_turned_off() {
+        let mut pose = Pose::default();
+        assert!(!pose.update(&Motion::default(), AnimationKind::Ground, (60.0, -30.0), true, 0.1));
+        let transform = pose.transform(&Motion::default(), (0.0, 0.0), 100.0);
+        assert_eq!(transform.apply(IDENTITY, WINDOW), IDENTITY);
+    }
+
+    #[test]
+    fn squashes_when_landing_and_settles() {
+        let mut pose = Pose::default();
+        assert!(pose.update(&motion(), AnimationKind::Ground, (0.0, 0.0), true, 0.05));
+        pose.update(&motion(), AnimationKind::Ground, (0.0, 0.0), false, 0.05);
+        let (width, height) = pose.transform(&motion(), (0.0, 0.0), 100.0).scale;
+        assert!(height < 1.0 && height > 0.8, "{}", height);
+        assert!((width * height - 1.0).abs() < 1e-5, "it keeps its area");
+
+        for _ in 0..10 { pose.update(&motion(), AnimationKind::Ground, (0.0, 0.0), false, 0.05); }
+        assert_eq!(pose.transform(&motion(), (0.0, 0.0), 100.0).scale, (1.0, 1.0));
+    }
+
+    #[test]
+    fn stretches_and_tilts_moving_up_a_slope() {
+        let mut pose = Pose::default();
+        for _ in 0..20 { pose.update(&motion(), AnimationKind::Ground, (60.0, -600.0), false, 0.05); }
+        let transform = pose.transform(&motion(), (0.0, 0.0), 100.0);
+        assert!((transform.scale.1 - 1.1).abs() < 1e-3, "{:?}", transform.scale);
+        // Heading right and up tilts it anticlockwise, but no further than
+        // the pack allows.
+        assert!((transform.angle + 15.0f32.to_radians()).abs() < 1e-3, "{}", transform.angle);
+
+        for _ in 0..20 { pose.update(&motion(), AnimationKind::Ground, (0.0, 0.0), false, 0.05); }
+        let transform = pose.transform(&motion(), (0.0, 0.0), 100.0);
+        assert_eq!((transform.scale, transform.angle, transform.offset), ((1.0, 1.0), 0.0, 0.0));
+    }
+
+    #[test]
+    fn bobs_while_moving() {
+        let mut pose = Pose::default();
+        let mut offsets = Vec::new();
+        for _ in 0..40 {
+            pose.update(&motion(), AnimationKind::Ground, (60.0, 0.0), false, 0.05);
+            offsets.push(pose.transform(&motion(), (0.0, 0.0), 100.0).offset);
+        }
+        let (low, high) = offsets.iter().fold((0.0f32, 0.0f32), |(low, high), &offset| (low.min(offset), high.max(offset)));
+        assert!(low < -2.5 && high > 2.5 && high <= 3.0, "{:?}", (low, high));
+    }
+
+    #[test]
+    fn sways_while_dragged() {
+        let mut pose = Pose::default();
+        let mut angles = Vec::new();
+        for _ in 0..40 {
+            pose.update(&motion(), AnimationKind::Drag, (0.0, 0.0), false, 0.05);
+            angles.push(pose.tilt);
+        }
+        let most = 15.0f32.to_radians();
+        assert!(angles.iter().all(|angle| angle.abs() <= most), "{:?}", angles);
+        assert!(angles.iter().any(|&angle| angle > most / 2.0) && angles.iter().any(|&angle| angle < -most / 2.0), "{:?}", angles);
+
+        for _ in 0..40 { pose.update(&motion(), AnimationKind::Fall, (0.0, 0.0), false, 0.05); }
+        assert_eq!((pose.tilt, pose.sway), (0.0, 0.0));
+    }
+
+    #[test]
+    fn transforms_around_the_pivot() {
+        let transform = Transform { pivot: (260.0, 230.0), scale: (1.0, 1.0), angle: 0.0, offset: 0.0 };
+        assert_eq!(transform.apply(IDENTITY, WINDOW), IDENTITY);
+
+        let scaled = Transform { scale: (2.0, 0.5), ..transform };
+        assert_moves(&scaled, (0.0, 0.0), (0.0, 0.0));
+        assert_moves(&scaled, (10.0, -20.0), (20.0, -10.0));
+
+        // Clockwise on screen, where the Y axis points down, even though the
+        // window isn't square.
+        let tilted = Transform { angle: PI / 2.0, ..transform };
+        assert_moves(&tilted, (0.0, 0.0), (0.0, 0.0));
+        assert_moves(&tilted, (10.0, 0.0), (0.0, 10.0));
+        assert_moves(&tilted, (0.0, -10.0), (10.0, 0.0));
+
+        let bobbed = Transform { offset: 5.0, ..transform };
+        assert_moves(&bobbed, (0.0, 0.0), (0.0, 5.0));
+        assert_moves(&bobbed, (-30.0, 7.0), (-30.0, 12.0));
+    }
+
+    #[test]
+    fn applies_after_the_sprite_matrix() {
+        // A sprite drawn by `measurements::matrix` has its corners at -1 and
+        // 1, so its bottom left corner ends up where the transform puts it.
+        let (pos, size) = ((200, 130), (80, 100));
+        let matrix = crate::measurements::matrix(pos, size, WINDOW);
+        let transform = Transform { pivot: (240.0, 230.0), scale: (1.25, 0.8), angle: 0.0, offset: 0.0 };
+        let corner = multiply(transform.apply(matrix, WINDOW), [ -1.0, -1.0, 0.0, 1.0 ]);
+        assert_near(corner, clip((240.0 - 40.0 * 1.25, 230.0)));
+        let top = multiply(transform.apply(matrix, WINDOW), [ 1.0, 1.0, 0.0, 1.0 ]);
+        assert_near(top, clip((240.0 + 40.0 * 1.25, 230.0 - 100.0 * 0.8)));
+    }
+}
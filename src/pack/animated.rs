@@ -0,0 +1,705 @@
+//! Imports animated GIF and APNG files.
+//!
+//! A pack can be a single animated image, or a directory of them. Each image
+//! becomes an animation named after the file, so `walk.gif` and `fall.png`
+//! become `walk` and `fall`, with the delays of the image as the durations of
+//! its frames.
+//!
+//! Frames of animated images usually only cover the part of the picture that
+//! changed, so they're drawn onto a canvas one after another, following their
+//! blend and disposal modes, and every frame of the pack is a copy of that
+//! canvas.
+//!
+//! GIFs and APNGs can also be written, for previewing the animations of any
+//! pack (see `commands::preview`).
+
+use failure;
+use gif;
+use image;
+use png;
+
+use std::borrow::Cow;
+use std::io::Cursor;
+use std::path::{ Path, PathBuf };
+
+use failure::Error;
+use image::{ Rgba, RgbaImage };
+
+use crate::motion::Motion;
+
+use super::{ Animation, AnimationFrame, AnimationKind, AssetError, Facing, Files, FrameSource, Pack, Sheet };
+
+/// How long frames without a delay are shown for, in milliseconds. Browsers
+/// treat delays this short as a mistake and slow them down, and images are
+/// made to look right in browsers.
+const DEFAULT_DELAY: u32 = 100;
+
+const EXTENSIONS: [&str; 3] = [ "gif", "png", "apng" ];
+
+/// The formats that can hold animations.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Gif,
+    /// Any PNG, whether it's animated or not.
+    Png,
+}
+
+/// A frame of an animated image, after it's been drawn onto the canvas.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub image: RgbaImage,
+    /// How long the frame is shown for, in milliseconds.
+    pub delay: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Blend {
+    /// The frame replaces what was under it.
+    Source,
+    /// The frame is drawn over what was under it.
+    Over,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Dispose {
+    /// The frame is left as it is for the next one to be drawn over.
+    Keep,
+    /// The frame is cleared to transparent.
+    Background,
+    /// The canvas goes back to how it was before the frame was drawn.
+    Previous,
+}
+
+/// Draws the frames of an animation on top of each other.
+struct Canvas {
+    image: RgbaImage,
+    /// What the canvas looked like before the last frame, if it's going to
+    /// be restored.
+    saved: Option<RgbaImage>,
+    /// How to dispose of the last frame, and where it was drawn.
+    pending: Option<(Dispose, (u32, u32, u32, u32))>,
+}
+
+/// Returns whether `path` is an animated image pack, either an image file or
+/// a directory with images in it. Directories with numbered frames in them
+/// are left to be loaded as those, even if they have other images too, like
+/// a `cover.png`.
+pub fn detect(files: &Files, path: &Path) -> bool {
+    if files.is_file(path) { return is_image(files, path); }
+    files.list(path)
+        .map(|paths| {
+            !paths.iter().any(|path| super::is_frame(path))
+                && paths.iter().any(|path| is_image(files, path))
+        })
+        .unwrap_or(false)
+}
+
+fn is_image(files: &Files, path: &Path) -> bool {
+    files.is_file(path) && path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+pub fn load(name: &str, files: &Files, path: &Path) -> Result<Pack, AssetError> {
+    let mut images = if files.is_dir(path) {
+        files.list(path)
+            .map_err(|error| AssetError::read(path, error))?
+            .into_iter()
+            .filter(|path| is_image(files, path))
+            .collect::<Vec<PathBuf>>()
+    } else {
+        vec![ path.to_owned() ]
+    };
+    images.sort();
+
+    let mut pack = Pack {
+        name: name.to_owned(),
+        frames: Vec::new(),
+        sheets: Vec::new(),
+        frame_size: (0, 0),
+        anchor: (0.0, 0.0),
+        animations: Vec::new(),
+        layers: Vec::new(),
+        accessories: Vec::new(),
+        emitters: Vec::new(),
+        events: Vec::new(),
+        shader: None,
+        filter: None,
+        motion: Motion::default(),
+        facing: Facing::Either,
+        warnings: Vec::new(),
+    };
+
+    for file in images {
+        let animation_name = file
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("")
+            .to_owned();
+        let bytes = files.read(&file)
+            .map_err(|error| AssetError::read(&file, error))?;
+
+        // The frames are decoded again when they're loaded, but their delays
+        // and how many of them there are are needed now.
+        let decoded = match decode(&bytes) {
+            Ok(decoded) if decoded.is_empty() => {
+                pack.warnings.push(format!("{} doesn't have any frames, so it was skipped.", display(&file)));
+                continue;
+            },
+            Ok(decoded) => decoded,
+            Err(error) => {
+                pack.warnings.push(format!("{} was skipped: {}", display(&file), error));
+                continue;
+            },
+        };
+
+        if pack.animations.is_empty() {
+            pack.frame_size = decoded[0].image.dimensions();
+            pack.anchor = super::default_anchor(pack.frame_size);
+        }
+
+        let kind = AnimationKind::guess(&animation_name);
+        let speed = if kind == AnimationKind::Ground { super::default_speed(pack.frame_size.1) } else { 0.0 };
+        let sheet = pack.sheets.len();
+
+        let frames = decoded
+            .iter()
+            .enumerate()
+            .map(|(index, frame)| {
+                pack.frames.push(FrameSource::Animated { sheet, index });
+                AnimationFrame {
+                    frame: pack.frames.len() - 1,
+                    duration: frame.delay,
+                    velocity: (speed, 0.0),
+                    anchor: None,
+                }
+            })
+            .collect();
+
+        pack.sheets.push(Sheet { file, bytes: Cow::Owned(bytes), key: None });
+        pack.animations.push(Animation {
+            name: animation_name,
+            kind,
+            frames,
+            weight: if kind == AnimationKind::Ground { 1 } else { 0 },
+            next: Vec::new(),
+            border: Vec::new(),
+        });
+    }
+
+    if pack.animations.is_empty() {
+        return Err(AssetError::invalid(path, "There aren't any images in it that can be imported."));
+    }
+
+    Ok(pack)
+}
+
+fn display(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+/// Works out which of the animated formats `bytes` is in, if any.
+pub fn format(bytes: &[u8]) -> Option<Format> {
+    if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some(Format::Gif)
+    } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(Format::Png)
+    } else {
+        None
+    }
+}
+
+/// Returns whether `bytes` is an image with more than one frame, or at
+/// least one that says it's animated.
+pub fn is_animated(bytes: &[u8]) -> bool {
+    match format(bytes) {
+        Some(Format::Gif) => true,
+        Some(Format::Png) => png_chunks(bytes)
+            .map(|(name, _)| name)
+            .take_while(|name| name != b"IDAT")
+            .any(|name| name == *b"acTL"),
+        None => false,
+    }
+}
+
+/// Decodes every frame of an animated image. Images that aren't animated
+/// give a single frame.
+pub fn decode(bytes: &[u8]) -> Result<Vec<Frame>, Error> {
+    match format(bytes) {
+        Some(Format::Gif) => decode_gif(bytes),
+        Some(Format::Png) => decode_png(bytes),
+        None => Err(failure::format_err!("This isn't a GIF or PNG image.")),
+    }
+}
+
+fn decode_gif(bytes: &[u8]) -> Result<Vec<Frame>, Error> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = options.read_info(Cursor::new(bytes))?;
+    let mut canvas = Canvas::new(decoder.width() as u32, decoder.height() as u32);
+    let mut frames = Vec::new();
+
+    while let Some(frame) = decoder.read_next_frame()? {
+        let image = RgbaImage::from_raw(frame.width as u32, frame.height as u32, frame.buffer.to_vec())
+            .ok_or_else(|| failure::format_err!("A frame is missing some of its pixels."))?;
+        let dispose = match frame.dispose {
+            gif::DisposalMethod::Any | gif::DisposalMethod::Keep => Dispose::Keep,
+            gif::DisposalMethod::Background => Dispose::Background,
+            gif::DisposalMethod::Previous => Dispose::Previous,
+        };
+        // GIF pixels are either fully transparent or fully opaque, and the
+        // transparent ones always show what's under them.
+        frames.push(Frame {
+            image: canvas.draw(&image, (frame.left as u32, frame.top as u32), Blend::Over, dispose),
+            delay: delay(frame.delay as u32 * 10),
+        });
+    }
+
+    Ok(frames)
+}
+
+fn decode_png(bytes: &[u8]) -> Result<Vec<Frame>, Error> {
+    // By default, palettes and low bit depths are expanded, and 16 bit
+    // channels are cut down to 8 bits.
+    let (info, mut reader) = png::Decoder::new(Cursor::new(bytes)).read_info()?;
+    let mut buffer = vec![ 0; reader.output_buffer_size() ];
+
+    let count = match reader.info().animation_control() {
+        Some(control) => control.num_frames,
+        None => {
+            reader.next_frame(&mut buffer)?;
+            let (color_type, _) = reader.output_color_type();
+            return Ok(vec![ Frame {
+                image: to_rgba(&buffer, (info.width, info.height), color_type)?,
+                delay: DEFAULT_DELAY,
+            } ]);
+        },
+    };
+
+    // If the first fcTL comes after the IDAT, the image in the IDAT is only
+    // shown by viewers that don't support APNG, and isn't part of the
+    // animation.
+    if reader.info().frame_control().is_none() {
+        reader.next_frame(&mut buffer)?;
+    }
+
+    let mut canvas = Canvas::new(info.width, info.height);
+    let mut frames = Vec::new();
+
+    for index in 0..count {
+        reader.next_frame(&mut buffer)?;
+        let (color_type, _) = reader.output_color_type();
+        let control = *reader.info()
+            .frame_control()
+            .ok_or_else(|| failure::format_err!("Frame {} is missing its fcTL chunk.", index))?;
+        let image = to_rgba(&buffer, (control.width, control.height), color_type)?;
+        let blend = match control.blend_op {
+            png::BlendOp::Source => Blend::Source,
+            png::BlendOp::Over => Blend::Over,
+        };
+        let dispose = match control.dispose_op {
+            png::DisposeOp::None => Dispose::Keep,
+            png::DisposeOp::Background => Dispose::Background,
+            // The first frame has nothing to go back to, so it's cleared.
+            png::DisposeOp::Previous if index == 0 => Dispose::Background,
+            png::DisposeOp::Previous => Dispose::Previous,
+        };
+        let den = if control.delay_den == 0 { 100 } else { control.delay_den as u32 };
+        frames.push(Frame {
+            image: canvas.draw(&image, (control.x_offset, control.y_offset), blend, dispose),
+            delay: delay(control.delay_num as u32 * 1000 / den),
+        });
+    }
+
+    Ok(frames)
+}
+
+/// Encodes `frames` as a GIF that loops forever. They should all be the same
+/// size. GIF pixels are either transparent or opaque, so pixels that are
+/// less than half opaque become transparent.
+pub fn encode_gif(frames: &[Frame]) -> Result<Vec<u8>, Error> {
+    let (width, height) = frames.first().map_or((1, 1), |frame| frame.image.dimensions());
+    if width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(failure::format_err!("{}x{} is too big for a GIF.", width, height));
+    }
+
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut bytes, width as u16, height as u16, &[])?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+        for frame in frames {
+            let mut pixels = frame.image.clone().into_raw();
+            // Only one colour can be transparent, so make them all the same.
+            for pixel in pixels.chunks_exact_mut(4) {
+                if pixel[3] < 128 { pixel.copy_from_slice(&[ 0, 0, 0, 0 ]); }
+            }
+            let mut image = gif::Frame::from_rgba_speed(width as u16, height as u16, &mut pixels, 10);
+            // GIF delays are in hundredths of a second, and most viewers
+            // treat anything shorter than two as the default.
+            image.delay = ((frame.delay + 5) / 10).max(2).min(u16::MAX as u32) as u16;
+            image.dispose = gif::DisposalMethod::Background;
+            encoder.write_frame(&image)?;
+        }
+    }
+    Ok(bytes)
+}
+
+/// Encodes `frames` as an APNG that loops forever. They should all be the
+/// same size.
+pub fn encode_png(frames: &[Frame]) -> Result<Vec<u8>, Error> {
+    let (width, height) = frames.first().map_or((1, 1), |frame| frame.image.dimensions());
+
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut bytes, width, height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+
+        // The frame count, and how many times to play them, where zero is
+        // forever.
+        let mut control = (frames.len() as u32).to_be_bytes().to_vec();
+        control.extend_from_slice(&0u32.to_be_bytes());
+        writer.write_chunk(*b"acTL", &control)?;
+
+        // fcTL and fdAT chunks share one sequence of numbers.
+        let mut sequence = 0u32;
+
+        for (index, frame) in frames.iter().enumerate() {
+            let mut control = Vec::with_capacity(26);
+            control.extend_from_slice(&sequence.to_be_bytes());
+            control.extend_from_slice(&width.to_be_bytes());
+            control.extend_from_slice(&height.to_be_bytes());
+            control.extend_from_slice(&0u32.to_be_bytes());
+            control.extend_from_slice(&0u32.to_be_bytes());
+            // The delay is a fraction of a second, given in milliseconds.
+            control.extend_from_slice(&(frame.delay.min(u16::MAX as u32) as u16).to_be_bytes());
+            control.extend_from_slice(&1000u16.to_be_bytes());
+            // Each frame covers the whole canvas, so it's simply replaced by
+            // the next one.
+            control.extend_from_slice(&[ 0, 0 ]);
+            writer.write_chunk(*b"fcTL", &control)?;
+            sequence += 1;
+
+            if index == 0 {
+                writer.write_image_data(frame.image.as_raw())?;
+            } else {
+                // The rest of the frames go in fdAT chunks, which are IDAT
+                // chunks with a sequence number in front. Encoding each one
+                // as a PNG of its own is the easiest way to get their IDATs.
+                let mut data = sequence.to_be_bytes().to_vec();
+                data.extend(compress(&frame.image)?);
+                writer.write_chunk(*b"fdAT", &data)?;
+                sequence += 1;
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+/// Returns the compressed pixels of `image`, as they'd be stored in the IDAT
+/// chunks of a PNG.
+fn compress(image: &RgbaImage) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut bytes, image.width(), image.height());
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(image.as_raw())?;
+    }
+    Ok(png_chunks(&bytes)
+        .filter(|(name, _)| name == b"IDAT")
+        .flat_map(|(_, data)| data.iter().copied())
+        .collect())
+}
+
+/// Turns the 8 bit pixels `png` decodes into RGBA.
+fn to_rgba(buffer: &[u8], size: (u32, u32), color_type: png::ColorType) -> Result<RgbaImage, Error> {
+    let pixels = (size.0 * size.1) as usize;
+    let channels = match color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        png::ColorType::RGBA => 4,
+        png::ColorType::Indexed => return Err(failure::format_err!("Palette wasn't expanded.")),
+    };
+    let data = buffer
+        .get(..pixels * channels)
+        .ok_or_else(|| failure::format_err!("A frame is missing some of its pixels."))?;
+    let mut rgba = Vec::with_capacity(pixels * 4);
+    for pixel in data.chunks_exact(channels) {
+        match *pixel {
+            [ l ] => rgba.extend_from_slice(&[ l, l, l, 255 ]),
+            [ l, a ] => rgba.extend_from_slice(&[ l, l, l, a ]),
+            [ r, g, b ] => rgba.extend_from_slice(&[ r, g, b, 255 ]),
+            _ => rgba.extend_from_slice(pixel),
+        }
+    }
+    Ok(RgbaImage::from_raw(size.0, size.1, rgba).unwrap())
+}
+
+fn delay(ms: u32) -> u32 {
+    if ms <= 10 { DEFAULT_DELAY } else { ms }
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        Canvas {
+            image: RgbaImage::new(width, height),
+            saved: None,
+            pending: None,
+        }
+    }
+
+    /// Disposes of the last frame, draws `frame` at `pos`, and returns what
+    /// the canvas looks like now.
+    fn draw(&mut self, frame: &RgbaImage, pos: (u32, u32), blend: Blend, dispose: Dispose) -> RgbaImage {
+        match self.pending.take() {
+            Some((Dispose::Background, (x, y, w, h))) => {
+                for y in y..y + h {
+                    for x in x..x + w {
+                        self.image.put_pixel(x, y, Rgba([ 0, 0, 0, 0 ]));
+                    }
+                }
+            },
+            Some((Dispose::Previous, _)) => {
+                if let Some(saved) = self.saved.take() { self.image = saved; }
+            },
+            Some((Dispose::Keep, _)) | None => (),
+        }
+
+        if dispose == Dispose::Previous { self.saved = Some(self.image.clone()); }
+
+        // Frames that stick out of the canvas are cut off.
+        let w = frame.width().min(self.image.width().saturating_sub(pos.0));
+        let h = frame.height().min(self.image.height().saturating_sub(pos.1));
+
+        for y in 0..h {
+            for x in 0..w {
+                let source = *frame.get_pixel(x, y);
+                let target = self.image.get_pixel_mut(pos.0 + x, pos.1 + y);
+                *target = match blend {
+                    Blend::Source => source,
+                    Blend::Over => over(source, *target),
+                };
+            }
+        }
+
+        self.pending = Some((dispose, (pos.0, pos.1, w, h)));
+        self.image.clone()
+    }
+}
+
+/// Draws `source` over `target`, for pixels that aren't premultiplied.
+fn over(source: Rgba<u8>, target: Rgba<u8>) -> Rgba<u8> {
+    let (source_alpha, target_alpha) = (source.0[3] as u32, target.0[3] as u32);
+    if source_alpha == 255 || target_alpha == 0 { return source; }
+    if source_alpha == 0 { return target; }
+    // Everything is scaled by 255 * 255 to stay in integers.
+    let alpha = source_alpha * 255 + target_alpha * (255 - source_alpha);
+    let mut pixel = [ 0, 0, 0, (alpha / 255) as u8 ];
+    for (channel, value) in pixel.iter_mut().take(3).enumerate() {
+        let color = source.0[channel] as u32 * source_alpha * 255
+            + target.0[channel] as u32 * target_alpha * (255 - source_alpha);
+        *value = (color / alpha) as u8;
+    }
+    Rgba(pixel)
+}
+
+/// Lists the names and data of the chunks in a PNG file, stopping at the
+/// first one that doesn't fit.
+fn png_chunks(bytes: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> + '_ {
+    let mut offset = 8;
+    std::iter::from_fn(move || {
+        let header = bytes.get(offset..offset + 8)?;
+        let len = u32::from_be_bytes([ header[0], header[1], header[2], header[3] ]) as usize;
+        let name = [ header[4], header[5], header[6], header[7] ];
+        let data = bytes.get(offset + 8..offset + 8 + len)?;
+        // Length, name, data and CRC.
+        offset += 12 + len;
+        Some((name, data))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgba<u8> = Rgba([ 255, 0, 0, 255 ]);
+    const GREEN: Rgba<u8> = Rgba([ 0, 255, 0, 255 ]);
+    const BLUE: Rgba<u8> = Rgba([ 0, 0, 255, 255 ]);
+    const CLEAR: Rgba<u8> = Rgba([ 0, 0, 0, 0 ]);
+
+    /// A frame of a test image: its pixels, where it goes, and how it's
+    /// disposed of and blended, as the numbers the formats use for them.
+    struct Part {
+        image: RgbaImage,
+        pos: (u32, u32),
+        dispose: u8,
+        blend: u8,
+    }
+
+    fn part(size: (u32, u32), color: Rgba<u8>, pos: (u32, u32), dispose: u8, blend: u8) -> Part {
+        Part { image: RgbaImage::from_pixel(size.0, size.1, color), pos, dispose, blend }
+    }
+
+    fn pixels(frame: &Frame, points: &[(u32, u32)]) -> Vec<Rgba<u8>> {
+        points.iter().map(|&(x, y)| *frame.image.get_pixel(x, y)).collect()
+    }
+
+    /// Encodes a 4x4 GIF. GIFs only blend over, so `blend` is ignored.
+    fn gif(parts: &[Part]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut bytes, 4, 4, &[]).unwrap();
+            for part in parts {
+                let mut pixels = part.image.clone().into_raw();
+                let mut frame = gif::Frame::from_rgba_speed(part.image.width() as u16, part.image.height() as u16, &mut pixels, 10);
+                frame.left = part.pos.0 as u16;
+                frame.top = part.pos.1 as u16;
+                frame.delay = 5;
+                frame.dispose = match part.dispose {
+                    1 => gif::DisposalMethod::Background,
+                    2 => gif::DisposalMethod::Previous,
+                    _ => gif::DisposalMethod::Keep,
+                };
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+        bytes
+    }
+
+    /// Encodes a 4x4 APNG, with `default` as the image that viewers without
+    /// APNG support show instead. Without one, the first part has to cover
+    /// the whole image.
+    fn apng(default: Option<&RgbaImage>, parts: &[Part]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, 4, 4);
+            encoder.set_color(png::ColorType::RGBA);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+
+            let mut control = (parts.len() as u32).to_be_bytes().to_vec();
+            control.extend_from_slice(&0u32.to_be_bytes());
+            writer.write_chunk(*b"acTL", &control).unwrap();
+            if let Some(default) = default { writer.write_image_data(default.as_raw()).unwrap(); }
+
+            let mut sequence = 0u32;
+            for (index, part) in parts.iter().enumerate() {
+                let mut control = sequence.to_be_bytes().to_vec();
+                for value in [ part.image.width(), part.image.height(), part.pos.0, part.pos.1 ].iter() {
+                    control.extend_from_slice(&value.to_be_bytes());
+                }
+                // 50 milliseconds, and then the first one has none at all.
+                let (num, den) = if index == 0 { (0u16, 0u16) } else { (50, 1000) };
+                control.extend_from_slice(&num.to_be_bytes());
+                control.extend_from_slice(&den.to_be_bytes());
+                control.extend_from_slice(&[ part.dispose, part.blend ]);
+                writer.write_chunk(*b"fcTL", &control).unwrap();
+                sequence += 1;
+
+                if index == 0 && default.is_none() {
+                    writer.write_image_data(part.image.as_raw()).unwrap();
+                } else {
+                    let mut data = sequence.to_be_bytes().to_vec();
+                    data.extend(compress(&part.image).unwrap());
+                    writer.write_chunk(*b"fdAT", &data).unwrap();
+                    sequence += 1;
+                }
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn blends_over_what_is_under() {
+        assert_eq!(over(Rgba([ 0, 0, 255, 128 ]), RED), Rgba([ 127, 0, 128, 255 ]));
+        assert_eq!(over(Rgba([ 255, 0, 0, 128 ]), Rgba([ 0, 0, 255, 128 ])), Rgba([ 170, 0, 84, 191 ]));
+        assert_eq!(over(Rgba([ 0, 0, 255, 128 ]), CLEAR), Rgba([ 0, 0, 255, 128 ]));
+        assert_eq!(over(CLEAR, RED), RED);
+        assert_eq!(over(GREEN, RED), GREEN);
+    }
+
+    #[test]
+    fn disposes_of_gif_frames() {
+        let mut corner = RgbaImage::from_pixel(2, 2, GREEN);
+        corner.put_pixel(0, 0, CLEAR);
+        let bytes = gif(&[
+            part((4, 4), RED, (0, 0), 0, 1),
+            // Put back how it was before, once the next frame comes.
+            part((2, 2), BLUE, (0, 0), 2, 1),
+            // Cleared once the next frame comes.
+            Part { image: corner, pos: (2, 2), dispose: 1, blend: 1 },
+            part((1, 1), BLUE, (0, 0), 0, 1),
+        ]);
+        assert!(is_animated(&bytes));
+        let frames = decode(&bytes).unwrap();
+        assert_eq!(frames.len(), 4);
+        assert!(frames.iter().all(|frame| frame.delay == 50 && frame.image.dimensions() == (4, 4)));
+
+        let points = [ (0, 0), (1, 1), (2, 2), (3, 3) ];
+        assert_eq!(pixels(&frames[0], &points), [ RED, RED, RED, RED ]);
+        assert_eq!(pixels(&frames[1], &points), [ BLUE, BLUE, RED, RED ]);
+        // The transparent pixel shows the red that was under it.
+        assert_eq!(pixels(&frames[2], &points), [ RED, RED, RED, GREEN ]);
+        assert_eq!(pixels(&frames[3], &points), [ BLUE, RED, CLEAR, CLEAR ]);
+    }
+
+    #[test]
+    fn disposes_and_blends_apng_frames() {
+        let default = RgbaImage::from_pixel(4, 4, GREEN);
+        let bytes = apng(Some(&default), &[
+            part((4, 4), RED, (0, 0), 0, 0),
+            // Drawn over the red, and then taken away again.
+            part((2, 2), Rgba([ 0, 0, 255, 128 ]), (1, 1), 2, 1),
+            // Replaces what's under it, and is cleared afterwards.
+            part((2, 2), CLEAR, (2, 2), 1, 0),
+            part((1, 1), GREEN, (0, 0), 0, 1),
+        ]);
+        assert!(is_animated(&bytes));
+        let frames = decode(&bytes).unwrap();
+
+        // The default image isn't part of the animation.
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames.iter().map(|frame| frame.delay).collect::<Vec<_>>(), [ DEFAULT_DELAY, 50, 50, 50 ]);
+
+        let points = [ (0, 0), (1, 1), (2, 2), (3, 3) ];
+        let purple = Rgba([ 127, 0, 128, 255 ]);
+        assert_eq!(pixels(&frames[0], &points), [ RED, RED, RED, RED ]);
+        assert_eq!(pixels(&frames[1], &points), [ RED, purple, purple, RED ]);
+        assert_eq!(pixels(&frames[2], &points), [ RED, RED, CLEAR, CLEAR ]);
+        assert_eq!(pixels(&frames[3], &points), [ GREEN, RED, CLEAR, CLEAR ]);
+    }
+
+    #[test]
+    fn clears_apngs_that_start_by_going_back() {
+        let bytes = apng(None, &[
+            part((4, 4), RED, (0, 0), 2, 0),
+            part((1, 1), BLUE, (3, 3), 0, 1),
+        ]);
+        let frames = decode(&bytes).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(pixels(&frames[0], &[ (0, 0), (3, 3) ]), [ RED, RED ]);
+        assert_eq!(pixels(&frames[1], &[ (0, 0), (3, 3) ]), [ CLEAR, BLUE ]);
+    }
+
+    #[test]
+    fn round_trips_previews() {
+        let frames = vec![
+            Frame { image: RgbaImage::from_pixel(3, 2, RED), delay: 120 },
+            Frame { image: RgbaImage::from_pixel(3, 2, BLUE), delay: 40 },
+        ];
+        for bytes in [ encode_gif(&frames).unwrap(), encode_png(&frames).unwrap() ].iter() {
+            let decoded = decode(bytes).unwrap();
+            assert_eq!(decoded.len(), 2);
+            for (before, after) in frames.iter().zip(decoded.iter()) {
+                assert_eq!(before.image, after.image);
+                assert_eq!(before.delay, after.delay);
+            }
+        }
+    }
+}
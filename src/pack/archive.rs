@@ -0,0 +1,226 @@
+//! Reads and writes pack archives.
+//!
+//! A pack archive is a zip file with a `.sharkpack` extension, holding the
+//! files of any other kind of pack as if it were its directory, along with a
+//! manifest (see `manifest`) that lists the hash of every file in it:
+//!
+//! ```toml
+//! format_version = 2
+//!
+//! [files]
+//! "frame01.png" = "sha256:9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+//! "frame02.png" = "sha256:60303ae22b998861bce3b28f33eec1be758a213c86c93c076dbe9f558c11c752"
+//! ```
+//!
+//! Archives with files that are missing, unlisted or don't match their hash
+//! are refused, so a pack that got cut off or mangled on its way from one
+//! person to another fails up front rather than showing broken frames.
+//!
+//! Archives are read into memory in one go, and loaders read from them
+//! through `Files`, so nothing is unpacked to disk to load them.
+
+use sha2;
+use toml;
+use zip;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{ Cursor, Read, Write };
+use std::path::{ Path, PathBuf };
+
+use sha2::{ Digest, Sha256 };
+use zip::{ CompressionMethod, ZipArchive, ZipWriter };
+use zip::result::ZipResult;
+use zip::write::FileOptions;
+
+use super::{ AssetError, Files };
+use super::manifest::{ self, Manifest, FORMAT_VERSION };
+
+pub const EXTENSION: &str = "sharkpack";
+
+/// The biggest a file in an archive can be once it's decompressed. Zip files
+/// say how big their files are, but that can't be trusted until they've
+/// actually been read, and a tiny archive can decompress to gigabytes.
+const MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Returns whether `path` is a pack archive.
+pub fn is_archive(path: &Path) -> bool {
+    path.is_file() && path.extension().is_some_and(|extension| extension == EXTENSION)
+}
+
+/// Reads the archive at `path`, and checks its files against its manifest.
+pub fn open(path: &Path) -> Result<Files, AssetError> {
+    Ok(Files::Archive {
+        root: path.to_owned(),
+        entries: read(path)?
+            .into_iter()
+            .map(|(name, bytes)| (path.join(name), bytes))
+            .collect(),
+    })
+}
+
+/// Reads every file in the archive at `path`, keyed by their path inside of
+/// it, and checks them against the manifest.
+fn read(path: &Path) -> Result<BTreeMap<String, Vec<u8>>, AssetError> {
+    let bytes = fs::read(path).map_err(|error| AssetError::read(path, error))?;
+    let mut archive = ZipArchive::new(Cursor::new(bytes))
+        .map_err(|error| AssetError::invalid(path, error))?;
+
+    let mut contents = BTreeMap::new();
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index).map_err(|error| AssetError::read(path, error))?;
+        if file.is_dir() { continue; }
+        // Don't let a file called something like `../../oops` anywhere
+        // near the rest of the file system.
+        if file.enclosed_name().is_none() {
+            return Err(AssetError::invalid(path, format!("\"{}\" isn't a valid path.", file.name())));
+        }
+        let name = file.name().to_owned();
+        let too_big = || AssetError::invalid(path, format!(
+            "\"{}\" is bigger than {} MiB, which is too big for a pack.",
+            name,
+            MAX_FILE_SIZE / 1024 / 1024));
+        if file.size() > MAX_FILE_SIZE { return Err(too_big()); }
+        // Read one byte past the limit, to tell files that are exactly at it
+        // from ones that are over it.
+        let mut bytes = Vec::new();
+        file.by_ref()
+            .take(MAX_FILE_SIZE + 1)
+            .read_to_end(&mut bytes)
+            .map_err(|error| AssetError::read(path.join(&name), error))?;
+        if bytes.len() as u64 > MAX_FILE_SIZE { return Err(too_big()); }
+        contents.insert(name, bytes);
+    }
+
+    let text = contents
+        .get(manifest::FILE)
+        .ok_or_else(|| AssetError::invalid(path, format!("There isn't a {} in it.", manifest::FILE)))?;
+    let text = String::from_utf8_lossy(text);
+    let (manifest, _) = manifest::parse(&path.join(manifest::FILE), &text)?;
+
+    for (name, expected) in manifest.files.iter() {
+        let bytes = contents.get(name).ok_or_else(|| AssetError::invalid(path, format!(
+            "\"{}\" is listed in the manifest, but it isn't in the archive.",
+            name)))?;
+        if !expected.starts_with("sha256:") {
+            return Err(AssetError::invalid(path, format!(
+                "The hash of \"{}\" isn't a SHA-256 hash, which is the only kind that can be checked.",
+                name)));
+        }
+        if !hash(bytes).eq_ignore_ascii_case(expected) {
+            return Err(AssetError::invalid(path, format!(
+                "\"{}\" doesn't match its hash in the manifest, so the archive is probably damaged.",
+                name)));
+        }
+    }
+
+    let unlisted = contents
+        .keys()
+        .find(|name| *name != manifest::FILE && !manifest.files.contains_key(*name));
+    if let Some(name) = unlisted {
+        return Err(AssetError::invalid(path, format!(
+            "\"{}\" is in the archive, but it isn't listed in the manifest.",
+            name)));
+    }
+
+    Ok(contents)
+}
+
+/// Packs the directory at `dir` into an archive at `out`, with a manifest of
+/// the files in it. If the directory already has a manifest, the rest of it
+/// is kept, upgraded to the current version.
+pub fn create(dir: &Path, out: &Path) -> Result<(), AssetError> {
+    if !dir.is_dir() {
+        return Err(AssetError::invalid(dir, "Only a pack's directory can be made into an archive."));
+    }
+
+    let mut paths = Vec::new();
+    walk(dir, &mut paths).map_err(|error| AssetError::read(dir, error))?;
+
+    let mut files = BTreeMap::new();
+    for path in paths.into_iter() {
+        let name = path
+            .strip_prefix(dir)
+            .unwrap_or(&path)
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<String>>()
+            .join("/");
+        if name == manifest::FILE { continue; }
+        let bytes = fs::read(&path).map_err(|error| AssetError::read(&path, error))?;
+        files.insert(name, bytes);
+    }
+
+    let mut manifest = if manifest::detect(&Files::Disk, dir) {
+        manifest::read(&Files::Disk, dir)?.0
+    } else {
+        Manifest {
+            format_version: FORMAT_VERSION,
+            facing: None,
+            anchor: None,
+            anchors: BTreeMap::new(),
+            filter: None,
+            animations: Vec::new(),
+            accessories: Vec::new(),
+            emitters: Vec::new(),
+            events: Vec::new(),
+            shader: None,
+            motion: None,
+            files: BTreeMap::new(),
+        }
+    };
+    manifest.files = files.iter().map(|(name, bytes)| (name.clone(), hash(bytes))).collect();
+    let manifest = toml::to_string(&manifest).map_err(|error| AssetError::invalid(out, error))?;
+
+    let archive = write(manifest.as_bytes(), &files).map_err(|error| AssetError::invalid(out, error))?;
+    fs::write(out, archive).map_err(|error| AssetError::read(out, error))
+}
+
+/// Zips up `files` with `manifest` in front of them.
+fn write(manifest: &[u8], files: &BTreeMap<String, Vec<u8>>) -> ZipResult<Vec<u8>> {
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    writer.start_file(manifest::FILE, options)?;
+    writer.write_all(manifest)?;
+    for (name, bytes) in files.iter() {
+        writer.start_file(name.as_str(), options)?;
+        writer.write_all(bytes)?;
+    }
+    Ok(writer.finish()?.into_inner())
+}
+
+/// Unpacks the archive at `path` into the directory `out`, which must not
+/// exist yet.
+pub fn extract(path: &Path, out: &Path) -> Result<(), AssetError> {
+    let contents = read(path)?;
+
+    fs::create_dir(out).map_err(|error| AssetError::read(out, error))?;
+
+    let written = contents.iter().try_for_each(|(name, bytes)| {
+        let file = out.join(name);
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent).map_err(|error| AssetError::read(parent, error))?;
+        }
+        fs::write(&file, bytes).map_err(|error| AssetError::read(&file, error))
+    });
+
+    // Don't leave half of a pack behind.
+    if written.is_err() { let _ = fs::remove_dir_all(out); }
+    written
+}
+
+/// Returns the SHA-256 hash of `bytes`, as it's written in a manifest.
+pub fn hash(bytes: &[u8]) -> String {
+    let hex = Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+    format!("sha256:{}", hex)
+}
+
+/// Collects the paths of every file in `dir` and the directories inside of
+/// it.
+fn walk(dir: &Path, paths: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() { walk(&path, paths)?; } else { paths.push(path); }
+    }
+    Ok(())
+}
@@ -0,0 +1,423 @@
+//! Imports sprite sheets exported by Aseprite.
+//!
+//! Aseprite's "Export Sprite Sheet" writes the sheet as a single image, and
+//! a JSON file describing where each frame is in it, how long it's shown
+//! for, and the tags that group frames into animations. Each tag becomes an
+//! animation with the same name. Sheets without tags are played as a single
+//! looping animation.
+//!
+//! Frames can be trimmed, meaning the transparent border around them was cut
+//! off to save space, or rotated by a quarter turn to pack them more tightly
+//! (Aseprite never does this, but other tools that write the same format
+//! do). Both are undone when the frames are cut out of the sheet.
+
+use serde::Deserialize;
+use serde_json;
+
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::path::{ Path, PathBuf };
+
+use serde_json::{ Map, Value };
+
+use crate::motion::Motion;
+
+use super::{ Animation, AnimationFrame, AnimationKind, AssetError, Facing, Files, FrameSource, Pack, Sheet };
+
+/// How long frames are shown for when the JSON doesn't say, in milliseconds.
+const DEFAULT_DURATION: u32 = 100;
+
+/// Tags can repeat their frames, so put a limit on how long they can get.
+const MAX_FRAMES: usize = 2000;
+
+/// The keys Aseprite writes, including the ones that aren't used. Anything
+/// else is probably a typo, and is listed in `Pack::warnings`.
+const DOCUMENT_KEYS: &[&str] = &[ "frames", "meta" ];
+const FRAME_KEYS: &[&str] = &[
+    "filename", "frame", "rotated", "trimmed", "spriteSourceSize", "sourceSize", "duration",
+];
+const META_KEYS: &[&str] = &[
+    "app", "version", "image", "format", "size", "scale", "frameTags", "layers", "slices",
+];
+const TAG_KEYS: &[&str] = &[ "name", "from", "to", "direction", "color", "repeat", "data" ];
+
+#[derive(Debug, Deserialize)]
+struct Document {
+    frames: Frames,
+    meta: Meta,
+}
+
+/// Frames are either a list, or an object with the file name of each frame
+/// as its key, depending on the export settings.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Frames {
+    Array(Vec<Frame>),
+    Hash(Map<String, Value>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Frame {
+    /// Where the frame is in the sheet, before it's rotated back.
+    frame: Rect,
+    #[serde(default)]
+    rotated: bool,
+    /// Where the trimmed frame goes in the untrimmed one.
+    sprite_source_size: Option<Rect>,
+    /// The size of the frame before it was trimmed.
+    source_size: Option<Size>,
+    duration: Option<u32>,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+struct Rect {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+struct Size {
+    w: u32,
+    h: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Meta {
+    /// The sheet, relative to the JSON file.
+    image: String,
+    #[serde(default)]
+    frame_tags: Vec<Tag>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Tag {
+    name: String,
+    from: usize,
+    to: usize,
+    #[serde(default)]
+    direction: String,
+    /// How many times the tag plays. Aseprite writes this as a string.
+    repeat: Option<Value>,
+}
+
+/// Returns whether `path` is an Aseprite sprite sheet, either a JSON file or
+/// a directory with one in it.
+pub fn detect(files: &Files, path: &Path) -> bool {
+    find_json(files, path).is_some()
+}
+
+/// Finds the JSON file Aseprite wrote, which is either `path` itself or the
+/// first one in it. Other JSON files are skipped, so the file has to have
+/// the `frames` and `meta.image` that every export does.
+fn find_json(files: &Files, path: &Path) -> Option<PathBuf> {
+    if files.is_file(path) {
+        return if is_export(files, path) { Some(path.to_owned()) } else { None };
+    }
+    let mut paths = files.list(path)
+        .ok()?
+        .into_iter()
+        .filter(|path| is_export(files, path))
+        .collect::<Vec<PathBuf>>();
+    paths.sort();
+    paths.into_iter().next()
+}
+
+fn is_export(files: &Files, path: &Path) -> bool {
+    if !files.is_file(path) || path.extension().is_none_or(|extension| extension != "json") {
+        return false;
+    }
+    files.read_to_string(path)
+        .ok()
+        .and_then(|text| serde_json::from_str::<Value>(&text).ok())
+        .is_some_and(|value| {
+            value.get("frames").is_some() && value.pointer("/meta/image").is_some_and(Value::is_string)
+        })
+}
+
+pub fn load(name: &str, files: &Files, path: &Path) -> Result<Pack, AssetError> {
+    let file = find_json(files, path)
+        .ok_or_else(|| AssetError::invalid(path, "There isn't a JSON file from Aseprite in it."))?;
+    let text = files.read_to_string(&file)
+        .map_err(|error| AssetError::read(&file, error))?;
+    let value = serde_json::from_str::<Value>(&text)
+        .map_err(|error| AssetError::invalid(&file, error))?;
+    let mut warnings = unknown_keys(&value);
+    let document = serde_json::from_value::<Document>(value)
+        .map_err(|error| AssetError::invalid(&file, error))?;
+
+    let image = file.parent().unwrap_or_else(|| Path::new(".")).join(&document.meta.image);
+    let bytes = files.read(&image)
+        .map_err(|error| AssetError::read(&image, error))?;
+
+    let list = match document.frames {
+        Frames::Array(list) => list,
+        Frames::Hash(map) => map
+            .into_iter()
+            .map(|(key, value)| serde_json::from_value::<Frame>(value)
+                .map_err(|error| AssetError::invalid(&file, format!("Frame \"{}\": {}", key, error))))
+            .collect::<Result<Vec<Frame>, AssetError>>()?,
+    };
+
+    if list.is_empty() {
+        return Err(AssetError::invalid(&file, "There aren't any frames in it."));
+    }
+
+    let frames = list
+        .iter()
+        .map(|frame| {
+            let Rect { x, y, w, h } = frame.frame;
+            // Rotated frames are stored turned a quarter turn clockwise, but
+            // their size is given the way they're shown.
+            let rect = if frame.rotated { (x, y, h, w) } else { (x, y, w, h) };
+            let size = frame.source_size.map_or((w, h), |size| (size.w, size.h));
+            let offset = frame.sprite_source_size.map_or((0, 0), |source| (source.x, source.y));
+            FrameSource::Sprite { sheet: 0, rect, rotated: frame.rotated, offset, size }
+        })
+        .collect::<Vec<FrameSource>>();
+
+    let frame_size = list[0].source_size.map_or((list[0].frame.w, list[0].frame.h), |size| (size.w, size.h));
+    let durations = list
+        .iter()
+        .map(|frame| frame.duration.filter(|duration| *duration > 0).unwrap_or(DEFAULT_DURATION))
+        .collect::<Vec<u32>>();
+
+    let mut animations = Vec::new();
+
+    for tag in document.meta.frame_tags.iter() {
+        if tag.from > tag.to || tag.to >= list.len() {
+            warnings.push(format!(
+                "Tag \"{}\" covers frames {} to {}, but there are only {}, so it was skipped.",
+                tag.name,
+                tag.from,
+                tag.to,
+                list.len()));
+            continue;
+        }
+
+        let forward = (tag.from..=tag.to).collect::<Vec<usize>>();
+        let backward = forward.iter().rev().copied().collect::<Vec<usize>>();
+        // Ping-pong doesn't show the frames it turns around on twice.
+        let bounce = |there: &[usize], back: &[usize]| {
+            let mut order = there.to_vec();
+            if back.len() > 2 { order.extend_from_slice(&back[1..back.len() - 1]); }
+            order
+        };
+        let order = match tag.direction.as_str() {
+            "" | "forward" => forward,
+            "reverse" => backward,
+            "pingpong" => bounce(&forward, &backward),
+            "pingpong_reverse" => bounce(&backward, &forward),
+            direction => {
+                warnings.push(format!(
+                    "Tag \"{}\" plays {}, which isn't supported, so it plays forward.",
+                    tag.name,
+                    direction));
+                forward
+            },
+        };
+
+        let repeat = match &tag.repeat {
+            Some(Value::String(repeat)) => repeat.trim().parse::<usize>().ok(),
+            Some(Value::Number(repeat)) => repeat.as_u64().map(|repeat| repeat as usize),
+            _ => None,
+        }.unwrap_or(1).max(1);
+
+        let mut order = order.repeat(repeat);
+        order.truncate(MAX_FRAMES);
+
+        animations.push(animation(&tag.name, &order, &durations, frame_size));
+    }
+
+    if animations.is_empty() {
+        let order = (0..list.len()).collect::<Vec<usize>>();
+        animations.push(animation("default", &order, &durations, frame_size));
+    }
+
+    Ok(Pack {
+        name: name.to_owned(),
+        frames,
+        sheets: vec![ Sheet { file: image, bytes: Cow::Owned(bytes), key: None } ],
+        frame_size,
+        anchor: super::default_anchor(frame_size),
+        animations,
+        layers: Vec::new(),
+        accessories: Vec::new(),
+        emitters: Vec::new(),
+        events: Vec::new(),
+        shader: None,
+        filter: None,
+        motion: Motion::default(),
+        facing: Facing::Either,
+        warnings,
+    })
+}
+
+/// Lists the keys in `document` that Aseprite doesn't write, once each.
+fn unknown_keys(document: &Value) -> Vec<String> {
+    let mut unknown = BTreeSet::new();
+    let mut check = |value: Option<&Value>, known: &[&str], place: &str| {
+        if let Some(Value::Object(object)) = value {
+            for key in object.keys().filter(|key| !known.contains(&key.as_str())) {
+                unknown.insert((place.to_owned(), key.clone()));
+            }
+        }
+    };
+
+    check(Some(document), DOCUMENT_KEYS, "the document");
+    match document.get("frames") {
+        Some(Value::Array(frames)) => for frame in frames {
+            check(Some(frame), FRAME_KEYS, "a frame");
+        },
+        Some(Value::Object(frames)) => for frame in frames.values() {
+            check(Some(frame), FRAME_KEYS, "a frame");
+        },
+        _ => (),
+    }
+    check(document.get("meta"), META_KEYS, "meta");
+    if let Some(Value::Array(tags)) = document.pointer("/meta/frameTags") {
+        for tag in tags {
+            check(Some(tag), TAG_KEYS, "a tag");
+        }
+    }
+
+    unknown
+        .into_iter()
+        .map(|(place, key)| format!("Unknown key \"{}\" in {}, so it was ignored.", key, place))
+        .collect()
+}
+
+fn animation(name: &str, order: &[usize], durations: &[u32], frame_size: (u32, u32)) -> Animation {
+    let kind = AnimationKind::guess(name);
+    let speed = if kind == AnimationKind::Ground { super::default_speed(frame_size.1) } else { 0.0 };
+    Animation {
+        name: name.to_owned(),
+        kind,
+        frames: order
+            .iter()
+            .map(|&frame| AnimationFrame {
+                frame,
+                duration: durations[frame],
+                velocity: (speed, 0.0),
+                anchor: None,
+            })
+            .collect(),
+        weight: if kind == AnimationKind::Ground { 1 } else { 0 },
+        next: Vec::new(),
+        border: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pack::files::fixture;
+
+    fn sheet(json: &str) -> Files {
+        fixture::files(&[
+            ("sheet.json", json.as_bytes().to_vec()),
+            ("sheet.png", fixture::png(64, 32)),
+        ])
+    }
+
+    fn order(animation: &Animation) -> Vec<usize> {
+        animation.frames.iter().map(|frame| frame.frame).collect()
+    }
+
+    #[test]
+    fn reads_a_list_of_frames_and_tags() {
+        let files = sheet(r#"{
+            "frames": [
+                { "frame": { "x": 0, "y": 0, "w": 16, "h": 16 }, "duration": 80 },
+                { "frame": { "x": 16, "y": 0, "w": 16, "h": 16 }, "duration": 120 },
+                { "frame": { "x": 32, "y": 0, "w": 16, "h": 16 } },
+                { "frame": { "x": 48, "y": 0, "w": 16, "h": 16 }, "duration": 0 }
+            ],
+            "meta": {
+                "image": "sheet.png",
+                "frameTags": [
+                    { "name": "walk", "from": 0, "to": 3, "direction": "pingpong" },
+                    { "name": "wave", "from": 1, "to": 2, "direction": "reverse", "repeat": "2" },
+                    { "name": "fall", "from": 3, "to": 3 },
+                    { "name": "broken", "from": 2, "to": 9 }
+                ]
+            }
+        }"#);
+        assert!(detect(&files, &fixture::root()));
+        let pack = load("sheet", &files, &fixture::root()).unwrap();
+
+        assert_eq!(pack.frame_size, (16, 16));
+        let names = pack.animations.iter().map(|animation| animation.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, [ "walk", "wave", "fall" ]);
+        assert_eq!(order(&pack.animations[0]), [ 0, 1, 2, 3, 2, 1 ]);
+        assert_eq!(order(&pack.animations[1]), [ 2, 1, 2, 1 ]);
+        assert_eq!(pack.animations[2].kind, AnimationKind::Fall);
+
+        let durations = pack.animations[0].frames.iter().map(|frame| frame.duration).collect::<Vec<_>>();
+        assert_eq!(durations, [ 80, 120, DEFAULT_DURATION, DEFAULT_DURATION, DEFAULT_DURATION, 120 ]);
+        assert!(pack.warnings.iter().any(|warning| warning.contains("\"broken\"")));
+    }
+
+    #[test]
+    fn reads_trimmed_and_rotated_frames() {
+        let files = sheet(r#"{
+            "frames": {
+                "sheet 0.aseprite": {
+                    "frame": { "x": 0, "y": 0, "w": 10, "h": 12 },
+                    "rotated": false,
+                    "trimmed": true,
+                    "spriteSourceSize": { "x": 3, "y": 4, "w": 10, "h": 12 },
+                    "sourceSize": { "w": 16, "h": 16 }
+                },
+                "sheet 1.aseprite": {
+                    "frame": { "x": 10, "y": 0, "w": 8, "h": 6 },
+                    "rotated": true,
+                    "sourceSize": { "w": 16, "h": 16 }
+                }
+            },
+            "meta": { "image": "sheet.png" }
+        }"#);
+        let pack = load("sheet", &files, &fixture::root()).unwrap();
+
+        assert_eq!(pack.frame_size, (16, 16));
+        assert_eq!(pack.animations.len(), 1, "sheets without tags play as one animation");
+        assert_eq!(order(&pack.animations[0]), [ 0, 1 ]);
+        match pack.frames[0] {
+            FrameSource::Sprite { rect, rotated, offset, size, .. } => {
+                assert_eq!((rect, rotated, offset, size), ((0, 0, 10, 12), false, (3, 4), (16, 16)));
+            },
+            _ => panic!("frames should be cut out of the sheet"),
+        }
+        match pack.frames[1] {
+            FrameSource::Sprite { rect, rotated, .. } => assert_eq!((rect, rotated), ((10, 0, 6, 8), true)),
+            _ => panic!("frames should be cut out of the sheet"),
+        }
+    }
+
+    #[test]
+    fn lists_unknown_keys() {
+        let files = sheet(r#"{
+            "frames": [ { "frame": { "x": 0, "y": 0, "w": 16, "h": 16 }, "durration": 100 } ],
+            "meta": { "image": "sheet.png", "app": "https://www.aseprite.org/", "colour": 1 }
+        }"#);
+        let pack = load("sheet", &files, &fixture::root()).unwrap();
+
+        assert_eq!(pack.warnings, [
+            "Unknown key \"durration\" in a frame, so it was ignored.",
+            "Unknown key \"colour\" in meta, so it was ignored.",
+        ]);
+    }
+
+    #[test]
+    fn ignores_other_json_files() {
+        let files = fixture::files(&[
+            ("package.json", br#"{ "name": "not a sprite sheet" }"#.to_vec()),
+            ("frames.json", br#"{ "frames": [], "meta": {} }"#.to_vec()),
+        ]);
+        assert!(!detect(&files, &fixture::root()));
+        assert!(!detect(&files, &fixture::root().join("package.json")));
+    }
+}
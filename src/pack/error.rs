@@ -0,0 +1,45 @@
+use failure;
+
+use std::fmt::Display;
+use std::path::Path;
+
+use failure::Fail;
+
+/// Something that went wrong while loading a pack or its frames. Each of
+/// them names the file that caused it, so that the user knows what to fix.
+#[derive(Debug, Clone, Fail)]
+pub enum AssetError {
+    /// There is no pack with that name installed.
+    #[fail(display = "There is no pack called \"{}\" in {}.", name, dir)]
+    NotFound { name: String, dir: String },
+    /// A file couldn't be read at all, or is missing.
+    #[fail(display = "Failed to read {}: {}", file, reason)]
+    Read { file: String, reason: String },
+    /// A file was read, but doesn't describe a pack that can be used.
+    #[fail(display = "{}: {}", file, reason)]
+    Invalid { file: String, reason: String },
+    /// An image couldn't be decoded, or a frame couldn't be cut out of it.
+    #[fail(display = "Failed to decode {}: {}", file, reason)]
+    Decode { file: String, reason: String },
+    /// A frame was decoded, but couldn't be uploaded to the GPU.
+    #[fail(display = "Failed to upload frame {} to the GPU: {}", frame, reason)]
+    Upload { frame: usize, reason: String },
+    /// A frame hasn't finished loading yet, or the thread loading it went
+    /// away before it was done.
+    #[fail(display = "Frame {} hasn't finished loading.", frame)]
+    Unfinished { frame: usize },
+}
+
+impl AssetError {
+    pub fn read<P: AsRef<Path>, R: Display>(file: P, reason: R) -> Self {
+        AssetError::Read { file: file.as_ref().display().to_string(), reason: reason.to_string() }
+    }
+
+    pub fn invalid<P: AsRef<Path>, R: Display>(file: P, reason: R) -> Self {
+        AssetError::Invalid { file: file.as_ref().display().to_string(), reason: reason.to_string() }
+    }
+
+    pub fn decode<P: AsRef<Path>, R: Display>(file: P, reason: R) -> Self {
+        AssetError::Decode { file: file.as_ref().display().to_string(), reason: reason.to_string() }
+    }
+}
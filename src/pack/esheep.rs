@@ -0,0 +1,460 @@
+//! Imports eSheep (desktopPet) packs.
+//!
+//! An eSheep pack is a single `animations.xml`, holding a base64 encoded
+//! sprite sheet, the grid of tiles it's cut into, and the animations. Each
+//! animation moves the pet by some amount every frame, and lists the
+//! animations that can follow it with their probabilities.
+//!
+//! Values in eSheep files can be expressions like `random*10`, which can't
+//! be imported. Those fall back to a default, and are listed in
+//! `Pack::warnings`.
+
+use base64;
+use roxmltree;
+
+use std::borrow::Cow;
+use std::collections::{ HashMap, HashSet };
+use std::path::Path;
+
+use roxmltree::{ Document, Node };
+
+use crate::motion::Motion;
+
+use super::{ Animation, AnimationFrame, AnimationKind, AssetError, Element, Facing, Files, FrameSource, Pack, Sheet };
+
+/// Sequences can repeat themselves a lot, and each repetition is unrolled
+/// into frames, so put a limit on how long they can get.
+const MAX_FRAMES: usize = 2000;
+
+/// What can be in an eSheep pack. `<animations>` is both the root and the
+/// list of animations in it, so it can have what either of those have.
+const ELEMENTS: &[Element] = &[
+    ("animations", &[ "header", "image", "spawns", "animations", "childs", "sounds", "animation" ], None),
+    ("header", &[ "author", "title", "petname", "version", "info", "application", "icon" ], Some(&[])),
+    ("image", &[ "tilesx", "tilesy", "png", "transparency" ], Some(&[])),
+    ("spawns", &[ "spawn" ], Some(&[])),
+    ("spawn", &[ "x", "y", "next" ], Some(&[ "id", "probability" ])),
+    ("animation", &[ "name", "start", "end", "sequence", "border", "gravity" ], Some(&[ "id" ])),
+    ("start", &[ "x", "y", "offsety", "opacity", "interval" ], Some(&[])),
+    ("end", &[ "x", "y", "offsety", "opacity", "interval" ], Some(&[])),
+    ("sequence", &[ "frame", "next", "action" ], Some(&[ "repeat", "repeatfrom" ])),
+    ("border", &[ "next" ], Some(&[])),
+    ("gravity", &[ "next" ], Some(&[])),
+    ("next", &[], Some(&[ "probability", "only" ])),
+    ("childs", &[ "child" ], Some(&[])),
+    ("child", &[ "x", "y", "next" ], Some(&[ "animationid" ])),
+    ("sounds", &[ "sound" ], Some(&[])),
+    ("sound", &[ "probability", "loop", "base64" ], Some(&[ "animationid" ])),
+];
+
+/// Returns whether `path` is an eSheep pack, either an XML file or a
+/// directory with an `animations.xml` in it. Other XML files are left alone,
+/// so the file has to have the `<animations>` root that eSheep packs have.
+pub fn detect(files: &Files, path: &Path) -> bool {
+    let file = if files.is_dir(path) {
+        path.join("animations.xml")
+    } else if path.extension().is_some_and(|extension| extension == "xml") {
+        path.to_owned()
+    } else {
+        return false;
+    };
+    files.read_to_string(&file)
+        .ok()
+        .is_some_and(|text| Document::parse(&text)
+            .is_ok_and(|document| document.root_element().has_tag_name("animations")))
+}
+
+pub fn load(name: &str, files: &Files, path: &Path) -> Result<Pack, AssetError> {
+    let file = if files.is_dir(path) { path.join("animations.xml") } else { path.to_owned() };
+    let text = files.read_to_string(&file)
+        .map_err(|error| AssetError::read(&file, error))?;
+    let document = Document::parse(&text)
+        .map_err(|error| AssetError::invalid(&file, error))?;
+    let root = document.root_element();
+
+    let mut warnings = super::unknown_xml(root, ELEMENTS);
+
+    // The sprite sheet, and the grid of tiles it's cut into.
+    let image = child(root, "image")
+        .ok_or_else(|| AssetError::invalid(&file, "There isn't an <image> in it."))?;
+    let tiles = (
+        number::<u32>(child_text(image, "tilesx")).unwrap_or(1).max(1),
+        number::<u32>(child_text(image, "tilesy")).unwrap_or(1).max(1),
+    );
+    let bytes = base64::decode(
+        child_text(image, "png")
+            .unwrap_or("")
+            .split_whitespace()
+            .collect::<String>())
+        .map_err(|error| AssetError::invalid(&file, format!("Invalid sprite sheet: {}", error)))?;
+    let sheet_size = super::frame_size(&file, &bytes)?;
+    let frame_size = (sheet_size.0 / tiles.0, sheet_size.1 / tiles.1);
+    let key = match child_text(image, "transparency").map(str::trim) {
+        None | Some("") => None,
+        Some(color) => {
+            let key = named_color(color);
+            if key.is_none() {
+                warnings.push(format!(
+                    "The transparency color \"{}\" isn't supported, so it was ignored.",
+                    color));
+            }
+            key
+        },
+    };
+
+    let mut list = child(root, "animations")
+        .map(|animations| animations
+            .children()
+            .filter(|node| node.has_tag_name("animation"))
+            .collect::<Vec<Node>>())
+        .unwrap_or_default();
+
+    // Animations refer to each other by id, but ours go by name, and eSheep
+    // names don't have to be unique. Animations without an id of their own
+    // can't be told apart from the others, so they're skipped.
+    let mut names = HashMap::<&str, String>::new();
+    list.retain(|animation| {
+        let id = animation.attribute("id").unwrap_or("").trim();
+        let name = child_text(*animation, "name").unwrap_or(id).trim();
+        if id.is_empty() || names.contains_key(id) {
+            warnings.push(format!(
+                "Animation \"{}\" {}, so it was skipped.",
+                name,
+                if id.is_empty() { "has no id".to_owned() } else { format!("has id {}, which another animation already has", id) }));
+            return false;
+        }
+        let mut unique = name.to_owned();
+        let mut count = 1;
+        while names.values().any(|other| *other == unique) {
+            unique = if count == 1 { format!("{}#{}", name, id) } else { format!("{}#{}-{}", name, id, count) };
+            count += 1;
+        }
+        names.insert(id, unique);
+        true
+    });
+
+    // The animations that any animation's <gravity> leads to are the ones to
+    // play while falling.
+    let falling = list
+        .iter()
+        .filter_map(|animation| child(*animation, "gravity"))
+        .flat_map(|gravity| gravity.children())
+        .filter(|node| node.has_tag_name("next"))
+        .filter_map(|next| next.text())
+        .map(|id| id.trim())
+        .collect::<Vec<&str>>();
+
+    // The animations spawns lead to are the ones to start out with.
+    let spawns = child(root, "spawns")
+        .map(|spawns| spawns
+            .children()
+            .filter(|node| node.has_tag_name("spawn"))
+            .flat_map(|spawn| spawn.children())
+            .filter(|node| node.has_tag_name("next"))
+            .collect::<Vec<Node>>())
+        .unwrap_or_default();
+
+    let mut frames = Vec::new();
+    // Maps tile numbers to their index in `frames`.
+    let mut loaded = HashMap::<u32, usize>::new();
+    let mut animations = Vec::new();
+
+    for node in list.iter() {
+        let id = node.attribute("id").unwrap_or("").trim();
+        let name = names[id].clone();
+
+        let sequence = match child(*node, "sequence") {
+            Some(sequence) => sequence,
+            None => {
+                warnings.push(format!("Animation \"{}\" has no <sequence>, so it was skipped.", name));
+                continue;
+            },
+        };
+
+        let tiles_used = sequence
+            .children()
+            .filter(|node| node.has_tag_name("frame"))
+            .filter_map(|frame| number::<u32>(frame.text()))
+            .collect::<Vec<u32>>();
+
+        if tiles_used.is_empty() {
+            warnings.push(format!("Animation \"{}\" has no frames, so it was skipped.", name));
+            continue;
+        }
+
+        let mut value = |node: Option<Node>, field: &str, default: f32| {
+            let text = node.and_then(|node| child_text(node, field));
+            match text.map(|text| (text, text.trim().parse::<f32>())) {
+                Some((_, Ok(value))) => value,
+                Some((text, Err(..))) => {
+                    warnings.push(format!(
+                        "Animation \"{}\" uses \"{}\" for <{}>, which isn't supported, so {} was used instead.",
+                        name,
+                        text.trim(),
+                        field,
+                        default));
+                    default
+                },
+                None => default,
+            }
+        };
+
+        let start = child(*node, "start");
+        let start = (value(start, "x", 0.0), value(start, "y", 0.0), value(start, "interval", 200.0));
+        let end = match child(*node, "end") {
+            Some(end) => (
+                value(Some(end), "x", start.0),
+                value(Some(end), "y", start.1),
+                value(Some(end), "interval", start.2),
+            ),
+            None => start,
+        };
+
+        let repeat = match sequence.attribute("repeat").map(|repeat| repeat.trim().parse::<usize>()) {
+            Some(Ok(repeat)) => repeat,
+            Some(Err(..)) => {
+                warnings.push(format!(
+                    "Animation \"{}\" uses \"{}\" for repeat, which isn't supported, so it plays once.",
+                    name,
+                    sequence.attribute("repeat").unwrap_or("")));
+                0
+            },
+            None => 0,
+        };
+        let repeat_from = number::<usize>(sequence.attribute("repeatfrom"))
+            .unwrap_or(0)
+            .min(tiles_used.len() - 1);
+
+        // Unroll the repetitions, so that movement and timing can be eased
+        // from `start` to `end` over the whole animation.
+        let mut sequence_tiles = tiles_used.clone();
+        for _ in 0..repeat {
+            if sequence_tiles.len() >= MAX_FRAMES { break; }
+            sequence_tiles.extend_from_slice(&tiles_used[repeat_from..]);
+        }
+        sequence_tiles.truncate(MAX_FRAMES);
+
+        let count = sequence_tiles.len();
+        let mut animation_frames = Vec::with_capacity(count);
+        for (index, tile) in sequence_tiles.into_iter().enumerate() {
+            let frame = *loaded.entry(tile).or_insert_with(|| {
+                frames.push(FrameSource::Tile {
+                    sheet: 0,
+                    rect: (
+                        (tile % tiles.0) * frame_size.0,
+                        (tile / tiles.0) * frame_size.1,
+                        frame_size.0,
+                        frame_size.1,
+                    ),
+                });
+                frames.len() - 1
+            });
+
+            let t = if count > 1 { (index as f32) / ((count - 1) as f32) } else { 0.0 };
+            let lerp = |a: f32, b: f32| a + (b - a) * t;
+            let (x, y, interval) = (lerp(start.0, end.0), lerp(start.1, end.1), lerp(start.2, end.2).max(1.0));
+
+            // eSheep moves the pet by x and y every frame, and pets face left,
+            // so moving left is moving forward.
+            animation_frames.push(AnimationFrame {
+                frame,
+                duration: interval as u32,
+                velocity: (-x * (1000.0 / interval), y * (1000.0 / interval)),
+                anchor: None,
+            });
+        }
+
+        let kind = if falling.contains(&id) { AnimationKind::Fall } else { AnimationKind::guess(&name) };
+
+        let weight = spawns
+            .iter()
+            .filter(|next| next.text().map(str::trim) == Some(id))
+            .map(|next| number::<u32>(next.attribute("probability")).unwrap_or(1))
+            .sum();
+
+        animations.push(Animation {
+            name: name.clone(),
+            kind,
+            frames: animation_frames,
+            weight,
+            next: transitions(Some(sequence), &names, &name, &mut warnings),
+            border: transitions(child(*node, "border"), &names, &name, &mut warnings),
+        });
+    }
+
+    if animations.is_empty() {
+        return Err(AssetError::invalid(&file, "There aren't any animations in it that can be imported."));
+    }
+
+    // Expressions tend to be repeated between <start> and <end>, so only
+    // mention each problem once.
+    let mut seen = HashSet::new();
+    warnings.retain(|warning| seen.insert(warning.clone()));
+
+    Ok(Pack {
+        name: name.to_owned(),
+        frames,
+        sheets: vec![ Sheet { file, bytes: Cow::Owned(bytes), key } ],
+        frame_size,
+        anchor: super::default_anchor(frame_size),
+        animations,
+        layers: Vec::new(),
+        accessories: Vec::new(),
+        emitters: Vec::new(),
+        events: Vec::new(),
+        shader: None,
+        filter: None,
+        motion: Motion::default(),
+        facing: Facing::Left,
+        warnings,
+    })
+}
+
+/// Reads the `<next>` elements inside of `node`. eSheep can limit these to
+/// when the pet is on a window or at a certain screen edge, but companions
+/// only ever walk along the taskbar, so those are left out.
+fn transitions(
+    node: Option<Node>,
+    names: &HashMap<&str, String>,
+    name: &str,
+    warnings: &mut Vec<String>,
+) -> Vec<(String, u32)> {
+    let mut transitions = Vec::new();
+    for next in node.iter().flat_map(|node| node.children()).filter(|node| node.has_tag_name("next")) {
+        match next.attribute("only").unwrap_or("none") {
+            "none" | "taskbar" => (),
+            only => {
+                warnings.push(format!(
+                    "Animation \"{}\" has a transition for \"{}\", which isn't supported, so it was skipped.",
+                    name,
+                    only));
+                continue;
+            },
+        }
+        let id = next.text().unwrap_or("").trim();
+        match names.get(id) {
+            Some(next_name) => transitions.push((
+                next_name.clone(),
+                number::<u32>(next.attribute("probability")).unwrap_or(1),
+            )),
+            None => warnings.push(format!(
+                "Animation \"{}\" leads to animation {}, which doesn't exist.",
+                name,
+                id)),
+        }
+    }
+    transitions
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    child(node, name).and_then(|child| child.text())
+}
+
+fn number<T: std::str::FromStr>(text: Option<&str>) -> Option<T> {
+    text.and_then(|text| text.trim().parse().ok())
+}
+
+fn named_color(name: &str) -> Option<[u8; 3]> {
+    match name.to_lowercase().as_str() {
+        "magenta" | "fuchsia" => Some([ 255, 0, 255 ]),
+        "black" => Some([ 0, 0, 0 ]),
+        "white" => Some([ 255, 255, 255 ]),
+        "lime" => Some([ 0, 255, 0 ]),
+        "cyan" | "aqua" => Some([ 0, 255, 255 ]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pack::files::fixture;
+
+    fn pet(animations: &str) -> Files {
+        let xml = format!(r#"
+            <animations>
+              <image>
+                <tilesx>2</tilesx>
+                <tilesy>1</tilesy>
+                <png>{}</png>
+                <transparency>Magenta</transparency>
+              </image>
+              <spawns>
+                <spawn id="1" probability="100"><x>0</x><y>0</y><next probability="100">1</next></spawn>
+              </spawns>
+              <animations>{}</animations>
+            </animations>
+        "#, base64::encode(fixture::png(64, 32)), animations);
+        fixture::files(&[ ("animations.xml", xml.into_bytes()) ])
+    }
+
+    #[test]
+    fn imports_animations() {
+        let files = pet(r#"
+            <animation id="1">
+              <name>walk</name>
+              <start><x>-2</x><y>0</y><interval>200</interval></start>
+              <end><x>-2</x><y>0</y><interval>200</interval></end>
+              <sequence repeat="1" repeatfrom="0"><frame>0</frame><frame>1</frame><next probability="100">1</next></sequence>
+            </animation>
+        "#);
+        assert!(detect(&files, &fixture::root()));
+        let pack = load("pet", &files, &fixture::root()).unwrap();
+
+        assert_eq!(pack.frame_size, (32, 32));
+        assert_eq!(pack.frames.len(), 2);
+        assert_eq!(pack.sheets[0].key, Some([ 255, 0, 255 ]));
+        let walk = &pack.animations[0];
+        assert_eq!(walk.frames.len(), 4, "repeats are unrolled");
+        assert_eq!(walk.frames[0].velocity, (10.0, 0.0));
+        assert_eq!(walk.weight, 100);
+        assert_eq!(walk.next, [ ("walk".to_owned(), 100) ]);
+        assert!(pack.warnings.is_empty(), "{:?}", pack.warnings);
+    }
+
+    #[test]
+    fn lists_unknown_elements_and_attributes() {
+        let files = pet(r#"
+            <animation id="1" speed="2">
+              <name>walk</name>
+              <sequence><frame>0</frame><frames>1</frames></sequence>
+            </animation>
+        "#);
+        let pack = load("pet", &files, &fixture::root()).unwrap();
+        assert_eq!(pack.warnings, [
+            "Unknown attribute \"speed\" on <animation>, so it was ignored.",
+            "Unknown element <frames> in <sequence>, so it was ignored.",
+        ]);
+    }
+
+    #[test]
+    fn skips_animations_without_their_own_id() {
+        let files = pet(r#"
+            <animation id="1"><name>walk</name><sequence><frame>0</frame></sequence></animation>
+            <animation id="1"><name>run</name><sequence><frame>1</frame></sequence></animation>
+            <animation><name>sit</name><sequence><frame>1</frame></sequence></animation>
+            <animation id="2"><name>walk</name><sequence><frame>1</frame><next>1</next></sequence></animation>
+            <animation id="3"><name>walk#2</name><sequence><frame>0</frame><next>2</next></sequence></animation>
+        "#);
+        let pack = load("pet", &files, &fixture::root()).unwrap();
+
+        let names = pack.animations.iter().map(|animation| animation.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, [ "walk", "walk#2", "walk#2#3" ]);
+        assert_eq!(pack.animations[1].next, [ ("walk".to_owned(), 1) ]);
+        assert_eq!(pack.animations[2].next, [ ("walk#2".to_owned(), 1) ]);
+        assert_eq!(pack.warnings, [
+            "Animation \"run\" has id 1, which another animation already has, so it was skipped.",
+            "Animation \"sit\" has no id, so it was skipped.",
+        ]);
+    }
+
+    #[test]
+    fn ignores_other_xml_files() {
+        let files = fixture::files(&[ ("animations.xml", b"<Mascot />".to_vec()) ]);
+        assert!(!detect(&files, &fixture::root()));
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+
+/// Where the files of a pack are read from. Loaders go through this instead
+/// of `std::fs`, so that packs work the same whether they're a directory or
+/// a pack archive (see `archive`).
+#[derive(Debug, Clone)]
+pub enum Files {
+    /// The file system.
+    Disk,
+    /// The files of an archive, which have already been read into memory.
+    /// They're keyed by the archive's path joined with their path inside of
+    /// it, so the archive looks like a directory.
+    Archive { root: PathBuf, entries: BTreeMap<PathBuf, Vec<u8>> },
+}
+
+impl Files {
+    pub fn is_file(&self, path: &Path) -> bool {
+        match self {
+            Files::Disk => path.is_file(),
+            Files::Archive { entries, .. } => entries.contains_key(path),
+        }
+    }
+
+    pub fn is_dir(&self, path: &Path) -> bool {
+        match self {
+            Files::Disk => path.is_dir(),
+            Files::Archive { root, entries } => path == root || entries
+                .keys()
+                .any(|entry| entry != path && entry.starts_with(path)),
+        }
+    }
+
+    /// Lists the files and directories in `dir`, in no particular order.
+    pub fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        match self {
+            Files::Disk => Ok(fs::read_dir(dir)?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .collect()),
+            Files::Archive { entries, .. } => {
+                if !self.is_dir(dir) { return Err(io::ErrorKind::NotFound.into()); }
+                let mut children = entries
+                    .keys()
+                    .filter_map(|entry| entry.strip_prefix(dir).ok())
+                    .filter_map(|rest| rest.components().next())
+                    .map(|child| dir.join(child))
+                    .collect::<Vec<PathBuf>>();
+                children.dedup();
+                Ok(children)
+            },
+        }
+    }
+
+    pub fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self {
+            Files::Disk => fs::read(path),
+            Files::Archive { entries, .. } => entries
+                .get(path)
+                .cloned()
+                .ok_or_else(|| io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    pub fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+/// Packs kept in memory, for testing loaders without touching the disk.
+#[cfg(test)]
+pub mod fixture {
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    use image::{ DynamicImage, ImageOutputFormat, RgbaImage };
+
+    use super::Files;
+
+    /// The directory fixtures pretend to be in.
+    pub fn root() -> PathBuf {
+        PathBuf::from("fixture")
+    }
+
+    /// Returns a pack made of `files`, by their paths inside of it.
+    pub fn files(files: &[(&str, Vec<u8>)]) -> Files {
+        Files::Archive {
+            root: root(),
+            entries: files
+                .iter()
+                .map(|(path, bytes)| (root().join(path), bytes.clone()))
+                .collect(),
+        }
+    }
+
+    /// Returns a transparent PNG of the given size.
+    pub fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgba8(RgbaImage::new(width, height))
+            .write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png)
+            .unwrap();
+        bytes
+    }
+}
@@ -0,0 +1,699 @@
+//! Reads pack manifests.
+//!
+//! A pack manifest is a `manifest.toml` at the top of a pack's directory or
+//! archive. Every pack archive has one, listing a hash of each of its files
+//! (see `archive`), and any pack can use one to describe its animations
+//! directly instead of having them worked out from its files:
+//!
+//! ```toml
+//! format_version = 2
+//! facing = "left"
+//! anchor = [64.0, 120.0]
+//! filter = "nearest"
+//!
+//! [anchors]
+//! "fall.png" = [64.0, 100.0]
+//!
+//! [[animation]]
+//! name = "swim"
+//! frames = ["swim1.png", "swim2.png", "swim3.png"]
+//! duration = 100
+//! velocity = [60.0, 0.0]
+//!
+//! [[animation]]
+//! name = "fall"
+//! frames = ["fall.png"]
+//! next = { swim = 1 }
+//!
+//! [[accessory]]
+//! name = "hat"
+//! image = "hat.png"
+//! offset = [70.0, 12.0]
+//! worn = true
+//!
+//! [accessory.offsets]
+//! "swim2.png" = [70.0, 16.0]
+//!
+//! [[emitter]]
+//! image = "bubble.png"
+//! when = "moving"
+//! rate = 3.0
+//! lifetime = 1.5
+//! offset = [40.0, 60.0]
+//! velocity = [-30.0, -20.0]
+//! spread = [10.0, 10.0]
+//! gravity = -40.0
+//!
+//! [[event]]
+//! name = "birthday"
+//! date = "07-20"
+//! accessories = ["hat"]
+//! say = "It's my birthday!"
+//!
+//! [shader]
+//! fragment = "ripple.glsl"
+//! uniforms = ["time", "direction"]
+//!
+//! [motion]
+//! squash = 0.2
+//! bob = 0.03
+//! ```
+//!
+//! Every manifest says which version of the format it was written for.
+//! Manifests written for an older version are upgraded while they're read,
+//! with a warning, and ones written for a newer version than this build
+//! understands are refused. Keys that manifests can't have, like typos, are
+//! left out with a warning, so that they don't stop the pack from loading.
+//!
+//! The versions so far are:
+//!
+//! 1. Only `files`, with bare SHA-256 hashes. The animations are always
+//!    worked out from the pack's files.
+//! 2. Adds everything else, and hashes name their algorithm, like
+//!    `sha256:9f86d08…`.
+//!
+//! Keys from a newer version than the manifest says it is are left out with
+//! a warning, the same as unknown ones.
+
+use serde::{ Deserialize, Serialize };
+use toml;
+
+use std::borrow::Cow;
+use std::collections::{ BTreeMap, BTreeSet };
+use std::convert::TryFrom;
+use std::path::Path;
+
+use toml::Value;
+
+use crate::assets::Filter;
+use crate::calendar::Event;
+use crate::motion::Motion;
+
+use super::{ default_anchor, default_speed, frame_size, Accessory, Animation, AnimationFrame, AnimationKind, AssetError, Emitter, Facing, Files, FrameSource, Layer, Pack, Shader, Trigger };
+
+/// The name of the manifest inside of a pack.
+pub const FILE: &str = "manifest.toml";
+
+/// The newest version of the format that can be read, and the one that
+/// manifests are upgraded to.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// The keys that can be at the top of a manifest, along with the version
+/// that added them.
+const MANIFEST_KEYS: &[(&str, u32)] = &[
+    ("format_version", 1), ("files", 1), ("facing", 2), ("anchor", 2), ("anchors", 2), ("filter", 2),
+    ("animation", 2), ("accessory", 2), ("emitter", 2), ("event", 2), ("shader", 2), ("motion", 2),
+];
+
+/// The keys that can be in each part of a manifest. Any others are left out
+/// by `parse`.
+const ANIMATION_KEYS: &[&str] = &[ "name", "kind", "frames", "duration", "velocity", "weight", "next", "border" ];
+const ACCESSORY_KEYS: &[&str] = &[ "name", "image", "images", "anchor", "offset", "offsets", "behind", "worn" ];
+const EMITTER_KEYS: &[&str] = &[ "image", "when", "rate", "lifetime", "offset", "velocity", "spread", "gravity" ];
+const EVENT_KEYS: &[&str] = &[
+    "name", "date", "until", "weekdays", "time", "minutes", "animation", "accessories", "say",
+];
+const SHADER_KEYS: &[&str] = &[ "fragment", "uniforms" ];
+const MOTION_KEYS: &[&str] = &[ "squash", "stretch", "bob", "bob_speed", "tilt" ];
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Manifest {
+    pub format_version: u32,
+    /// Which way the character in the frames is facing: `left`, `right` or
+    /// `either`, which is the default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub facing: Option<String>,
+    /// The point in the frames that sits on the ground, in pixels from their
+    /// top left corner. If this is unset, it's the middle of their bottom
+    /// edge.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor: Option<(f32, f32)>,
+    /// Where the point that sits on the ground is in images that don't have
+    /// it at `anchor`, keyed by their path inside of the pack.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub anchors: BTreeMap<String, (f32, f32)>,
+    /// How the frames are best resized: `nearest` for pixel art, or
+    /// `triangle`, `catmull-rom` or `lanczos`. If this is unset, the
+    /// companion's config picks, or `triangle`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<Filter>,
+    /// The animations of the pack. If there aren't any, the pack is loaded
+    /// like it would be without a manifest.
+    #[serde(default, rename = "animation", skip_serializing_if = "Vec::is_empty")]
+    pub animations: Vec<ManifestAnimation>,
+    /// Things the companion can wear along with its frames. These need the
+    /// animations to be listed, since they're placed by the frames' paths.
+    #[serde(default, rename = "accessory", skip_serializing_if = "Vec::is_empty")]
+    pub accessories: Vec<ManifestAccessory>,
+    /// Things that give off particles. These need the animations to be
+    /// listed too, since their images are loaded along with the
+    /// accessories'.
+    #[serde(default, rename = "emitter", skip_serializing_if = "Vec::is_empty")]
+    pub emitters: Vec<ManifestEmitter>,
+    /// Special days that companions drawn with the pack celebrate.
+    #[serde(default, rename = "event", skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<Event>,
+    /// A fragment shader to draw the pack with instead of the built-in one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shader: Option<ManifestShader>,
+    /// How much companions squash, stretch, bob and tilt as they move.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub motion: Option<Motion>,
+    /// The hash of each file, keyed by its path inside of the pack, with `/`
+    /// between directories. Only archives need these, and only archives are
+    /// checked against them.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub files: BTreeMap<String, String>,
+}
+
+/// An animation, as it's written in a manifest. See `Animation` for what
+/// each of these mean.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ManifestAnimation {
+    pub name: String,
+    /// `ground`, `fall` or `drag`. If this is unset, it's guessed from the
+    /// name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// The image of each frame, as a path inside of the pack.
+    pub frames: Vec<String>,
+    /// How long each frame is shown for, in milliseconds.
+    #[serde(default = "default_duration")]
+    pub duration: u32,
+    /// How fast the companion moves, in pixels of the original frames per
+    /// second. If this is unset, it moves forward at the default speed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub velocity: Option<(f32, f32)>,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub next: BTreeMap<String, u32>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub border: BTreeMap<String, u32>,
+}
+
+/// An accessory, as it's written in a manifest. See `Accessory` for what
+/// each of these mean.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ManifestAccessory {
+    pub name: String,
+    /// The image of the accessory, as a path inside of the pack.
+    pub image: String,
+    /// Images to draw instead of `image` on some frames, keyed by the path of
+    /// the frame.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub images: BTreeMap<String, String>,
+    /// The point in the accessory's images that's attached to the frames, in
+    /// pixels from their top left corner. If this is unset, it's the middle
+    /// of their bottom edge.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor: Option<(f32, f32)>,
+    /// Where the accessory is attached on every frame, in pixels from the top
+    /// left corner of the frame.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<(f32, f32)>,
+    /// Where it's attached on frames that don't have it at `offset`, keyed by
+    /// the path of the frame. Frames that have neither don't show it.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub offsets: BTreeMap<String, (f32, f32)>,
+    #[serde(default)]
+    pub behind: bool,
+    #[serde(default)]
+    pub worn: bool,
+}
+
+/// An emitter, as it's written in a manifest. See `Emitter` for what each of
+/// these mean.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ManifestEmitter {
+    /// The image of the particles, as a path inside of the pack.
+    pub image: String,
+    pub when: Trigger,
+    #[serde(default = "default_rate")]
+    pub rate: f32,
+    #[serde(default = "default_lifetime")]
+    pub lifetime: f32,
+    /// If this is unset, particles start out at the pack's anchor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<(f32, f32)>,
+    #[serde(default)]
+    pub velocity: (f32, f32),
+    #[serde(default)]
+    pub spread: (f32, f32),
+    #[serde(default)]
+    pub gravity: f32,
+}
+
+/// A pack's shader, as it's written in a manifest. See `Shader` for what
+/// each of these mean.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ManifestShader {
+    /// The fragment shader, as a path inside of the pack.
+    pub fragment: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub uniforms: Vec<String>,
+}
+
+fn default_duration() -> u32 { 100 }
+
+fn default_weight() -> u32 { 1 }
+
+fn default_rate() -> f32 { 4.0 }
+
+fn default_lifetime() -> f32 { 1.0 }
+
+/// Returns whether the pack at `path` has a manifest.
+pub fn detect(files: &Files, path: &Path) -> bool {
+    files.is_file(&path.join(FILE))
+}
+
+/// Reads the manifest of the pack at `path`, upgrading it to the current
+/// version. Returns the manifest, and warnings about anything in it that had
+/// to be upgraded or left out.
+pub fn read(files: &Files, path: &Path) -> Result<(Manifest, Vec<String>), AssetError> {
+    let file = path.join(FILE);
+    let text = files.read_to_string(&file).map_err(|error| AssetError::read(&file, error))?;
+    parse(&file, &text)
+}
+
+/// Parses a manifest that was read from `file`, upgrading it to the current
+/// version. Returns the manifest, and warnings about anything in it that had
+/// to be upgraded or left out.
+pub fn parse(file: &Path, text: &str) -> Result<(Manifest, Vec<String>), AssetError> {
+    let mut value = text.parse::<Value>().map_err(|error| AssetError::invalid(file, error))?;
+
+    let version = match value.get("format_version").and_then(Value::as_integer) {
+        Some(version) => match u32::try_from(version) {
+            Ok(version) if version >= 1 => version,
+            _ => return Err(AssetError::invalid(file, format!(
+                "There is no format version {}.",
+                version))),
+        },
+        None => return Err(AssetError::invalid(
+            file,
+            "It doesn't say which format_version it was written for.")),
+    };
+
+    if version > FORMAT_VERSION {
+        return Err(AssetError::invalid(file, format!(
+            "It's format version {}, but only versions up to {} can be read. Try updating desktop sharkie.",
+            version,
+            FORMAT_VERSION)));
+    }
+
+    let unknown = remove_unknown_keys(&mut value, version);
+    for from in version..FORMAT_VERSION { migrate(from, &mut value); }
+    if let Some(table) = value.as_table_mut() {
+        table.insert("format_version".to_owned(), Value::Integer(FORMAT_VERSION as i64));
+    }
+
+    let manifest = value.try_into::<Manifest>().map_err(|error| AssetError::invalid(file, error))?;
+    let mut warnings = Vec::new();
+    if version < FORMAT_VERSION {
+        warnings.push(format!(
+            "{} is format version {}, which is deprecated, so it was upgraded to version {} while loading. \
+             Running `desktop-sharkie archive` on the pack writes it in the new format.",
+            file.display(),
+            version,
+            FORMAT_VERSION));
+    }
+    warnings.extend(unknown);
+
+    Ok((manifest, warnings))
+}
+
+/// Removes the keys in `value` that manifests of `version` can't have, and
+/// lists them, once each.
+fn remove_unknown_keys(value: &mut Value, version: u32) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if let Some(table) = value.as_table_mut() {
+        let keys = table.keys()
+            .filter_map(|key| match MANIFEST_KEYS.iter().find(|(known, _)| known == key) {
+                Some(&(_, added)) if added <= version => None,
+                added => Some((key.clone(), added.map(|&(_, added)| added))),
+            })
+            .collect::<Vec<_>>();
+        for (key, added) in keys {
+            table.remove(&key);
+            warnings.push(match added {
+                Some(added) => format!(
+                    "Key \"{}\" needs format version {}, but the manifest is version {}, so it was ignored.",
+                    key,
+                    added,
+                    version),
+                None => format!("Unknown key \"{}\" in the manifest, so it was ignored.", key),
+            });
+        }
+    }
+
+    let mut unknown = BTreeSet::new();
+    let mut check = |value: Option<&mut Value>, known: &[&str], place: &str| {
+        if let Some(Value::Table(table)) = value {
+            let keys = table.keys()
+                .filter(|key| !known.contains(&key.as_str()))
+                .cloned()
+                .collect::<Vec<String>>();
+            for key in keys {
+                table.remove(&key);
+                unknown.insert((place.to_owned(), key));
+            }
+        }
+    };
+
+    let arrays = [
+        ("animation", ANIMATION_KEYS, "an [[animation]]"),
+        ("accessory", ACCESSORY_KEYS, "an [[accessory]]"),
+        ("emitter", EMITTER_KEYS, "an [[emitter]]"),
+        ("event", EVENT_KEYS, "an [[event]]"),
+    ];
+    for (key, known, place) in arrays.iter() {
+        if let Some(Value::Array(items)) = value.get_mut(*key) {
+            for item in items.iter_mut() { check(Some(item), known, place); }
+        }
+    }
+    check(value.get_mut("shader"), SHADER_KEYS, "[shader]");
+    check(value.get_mut("motion"), MOTION_KEYS, "[motion]");
+
+    warnings
+        .into_iter()
+        .chain(unknown
+            .into_iter()
+            .map(|(place, key)| format!("Unknown key \"{}\" in {}, so it was ignored.", key, place)))
+        .collect()
+}
+
+/// Upgrades a manifest from version `from` to the one after it. Versions
+/// that only added fields don't need anything done.
+fn migrate(from: u32, value: &mut Value) {
+    // Hashes started saying which algorithm they are, so that it can change
+    // later. Version 1 only ever used SHA-256.
+    if from == 1 {
+        let files = value.get_mut("files").and_then(Value::as_table_mut);
+        for (_, hash) in files.into_iter().flat_map(|files| files.iter_mut()) {
+            if let Value::String(hash) = hash { *hash = format!("sha256:{}", hash); }
+        }
+    }
+}
+
+/// Builds a pack from the animations in `manifest`, which was read from
+/// `file`. `frame` gets the source and size of a frame from its path in the
+/// manifest.
+pub fn load<F>(name: &str, manifest: &Manifest, file: &Path, mut frame: F) -> Result<Pack, AssetError>
+where
+    F: FnMut(&str) -> Result<(FrameSource, (u32, u32)), AssetError>
+{
+    let facing = match manifest.facing.as_deref() {
+        None | Some("either") => Facing::Either,
+        Some("left") => Facing::Left,
+        Some("right") => Facing::Right,
+        Some(other) => return Err(AssetError::invalid(file, format!(
+            "\"{}\" isn't a direction to face. Use \"left\", \"right\" or \"either\".",
+            other))),
+    };
+
+    // Animations can share frames, so each image only becomes one frame.
+    let mut paths: Vec<&str> = Vec::new();
+    let mut frames = Vec::new();
+    let mut frame_size = None;
+    let mut animations = Vec::new();
+
+    for animation in manifest.animations.iter() {
+        let kind = match animation.kind.as_deref() {
+            None => AnimationKind::guess(&animation.name),
+            Some("ground") => AnimationKind::Ground,
+            Some("fall") => AnimationKind::Fall,
+            Some("drag") => AnimationKind::Drag,
+            Some(other) => return Err(AssetError::invalid(file, format!(
+                "Animation \"{}\" is a \"{}\" animation, which isn't a kind. Use \"ground\", \"fall\" or \"drag\".",
+                animation.name,
+                other))),
+        };
+
+        let mut indices = Vec::with_capacity(animation.frames.len());
+        for path in animation.frames.iter() {
+            let index = match paths.iter().position(|other| other == path) {
+                Some(index) => index,
+                None => {
+                    let (source, size) = frame(path)?;
+                    frame_size.get_or_insert(size);
+                    paths.push(path);
+                    frames.push(source);
+                    frames.len() - 1
+                },
+            };
+            indices.push(index);
+        }
+
+        animations.push((animation, kind, indices));
+    }
+
+    let frame_size = frame_size.ok_or_else(|| AssetError::invalid(file, "None of the animations have any frames."))?;
+
+    // Accessories can share images too, so each one only becomes one layer.
+    let mut layer_paths: Vec<&str> = Vec::new();
+    let mut layers = Vec::new();
+    let mut accessories = Vec::new();
+    let mut warnings = Vec::new();
+
+    for accessory in manifest.accessories.iter() {
+        let unknown = accessory.offsets.keys()
+            .chain(accessory.images.keys())
+            .filter(|path| !paths.contains(&path.as_str()));
+        for path in unknown {
+            warnings.push(format!(
+                "Accessory \"{}\" is placed on \"{}\", which isn't a frame of any animation.",
+                accessory.name,
+                path));
+        }
+
+        let mut placements = Vec::with_capacity(paths.len());
+        for path in paths.iter() {
+            let offset = match accessory.offsets.get(*path).or(accessory.offset.as_ref()) {
+                Some(offset) => *offset,
+                None => {
+                    placements.push(None);
+                    continue;
+                },
+            };
+            let image = accessory.images.get(*path).unwrap_or(&accessory.image);
+            let layer = match layer_paths.iter().position(|other| other == image) {
+                Some(layer) => layer,
+                None => {
+                    let (source, size) = frame(image)?;
+                    layer_paths.push(image);
+                    layers.push(Layer { source, size });
+                    layers.len() - 1
+                },
+            };
+            let anchor = accessory.anchor.unwrap_or_else(|| default_anchor(layers[layer].size));
+            placements.push(Some((layer, (offset.0 - anchor.0, offset.1 - anchor.1))));
+        }
+
+        if placements.iter().all(Option::is_none) {
+            warnings.push(format!(
+                "Accessory \"{}\" isn't placed on any frames, so it's never shown.",
+                accessory.name));
+        }
+
+        accessories.push(Accessory {
+            name: accessory.name.clone(),
+            behind: accessory.behind,
+            worn: accessory.worn,
+            placements,
+        });
+    }
+
+    let anchor = manifest.anchor.unwrap_or_else(|| default_anchor(frame_size));
+    let mut emitters = Vec::new();
+
+    for emitter in manifest.emitters.iter() {
+        let layer = match layer_paths.iter().position(|other| *other == emitter.image) {
+            Some(layer) => layer,
+            None => {
+                let (source, size) = frame(&emitter.image)?;
+                layer_paths.push(&emitter.image);
+                layers.push(Layer { source, size });
+                layers.len() - 1
+            },
+        };
+        if emitter.rate <= 0.0 || emitter.lifetime <= 0.0 {
+            warnings.push(format!(
+                "Emitter \"{}\" has no rate or lifetime, so its particles are never shown.",
+                emitter.image));
+        }
+        emitters.push(Emitter {
+            layer,
+            trigger: emitter.when,
+            rate: emitter.rate,
+            lifetime: emitter.lifetime,
+            offset: emitter.offset.unwrap_or(anchor),
+            velocity: emitter.velocity,
+            spread: emitter.spread,
+            gravity: emitter.gravity,
+        });
+    }
+
+    for event in manifest.events.iter() {
+        if let Some(ref animation) = event.animation {
+            if !manifest.animations.iter().any(|other| other.name == *animation) {
+                warnings.push(format!(
+                    "Event \"{}\" plays animation \"{}\", which doesn't exist.",
+                    event.name,
+                    animation));
+            }
+        }
+        let missing_accessories = event.accessories
+            .iter()
+            .filter(|accessory| !manifest.accessories.iter().any(|other| other.name == **accessory));
+        for accessory in missing_accessories {
+            warnings.push(format!(
+                "Event \"{}\" puts on accessory \"{}\", which doesn't exist.",
+                event.name,
+                accessory));
+        }
+    }
+
+    Ok(Pack {
+        name: name.to_owned(),
+        frames,
+        sheets: Vec::new(),
+        frame_size,
+        anchor,
+        animations: animations
+            .into_iter()
+            .map(|(animation, kind, indices)| Animation {
+                name: animation.name.clone(),
+                kind,
+                frames: indices
+                    .into_iter()
+                    .map(|frame| AnimationFrame {
+                        frame,
+                        duration: animation.duration,
+                        velocity: animation.velocity.unwrap_or((default_speed(frame_size.1), 0.0)),
+                        anchor: manifest.anchors.get(paths[frame]).copied(),
+                    })
+                    .collect(),
+                weight: animation.weight,
+                next: animation.next.iter().map(|(name, weight)| (name.clone(), *weight)).collect(),
+                border: animation.border.iter().map(|(name, weight)| (name.clone(), *weight)).collect(),
+            })
+            .collect(),
+        layers,
+        accessories,
+        emitters,
+        events: manifest.events.clone(),
+        shader: None,
+        filter: manifest.filter,
+        motion: manifest.motion.unwrap_or_default(),
+        facing,
+        warnings,
+    })
+}
+
+/// Loads the pack at `path` from its manifest, reading its frames from
+/// `files`.
+pub fn load_files(name: &str, manifest: &Manifest, files: &Files, path: &Path) -> Result<Pack, AssetError> {
+    load(name, manifest, &path.join(FILE), |frame| {
+        let file = path.join(frame);
+        let bytes = files.read(&file).map_err(|error| AssetError::read(&file, error))?;
+        let size = frame_size(&file, &bytes)?;
+        Ok((FrameSource::Encoded { file, bytes: Cow::Owned(bytes) }, size))
+    })
+}
+
+/// Reads the shader of the pack at `path` from `files`, checking that it
+/// only declares uniforms that shaders can use. Whether it compiles is only
+/// known once it's uploaded to the GPU.
+pub fn load_shader(shader: &ManifestShader, files: &Files, path: &Path) -> Result<Shader, AssetError> {
+    for uniform in shader.uniforms.iter() {
+        if !Shader::UNIFORMS.iter().any(|(name, _)| name == uniform) {
+            return Err(AssetError::invalid(path.join(FILE), format!(
+                "The shader uses \"{}\", which isn't a uniform shaders can use. Use {}.",
+                uniform,
+                Shader::UNIFORMS.iter().map(|(name, _)| format!("\"{}\"", name)).collect::<Vec<String>>().join(", "))));
+        }
+    }
+    let file = path.join(&shader.fragment);
+    let source = files.read_to_string(&file).map_err(|error| AssetError::read(&file, error))?;
+    Ok(Shader { file, source, uniforms: shader.uniforms.clone() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_out_unknown_keys() {
+        let (manifest, warnings) = parse(Path::new(FILE), r#"
+            format_version = 2
+            colour = "blue"
+
+            [[animation]]
+            name = "swim"
+            frames = ["swim1.png", "swim2.png"]
+            speed = 2.0
+
+            [[animation]]
+            name = "rest"
+            frames = ["swim1.png"]
+            speed = 0.0
+
+            [motion]
+            bob = 0.03
+            sway = 0.1
+        "#).unwrap();
+
+        assert_eq!(manifest.animations.len(), 2);
+        assert_eq!(manifest.motion.map(|motion| motion.bob), Some(0.03));
+        assert_eq!(warnings, [
+            "Unknown key \"colour\" in the manifest, so it was ignored.",
+            "Unknown key \"sway\" in [motion], so it was ignored.",
+            "Unknown key \"speed\" in an [[animation]], so it was ignored.",
+        ]);
+    }
+
+    #[test]
+    fn upgrades_old_manifests() {
+        let (manifest, warnings) = parse(Path::new(FILE), r#"
+            format_version = 1
+            facing = "left"
+
+            [files]
+            "frame01.png" = "9f86d08"
+        "#).unwrap();
+
+        assert_eq!(manifest.format_version, FORMAT_VERSION);
+        assert_eq!(manifest.facing, None);
+        assert_eq!(manifest.files["frame01.png"], "sha256:9f86d08");
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("deprecated"));
+        assert_eq!(warnings[1], "Key \"facing\" needs format version 2, but the manifest is version 1, so it was ignored.");
+    }
+
+    #[test]
+    fn refuses_versions_it_does_not_know() {
+        for version in [ "0", "-1", "3", "4294967297" ].iter() {
+            let text = format!("format_version = {}", version);
+            assert!(parse(Path::new(FILE), &text).is_err(), "{}", version);
+        }
+    }
+
+    #[test]
+    fn reads_the_built_in_manifest_without_warnings() {
+        let (_, warnings) = parse(Path::new(FILE), include_str!("../assets/gura.toml")).unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+
+    #[test]
+    fn reads_emitter_triggers() {
+        let emitter = |when: &str| parse(Path::new(FILE), &format!(
+            "format_version = 2\n[[emitter]]\nimage = \"bubble.png\"\nwhen = \"{}\"", when,
+        )).map(|(manifest, _)| manifest.emitters[0].when);
+
+        assert_eq!(emitter("moving").unwrap(), Trigger::Moving);
+        assert_eq!(emitter("landing").unwrap(), Trigger::Landing);
+        assert_eq!(emitter("petted").unwrap(), Trigger::Petted);
+        assert!(emitter("Moving").is_err());
+        assert!(emitter("swimming").is_err());
+    }
+}
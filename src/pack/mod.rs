@@ -0,0 +1,721 @@
+use image;
+use roxmltree;
+
+pub mod animated;
+pub mod archive;
+pub mod aseprite;
+pub mod error;
+pub mod esheep;
+pub mod files;
+pub mod manifest;
+pub mod shimeji;
+pub mod vector;
+
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::io::Cursor;
+use std::path::{ Component, Path, PathBuf };
+
+use image::{ imageops, DynamicImage, GenericImageView, Rgba, RgbaImage };
+use roxmltree::Node;
+use serde::{ Deserialize, Serialize };
+
+use crate::assets::{ baked, Filter, FRAMES };
+use crate::calendar::Event;
+use crate::config;
+use crate::motion::Motion;
+
+pub use error::AssetError;
+pub use files::Files;
+
+/// A set of frames that a companion can be drawn with, and the animations
+/// that play them.
+///
+/// Other than the built-in Gura pack, packs are directories inside of
+/// `packs` in desktop sharkie's data directory (see `config::data_dir`).
+/// They either contain numbered frames like `frame01.png`, `frame02.png` and
+/// so on (or SVGs, see `vector`), which are played as a single looping
+/// animation, a Shimeji mascot
+/// (see `shimeji`), an eSheep `animations.xml` (see `esheep`), an Aseprite
+/// sprite sheet (see `aseprite`), or animated GIF and APNG files (see
+/// `animated`). Any of those can also be packed into a single pack archive
+/// (see `archive`).
+#[derive(Debug, Clone)]
+pub struct Pack {
+    pub name: String,
+    /// Where to get the image of each frame from.
+    pub frames: Vec<FrameSource>,
+    /// Sprite sheets that frames can be cut out of.
+    pub sheets: Vec<Sheet>,
+    /// The size of the frames, before they get resized to fit the companion.
+    pub frame_size: (u32, u32),
+    /// The point in the frames that sits on the ground, usually between the
+    /// character's feet, in pixels of the original frames from their top left
+    /// corner. Frames can have their own (see `AnimationFrame::anchor`).
+    pub anchor: (f32, f32),
+    pub animations: Vec<Animation>,
+    /// The images of `accessories`. They're loaded along with the frames,
+    /// and resized by as much as the frames are.
+    pub layers: Vec<Layer>,
+    /// Things companions can wear on top of or behind the frames, like hats,
+    /// which can be put on and taken off while they're running.
+    pub accessories: Vec<Accessory>,
+    /// Things that give off particles, like bubbles trailing behind a
+    /// swimming shark.
+    pub emitters: Vec<Emitter>,
+    /// Special days that companions drawn with the pack celebrate.
+    pub events: Vec<Event>,
+    /// A fragment shader to draw the companions with instead of the built-in
+    /// one.
+    pub shader: Option<Shader>,
+    /// How the frames are best resized, for packs that care, like pixel
+    /// art. Companions can override it in their config.
+    pub filter: Option<Filter>,
+    /// How much companions squash, stretch, bob and tilt as they move.
+    pub motion: Motion,
+    /// Which way the character in the frames is facing.
+    pub facing: Facing,
+    /// Problems that were found while loading the pack, which didn't stop it
+    /// from loading.
+    pub warnings: Vec<String>,
+}
+
+/// A sequence of frames, and how the companion moves while they play.
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub name: String,
+    pub kind: AnimationKind,
+    pub frames: Vec<AnimationFrame>,
+    /// How likely this animation is to be picked when the previous one
+    /// finishes, relative to the others. Animations with a weight of zero are
+    /// only played when another animation lists them in `next`.
+    pub weight: u32,
+    /// The animations that can follow this one, by name and weight. If this
+    /// is empty, the next animation is picked from all of them by `weight`.
+    pub next: Vec<(String, u32)>,
+    /// The animations to switch to when the companion runs into the edge of
+    /// the screen while this one is playing, by name and weight.
+    pub border: Vec<(String, u32)>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AnimationKind {
+    /// Played while standing on the ground.
+    Ground,
+    /// Played while falling back down to the ground.
+    Fall,
+    /// Played while being dragged around.
+    Drag,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct AnimationFrame {
+    /// The index of the frame in `Pack::frames`.
+    pub frame: usize,
+    /// How long the frame is shown for, in milliseconds.
+    pub duration: u32,
+    /// How fast the companion moves while the frame is shown, in pixels of
+    /// the original frame per second. Positive X is the way the companion is
+    /// facing, and positive Y is down.
+    pub velocity: (f32, f32),
+    /// Where the point that sits on the ground is in this frame, if it isn't
+    /// at `Pack::anchor`. The frame is moved so that the two line up.
+    pub anchor: Option<(f32, f32)>,
+}
+
+/// An image that is drawn along with the frames, rather than instead of
+/// them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    pub source: FrameSource,
+    /// The size of the image, in pixels of the original frames.
+    pub size: (u32, u32),
+}
+
+/// Something a companion can wear, which is drawn at a different spot on
+/// each frame.
+#[derive(Debug, Clone)]
+pub struct Accessory {
+    pub name: String,
+    /// Whether it's drawn behind the frames instead of on top of them.
+    pub behind: bool,
+    /// Whether companions wear it unless they're told otherwise.
+    pub worn: bool,
+    /// For each of `Pack::frames`, the index in `Pack::layers` of the image
+    /// to draw, and where its top left corner goes in pixels of the original
+    /// frame. Frames without one don't show the accessory.
+    pub placements: Vec<Option<(usize, (f32, f32))>>,
+}
+
+/// Something that gives off particles while a companion is doing something,
+/// like bubbles trailing behind it while it swims (see `particles`).
+#[derive(Debug, Clone)]
+pub struct Emitter {
+    /// The index in `Pack::layers` of the particles' image.
+    pub layer: usize,
+    pub trigger: Trigger,
+    /// How many particles it gives off a second, or all at once for
+    /// triggers that don't last.
+    pub rate: f32,
+    /// How long each particle lasts, in seconds. They fade out towards the
+    /// end.
+    pub lifetime: f32,
+    /// Where particles start out, in pixels from the top left corner of the
+    /// original frames.
+    pub offset: (f32, f32),
+    /// How fast particles start out moving while the companion is heading
+    /// right, in pixels of the original frames per second. It's mirrored
+    /// while heading left, so that particles can trail behind it.
+    pub velocity: (f32, f32),
+    /// How much faster or slower each particle can start out moving, at
+    /// random, either way.
+    pub spread: (f32, f32),
+    /// How fast particles speed up downwards, in pixels of the original
+    /// frames per second per second. Particles that float up, like bubbles,
+    /// have a negative one.
+    pub gravity: f32,
+}
+
+/// What makes an emitter give off particles.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Trigger {
+    /// Moving along the ground, for as long as it lasts.
+    Moving,
+    /// Landing after a fall.
+    Landing,
+    /// Being petted (see `Companion::pet`).
+    Petted,
+}
+
+/// A fragment shader that comes with a pack, for effects that aren't built
+/// in, like water ripples or a glow.
+///
+/// It's compiled along with the built-in vertex shader for the same version
+/// of GLSL, which has to be 100, 110 or 140, and samples `tex` at
+/// `v_tex_coords` like the built-in fragment shaders do. Frames have
+/// premultiplied alpha, and what it draws is blended as premultiplied too.
+/// Other than `tex`, it can only use the uniforms in `Shader::UNIFORMS` that
+/// it declares.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shader {
+    /// The file the shader was read from, for error messages.
+    pub file: PathBuf,
+    pub source: String,
+    /// The names of the uniforms the shader uses.
+    pub uniforms: Vec<String>,
+}
+
+/// Where the image of a frame comes from.
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub enum FrameSource {
+    /// An encoded image, like a PNG or SVG file, and the file it was read
+    /// from.
+    Encoded { file: PathBuf, bytes: Cow<'static, [u8]> },
+    /// One of the built-in frames, as baked by `build.rs`.
+    Baked(&'static [u8]),
+    /// A rectangle cut out of one of `Pack::sheets`, given as x, y, width and
+    /// height.
+    Tile { sheet: usize, rect: (u32, u32, u32, u32) },
+    /// A rectangle cut out of one of `Pack::sheets`, which may have been
+    /// turned a quarter turn clockwise and had its transparent border trimmed
+    /// off. Once it's turned back, it's placed at `offset` in a transparent
+    /// frame of `size`.
+    Sprite {
+        sheet: usize,
+        rect: (u32, u32, u32, u32),
+        rotated: bool,
+        offset: (u32, u32),
+        size: (u32, u32),
+    },
+    /// One of the frames of an animated image in `Pack::sheets`.
+    Animated { sheet: usize, index: usize },
+}
+
+/// A single image that holds several frames, either as tiles or as the
+/// frames of an animated image.
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct Sheet {
+    /// The file the image was read from, for error messages.
+    pub file: PathBuf,
+    /// The encoded image.
+    pub bytes: Cow<'static, [u8]>,
+    /// A colour that should be made transparent, for formats that predate
+    /// alpha channels.
+    pub key: Option<[u8; 3]>,
+}
+
+/// Which way the character in a pack's frames is facing. Frames are mirrored
+/// when the companion walks the other way.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Facing {
+    Left,
+    Right,
+    /// The frames look right either way, and are never mirrored.
+    Either,
+}
+
+impl Pack {
+    /// The name of the built-in pack.
+    pub const BUILTIN: &'static str = "gura";
+
+    /// Returns the pack that is compiled into the executable. It's described
+    /// by a manifest like any other pack, with its frames baked by `build.rs`
+    /// and the bubbles it gives off drawn from an SVG.
+    pub fn builtin() -> Self {
+        let file = Path::new("src/assets/gura.toml");
+        let (manifest, _) = manifest::parse(file, include_str!("../assets/gura.toml"))
+            .expect("The built-in pack's manifest is broken.");
+        manifest::load(Pack::BUILTIN, &manifest, file, |frame| {
+            if frame == "water-bubble.svg" {
+                let file = PathBuf::from("src/assets/water-bubble.svg");
+                let bytes = include_bytes!("../assets/water-bubble.svg");
+                let size = vector::size(&file, bytes)?;
+                return Ok((FrameSource::Encoded { file, bytes: Cow::Borrowed(bytes) }, size));
+            }
+            let index = (0..FRAMES.len())
+                .find(|index| frame == format!("frame{:02}.png", index + 1))
+                .ok_or_else(|| AssetError::invalid(file, format!("There is no built-in {}.", frame)))?;
+            let header = baked::header(FRAMES[index])
+                .map_err(|error| AssetError::decode(frame, error))?;
+            Ok((FrameSource::Baked(FRAMES[index]), header.size))
+        })
+        .expect("The built-in pack's manifest is broken.")
+    }
+
+    /// Returns the directory installed packs are kept in.
+    pub fn dir() -> PathBuf {
+        config::data_dir().join("packs")
+    }
+
+    /// Extensions that packs installed as a lone file can have. Pack
+    /// archives, eSheep packs and animated images can all be installed that
+    /// way.
+    pub const EXTENSIONS: [&'static str; 6] = [ archive::EXTENSION, "xml", "json", "gif", "png", "apng" ];
+
+    /// Returns where the installed pack called `name` is, if there is one.
+    /// Names have to be a single file name, so that they can't point outside
+    /// of `Pack::dir`.
+    pub fn path(name: &str) -> Option<PathBuf> {
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => (),
+            _ => return None,
+        }
+        let path = Pack::dir().join(name);
+        if path.exists() { return Some(path); }
+        Pack::EXTENSIONS
+            .iter()
+            .map(|extension| Pack::dir().join(format!("{}.{}", name, extension)))
+            .find(|file| file.is_file())
+    }
+
+    /// Finds the pack called `name`, either the built-in one or one from
+    /// `Pack::dir`.
+    pub fn find(name: &str) -> Result<Self, AssetError> {
+        if name == Pack::BUILTIN { return Ok(Pack::builtin()); }
+        match Pack::path(name) {
+            Some(path) => Pack::load(name, &path),
+            None => Err(AssetError::NotFound {
+                name: name.to_owned(),
+                dir: Pack::dir().display().to_string(),
+            }),
+        }
+    }
+
+    /// Loads a pack from a directory, a file or a pack archive. Packs with a
+    /// manifest that lists their animations are loaded from that, and
+    /// otherwise what kind of pack it is gets worked out from its files.
+    pub fn load(name: &str, path: &Path) -> Result<Self, AssetError> {
+        let files = if archive::is_archive(path) { archive::open(path)? } else { Files::Disk };
+
+        let (manifest, warnings) = if manifest::detect(&files, path) {
+            let (manifest, warnings) = manifest::read(&files, path)?;
+            (Some(manifest), warnings)
+        } else {
+            (None, Vec::new())
+        };
+
+        let mut pack = match manifest {
+            Some(ref manifest) if !manifest.animations.is_empty() => manifest::load_files(name, manifest, &files, path)?,
+            Some(ref manifest) => Pack {
+                events: manifest.events.clone(),
+                filter: manifest.filter,
+                motion: manifest.motion.unwrap_or_default(),
+                ..Pack::detect(name, &files, path)?
+            },
+            None => Pack::detect(name, &files, path)?,
+        };
+        pack.warnings.splice(0..0, warnings);
+
+        // The frames are fine without the shader, so the pack is still
+        // drawn, just with the built-in one.
+        if let Some(shader) = manifest.as_ref().and_then(|manifest| manifest.shader.as_ref()) {
+            match manifest::load_shader(shader, &files, path) {
+                Ok(shader) => pack.shader = Some(shader),
+                Err(error) => pack.warnings.push(format!(
+                    "The shader couldn't be loaded, so the built-in one is used instead. {}",
+                    error)),
+            }
+        }
+
+        // Accessories are placed by the paths of the frames in the manifest,
+        // and emitters' images are loaded along with theirs, so packs that
+        // leave working out their frames to the loader can't have either.
+        if let Some(manifest) = manifest.filter(|manifest| manifest.animations.is_empty()) {
+            if !manifest.accessories.is_empty() {
+                pack.warnings.push(
+                    "Accessories only work in manifests that list the animations, so they were skipped.".to_owned());
+            }
+            if !manifest.emitters.is_empty() {
+                pack.warnings.push(
+                    "Emitters only work in manifests that list the animations, so they were skipped.".to_owned());
+            }
+        }
+        Ok(pack)
+    }
+
+    /// Works out what kind of pack `path` is from its files, and loads it.
+    fn detect(name: &str, files: &Files, path: &Path) -> Result<Self, AssetError> {
+        if shimeji::detect(files, path) { return shimeji::load(name, files, path); }
+        if esheep::detect(files, path) { return esheep::load(name, files, path); }
+        if aseprite::detect(files, path) { return aseprite::load(name, files, path); }
+        if animated::detect(files, path) { return animated::load(name, files, path); }
+
+        let mut paths = files.list(path)
+            .map_err(|error| AssetError::read(path, error))?
+            .into_iter()
+            .filter(|path| is_frame(path))
+            .collect::<Vec<PathBuf>>();
+
+        // `frame2.png` should come before `frame10.png`, so sort by the
+        // number in the file name rather than by the name itself.
+        paths.sort_by_key(|path| frame_number(path));
+
+        if paths.is_empty() {
+            return Err(AssetError::invalid(
+                path,
+                "There aren't any frames in it, or any other kind of pack."));
+        }
+
+        let frames = paths
+            .into_iter()
+            .map(|file| match files.read(&file) {
+                Ok(bytes) => Ok((file, bytes)),
+                Err(error) => Err(AssetError::read(&file, error)),
+            })
+            .collect::<Result<Vec<(PathBuf, Vec<u8>)>, AssetError>>()?;
+
+        let frame_size = frame_size(&frames[0].0, &frames[0].1)?;
+
+        Ok(Pack {
+            name: name.to_owned(),
+            frame_size,
+            anchor: default_anchor(frame_size),
+            animations: vec![Animation::looping(
+                "default",
+                frames.len(),
+                100,
+                default_speed(frame_size.1))],
+            frames: frames
+                .into_iter()
+                .map(|(file, bytes)| FrameSource::Encoded { file, bytes: Cow::Owned(bytes) })
+                .collect(),
+            sheets: Vec::new(),
+            layers: Vec::new(),
+            accessories: Vec::new(),
+            emitters: Vec::new(),
+            events: Vec::new(),
+            shader: None,
+            filter: None,
+            motion: Motion::default(),
+            facing: Facing::Either,
+            warnings: Vec::new(),
+        })
+    }
+
+    /// Returns the index of the animation called `name`.
+    pub fn animation(&self, name: &str) -> Option<usize> {
+        self.animations.iter().position(|animation| animation.name == name)
+    }
+
+    /// Returns the index of the accessory called `name`.
+    pub fn accessory(&self, name: &str) -> Option<usize> {
+        self.accessories.iter().position(|accessory| accessory.name == name)
+    }
+}
+
+impl Shader {
+    /// The uniforms pack shaders can use, and their GLSL types:
+    ///
+    /// - `time` is how many seconds desktop sharkie has been running for.
+    ///   Companions with shaders that use it are redrawn on every tick.
+    /// - `direction` is 1 while the companion is heading right, and -1 while
+    ///   it's heading left.
+    /// - `state` is 0 while the companion is on the ground, 1 while it's
+    ///   falling and 2 while it's being dragged.
+    /// - `size` is how big the frame is on screen, in pixels.
+    /// - The rest are the companion's effects (see `effects`), so that
+    ///   shaders can keep them working.
+    pub const UNIFORMS: [(&'static str, &'static str); 9] = [
+        ("time", "float"),
+        ("direction", "float"),
+        ("state", "int"),
+        ("size", "vec2"),
+        ("tint", "vec4"),
+        ("outline_color", "vec4"),
+        ("outline_size", "vec2"),
+        ("palette", "sampler2D"),
+        ("palette_size", "int"),
+    ];
+
+    /// The versions of GLSL that pack shaders can be written in, which are
+    /// the ones there are built-in vertex shaders for.
+    pub const VERSIONS: [&'static str; 3] = [ "100", "110", "140" ];
+
+    /// Returns the version of GLSL the shader is written in, from its
+    /// `#version` line.
+    pub fn version(&self) -> Option<&str> {
+        self.source
+            .lines()
+            .map(str::trim)
+            .find(|line| line.starts_with("#version"))
+            .and_then(|line| line["#version".len()..].split_whitespace().next())
+    }
+
+    /// Returns whether the shader uses `time`, which means the companion
+    /// changes even when it's standing still.
+    pub fn animated(&self) -> bool {
+        self.uniforms.iter().any(|uniform| uniform == "time")
+    }
+}
+
+impl AnimationKind {
+    /// Guesses the kind of an animation from its name, for packs that don't
+    /// say.
+    pub fn guess(name: &str) -> Self {
+        match name.to_lowercase().as_str() {
+            "fall" => AnimationKind::Fall,
+            "drag" => AnimationKind::Drag,
+            _ => AnimationKind::Ground,
+        }
+    }
+}
+
+impl Animation {
+    /// Returns an animation that plays the first `count` frames of a pack in
+    /// order, moving forward at `speed`.
+    pub fn looping(name: &str, count: usize, duration: u32, speed: f32) -> Self {
+        Animation {
+            name: name.to_owned(),
+            kind: AnimationKind::Ground,
+            frames: (0..count)
+                .map(|frame| AnimationFrame {
+                    frame,
+                    duration,
+                    velocity: (speed, 0.0),
+                    anchor: None,
+                })
+                .collect(),
+            weight: 1,
+            next: Vec::new(),
+            border: Vec::new(),
+        }
+    }
+}
+
+impl FrameSource {
+    /// Returns the index of the sheet in `Pack::sheets` that the frame comes
+    /// from, if it comes from one.
+    pub fn sheet(&self) -> Option<usize> {
+        match self {
+            FrameSource::Tile { sheet, .. }
+            | FrameSource::Sprite { sheet, .. }
+            | FrameSource::Animated { sheet, .. } => Some(*sheet),
+            FrameSource::Encoded { .. } | FrameSource::Baked(..) => None,
+        }
+    }
+
+    /// Decodes the frame. `sheets` are `Pack::sheets`, and `decoded` is what
+    /// came out of decoding each of them. SVGs are drawn as big as they fit
+    /// in `bounds`, and everything else comes out at the size it is.
+    pub fn decode(
+        &self,
+        sheets: &[Sheet],
+        decoded: &[Result<Vec<DynamicImage>, AssetError>],
+        bounds: (u32, u32),
+    ) -> Result<DynamicImage, AssetError> {
+        // Frames cut out of a sheet that failed to decode fail the same way.
+        let lookup = |sheet: usize| match decoded.get(sheet) {
+            Some(Ok(frames)) => Ok((&sheets[sheet].file, frames)),
+            Some(Err(error)) => Err(error.clone()),
+            None => Err(AssetError::invalid(
+                "the pack",
+                format!("A frame comes from sprite sheet {}, which doesn't exist.", sheet))),
+        };
+        match self {
+            FrameSource::Encoded { file, bytes } if vector::is_svg(file) => vector::render(file, bytes, bounds)
+                .map(DynamicImage::ImageRgba8),
+            FrameSource::Encoded { file, bytes } => image::load_from_memory(bytes)
+                .map_err(|error| AssetError::decode(file, error)),
+            FrameSource::Baked(bytes) => baked::decode(bytes)
+                .map_err(|error| AssetError::decode("a built-in frame", error)),
+            FrameSource::Tile { sheet, rect: (x, y, w, h) } => {
+                let (file, frames) = lookup(*sheet)?;
+                let image = frames
+                    .first()
+                    .ok_or_else(|| AssetError::decode(file, "It doesn't have any frames."))?;
+                if x + w > image.width() || y + h > image.height() {
+                    return Err(AssetError::invalid(file, format!(
+                        "The frame at {},{} ({}x{}) is outside of the image.",
+                        x, y, w, h)));
+                }
+                Ok(image.crop_imm(*x, *y, *w, *h))
+            },
+            FrameSource::Sprite { sheet, rect, rotated, offset, size } => {
+                let tile = FrameSource::Tile { sheet: *sheet, rect: *rect }.decode(sheets, decoded, bounds)?;
+                let tile = if *rotated { tile.rotate270() } else { tile };
+                let mut frame = RgbaImage::new(size.0, size.1);
+                imageops::replace(&mut frame, &tile.to_rgba8(), offset.0, offset.1);
+                Ok(DynamicImage::ImageRgba8(frame))
+            },
+            FrameSource::Animated { sheet, index } => {
+                let (file, frames) = lookup(*sheet)?;
+                frames
+                    .get(*index)
+                    .cloned()
+                    .ok_or_else(|| AssetError::decode(file, format!("It doesn't have a frame {}.", index)))
+            },
+        }
+    }
+}
+
+impl Sheet {
+    /// Decodes every frame of the sheet, making `key` transparent. Sheets
+    /// that aren't animated have a single frame.
+    pub fn decode(&self) -> Result<Vec<DynamicImage>, AssetError> {
+        let frames = if animated::is_animated(&self.bytes) {
+            animated::decode(&self.bytes)
+                .map_err(|error| AssetError::decode(&self.file, error))?
+                .into_iter()
+                .map(|frame| DynamicImage::ImageRgba8(frame.image))
+                .collect()
+        } else {
+            vec![ image::load_from_memory(&self.bytes).map_err(|error| AssetError::decode(&self.file, error))? ]
+        };
+        Ok(frames
+            .into_iter()
+            .map(|image| match self.key {
+                Some(key) => {
+                    let mut rgba = image.into_rgba8();
+                    for pixel in rgba.pixels_mut() {
+                        if pixel.0[..3] == key { *pixel = Rgba([ 0, 0, 0, 0 ]); }
+                    }
+                    DynamicImage::ImageRgba8(rgba)
+                },
+                None => image,
+            })
+            .collect())
+    }
+}
+
+/// Returns how fast a pack with frames that are `height` pixels tall moves
+/// by default, in pixels of the original frames per second.
+///
+/// Gura originally swam 3 pixels every 50ms at the default size of 180px on a
+/// 900px tall screen, so this keeps everything moving at that pace.
+pub fn default_speed(height: u32) -> f32 {
+    60.0 * ((height as f32) / 180.0)
+}
+
+/// Returns where the ground is in frames of `size` by default, which is the
+/// middle of their bottom edge.
+pub fn default_anchor(size: (u32, u32)) -> (f32, f32) {
+    (size.0 as f32 / 2.0, size.1 as f32)
+}
+
+/// Reads the size of an encoded image, without decoding the whole thing.
+/// `file` is where it was read from, for the error message.
+pub fn frame_size(file: &Path, bytes: &[u8]) -> Result<(u32, u32), AssetError> {
+    if vector::is_svg(file) { return vector::size(file, bytes); }
+    image::io::Reader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|error| AssetError::decode(file, error))?
+        .into_dimensions()
+        .map_err(|error| AssetError::decode(file, error))
+}
+
+/// An element that XML packs can have, with the elements that can be in it,
+/// and its attributes. If the attributes are `None`, it can have any.
+type Element = (&'static str, &'static [&'static str], Option<&'static [&'static str]>);
+
+/// Lists the elements and attributes under `root` that aren't in `known`,
+/// once each. Only the insides of elements that are in `known` are checked,
+/// so an unknown element is mentioned, but what's in it isn't.
+fn unknown_xml(root: Node, known: &[Element]) -> Vec<String> {
+    let mut unknown = BTreeSet::new();
+    for node in root.descendants().filter(Node::is_element) {
+        let name = node.tag_name().name();
+        let (children, attributes) = match known.iter().find(|(element, ..)| *element == name) {
+            Some((_, children, attributes)) => (children, attributes),
+            None => continue,
+        };
+        for child in node.children().filter(Node::is_element) {
+            if !children.contains(&child.tag_name().name()) {
+                unknown.insert(format!(
+                    "Unknown element <{}> in <{}>, so it was ignored.",
+                    child.tag_name().name(),
+                    name));
+            }
+        }
+        if let Some(attributes) = attributes {
+            for attribute in node.attributes().iter().filter(|attribute| !attributes.contains(&attribute.name())) {
+                unknown.insert(format!(
+                    "Unknown attribute \"{}\" on <{}>, so it was ignored.",
+                    attribute.name(),
+                    name));
+            }
+        }
+    }
+    unknown.into_iter().collect()
+}
+
+fn is_frame(path: &Path) -> bool {
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+    name.starts_with("frame") && (name.ends_with(".png") || name.ends_with(".svg"))
+}
+
+fn frame_number(path: &Path) -> u32 {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .map(|stem| stem.trim_start_matches("frame"))
+        .and_then(|number| number.parse().ok())
+        .unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_without_a_shader_that_cannot_be_read() {
+        let dir = std::env::temp_dir().join(format!("desktop-sharkie-shader-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("frame01.png"), files::fixture::png(16, 16)).unwrap();
+        std::fs::write(dir.join(manifest::FILE), "format_version = 2\n[shader]\nfragment = \"missing.glsl\"\n").unwrap();
+
+        let pack = Pack::load("shader", &dir);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let pack = pack.unwrap();
+        assert!(pack.shader.is_none());
+        assert!(pack.warnings.iter().any(|warning| warning.contains("missing.glsl")), "{:?}", pack.warnings);
+    }
+
+    #[test]
+    fn only_finds_packs_by_plain_names() {
+        for name in [ "", ".", "..", "../packs", "a/b", "/etc" ].iter() {
+            assert_eq!(Pack::path(name), None, "{:?}", name);
+        }
+    }
+}
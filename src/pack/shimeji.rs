@@ -0,0 +1,419 @@
+//! Imports Shimeji mascots.
+//!
+//! A Shimeji mascot is a directory with its frames in `img` (`shime1.png`,
+//! `shime2.png` and so on), and two files in `conf`: `actions.xml`, which
+//! lists the animations, and `behaviors.xml`, which lists how often each of
+//! them is played and what can follow it.
+//!
+//! Only the `Stay`, `Move`, `Animate`, `Fall` and `Dragged` actions can be
+//! imported. Everything else is listed in `Pack::warnings`.
+
+use roxmltree;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::{ Path, PathBuf };
+
+use roxmltree::{ Document, Node };
+
+use crate::motion::Motion;
+
+use super::{ Animation, AnimationFrame, AnimationKind, AssetError, Element, Facing, Files, FrameSource, Pack };
+
+/// How long one Shimeji tick is, in milliseconds. Durations and velocities
+/// in Shimeji files are given in ticks.
+const TICK: f32 = 40.0;
+
+/// What can be in `actions.xml`. Actions take parameters that depend on
+/// their type as attributes, so any attribute goes on those.
+const ACTIONS: &[Element] = &[
+    ("Mascot", &[ "ActionList" ], None),
+    ("ActionList", &[ "Action" ], None),
+    ("Action", &[ "Animation", "ActionReference", "Action" ], None),
+    ("Animation", &[ "Pose" ], Some(&[ "Condition", "IsTurn" ])),
+    ("Pose", &[], Some(&[ "Image", "ImageRight", "ImageAnchor", "Velocity", "Duration", "Sound", "Volume" ])),
+];
+
+/// What can be in `behaviors.xml`.
+const BEHAVIORS: &[Element] = &[
+    ("Mascot", &[ "BehaviorList" ], None),
+    ("BehaviorList", &[ "Behavior", "Condition" ], None),
+    ("Condition", &[ "Behavior", "BehaviorReference", "Condition" ], Some(&[ "Condition" ])),
+    ("Behavior", &[ "NextBehaviorList" ], Some(&[ "Name", "Action", "Frequency", "Hidden", "Condition" ])),
+    ("NextBehaviorList", &[ "BehaviorReference", "Condition" ], Some(&[ "Add" ])),
+    ("BehaviorReference", &[], Some(&[ "Name", "Frequency", "Condition" ])),
+];
+
+/// Returns whether the directory at `path` looks like a Shimeji mascot.
+pub fn detect(files: &Files, path: &Path) -> bool {
+    find_conf(files, path, "actions.xml").is_some()
+}
+
+/// Looks for one of the files that usually go in `conf`, also accepting it
+/// next to the images.
+fn find_conf(files: &Files, path: &Path, name: &str) -> Option<PathBuf> {
+    vec![ path.join("conf").join(name), path.join(name) ]
+        .into_iter()
+        .find(|path| files.is_file(path))
+}
+
+fn read(files: &Files, path: &Path) -> Result<String, AssetError> {
+    files.read_to_string(path)
+        .map_err(|error| AssetError::read(path, error))
+}
+
+fn parse<'a>(path: &Path, text: &'a str) -> Result<Document<'a>, AssetError> {
+    Document::parse(text)
+        .map_err(|error| AssetError::invalid(path, error))
+}
+
+pub fn load(name: &str, files: &Files, path: &Path) -> Result<Pack, AssetError> {
+    let actions_path = find_conf(files, path, "actions.xml")
+        .ok_or_else(|| AssetError::invalid(path, "There isn't an actions.xml in it."))?;
+    let actions = read(files, &actions_path)?;
+    let actions = parse(&actions_path, &actions)?;
+
+    let images = if files.is_dir(&path.join("img")) {
+        path.join("img")
+    } else {
+        path.to_owned()
+    };
+
+    let mut pack = Pack {
+        name: name.to_owned(),
+        frames: Vec::new(),
+        sheets: Vec::new(),
+        frame_size: (0, 0),
+        anchor: (0.0, 0.0),
+        animations: Vec::new(),
+        layers: Vec::new(),
+        accessories: Vec::new(),
+        emitters: Vec::new(),
+        events: Vec::new(),
+        shader: None,
+        filter: None,
+        motion: Motion::default(),
+        facing: Facing::Left,
+        warnings: super::unknown_xml(actions.root_element(), ACTIONS),
+    };
+
+    // Maps the image paths used by poses to their index in `pack.frames`, so
+    // that images used by several actions are only loaded once.
+    let mut loaded = HashMap::<String, usize>::new();
+
+    let list = actions
+        .descendants()
+        .filter(|node| node.has_tag_name("Action"))
+        .filter(|node| node.parent().is_some_and(|parent| parent.has_tag_name("ActionList")));
+
+    for action in list {
+        let action_name = action.attribute("Name").unwrap_or("");
+        let action_type = action.attribute("Type").unwrap_or("");
+
+        let kind = match (action_type, action.attribute("Class")) {
+            ("Stay", _) | ("Move", _) | ("Animate", _) => AnimationKind::Ground,
+            ("Fall", _) => AnimationKind::Fall,
+            ("Dragged", _) => AnimationKind::Drag,
+            ("Embedded", Some(class)) if class.ends_with(".Fall") => AnimationKind::Fall,
+            ("Embedded", Some(class)) if class.ends_with(".Dragged") => AnimationKind::Drag,
+            ("Embedded", Some(class)) => {
+                pack.warnings.push(format!(
+                    "Action \"{}\" uses {}, which isn't supported, so it was skipped.",
+                    action_name,
+                    class));
+                continue;
+            },
+            _ => {
+                pack.warnings.push(format!(
+                    "Action \"{}\" is a {} action, which isn't supported, so it was skipped.",
+                    action_name,
+                    action_type));
+                continue;
+            },
+        };
+
+        let poses = action
+            .children()
+            .find(|node| node.has_tag_name("Animation"))
+            .map(|animation| animation
+                .children()
+                .filter(|node| node.has_tag_name("Pose"))
+                .collect::<Vec<Node>>())
+            .unwrap_or_default();
+
+        if poses.is_empty() {
+            pack.warnings.push(format!(
+                "Action \"{}\" doesn't have any poses, so it was skipped.",
+                action_name));
+            continue;
+        }
+
+        let mut frames = Vec::new();
+
+        for pose in poses {
+            let image = pose.attribute("Image").unwrap_or("");
+            let frame = match loaded.get(image) {
+                Some(&frame) => frame,
+                None => {
+                    let file = images.join(image.trim_start_matches('/'));
+                    let bytes = files.read(&file)
+                        .map_err(|error| AssetError::read(&file, error))?;
+                    if pack.frames.is_empty() {
+                        pack.frame_size = super::frame_size(&file, &bytes)?;
+                        pack.anchor = super::default_anchor(pack.frame_size);
+                    }
+                    pack.frames.push(FrameSource::Encoded { file, bytes: Cow::Owned(bytes) });
+                    loaded.insert(image.to_owned(), pack.frames.len() - 1);
+                    pack.frames.len() - 1
+                },
+            };
+
+            let duration = pose.attribute("Duration")
+                .and_then(|duration| duration.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            // Shimeji characters face left, so moving left is moving forward.
+            let velocity = parse_pair(pose.attribute("Velocity").unwrap_or("0,0"))
+                .map(|(x, y)| (-x * (1000.0 / TICK), y * (1000.0 / TICK)))
+                .unwrap_or((0.0, 0.0));
+
+            // The anchor is the point of the image that Shimeji puts on the
+            // ground, which is the bottom middle for most mascots.
+            let anchor = pose.attribute("ImageAnchor").and_then(parse_pair);
+
+            frames.push(AnimationFrame {
+                frame,
+                duration: (duration * TICK).max(1.0) as u32,
+                velocity,
+                anchor,
+            });
+        }
+
+        pack.animations.push(Animation {
+            name: action_name.to_owned(),
+            kind,
+            frames,
+            weight: if kind == AnimationKind::Ground { 1 } else { 0 },
+            next: Vec::new(),
+            border: Vec::new(),
+        });
+    }
+
+    if pack.animations.is_empty() {
+        return Err(AssetError::invalid(
+            &actions_path,
+            "There aren't any actions in it that can be imported."));
+    }
+
+    if let Some(behaviors_path) = find_conf(files, path, "behaviors.xml") {
+        let behaviors = read(files, &behaviors_path)?;
+        let behaviors = parse(&behaviors_path, &behaviors)?;
+        pack.warnings.extend(super::unknown_xml(behaviors.root_element(), BEHAVIORS));
+        apply_behaviors(&mut pack, &behaviors);
+    }
+
+    Ok(pack)
+}
+
+/// Turns the frequencies in `behaviors.xml` into animation weights, and the
+/// `NextBehaviorList`s into `Animation::next`.
+fn apply_behaviors(pack: &mut Pack, behaviors: &Document) {
+    let list = behaviors
+        .descendants()
+        .filter(|node| node.has_tag_name("Behavior"))
+        .collect::<Vec<Node>>();
+
+    // Behaviors play the action with the same name, unless they say
+    // otherwise.
+    let actions = list
+        .iter()
+        .filter_map(|behavior| {
+            let name = behavior.attribute("Name")?;
+            Some((name, behavior.attribute("Action").unwrap_or(name)))
+        })
+        .collect::<HashMap<&str, &str>>();
+
+    for animation in pack.animations.iter_mut() { animation.weight = 0; }
+
+    if behaviors.descendants().any(|node| node.has_tag_name("Condition")) {
+        pack.warnings.push(
+            "Conditions in behaviors.xml aren't supported, so every behavior \
+            can happen at any time.".to_owned());
+    }
+
+    for behavior in list {
+        let name = behavior.attribute("Name").unwrap_or("");
+        let action = actions.get(name).copied().unwrap_or(name);
+
+        let index = match pack.animation(action) {
+            Some(index) => index,
+            None => {
+                pack.warnings.push(format!(
+                    "Behavior \"{}\" plays action \"{}\", which wasn't imported.",
+                    name,
+                    action));
+                continue;
+            },
+        };
+
+        let weight = frequency(&behavior);
+
+        let next = behavior
+            .children()
+            .filter(|node| node.has_tag_name("NextBehaviorList"))
+            .flat_map(|list| list.children())
+            .filter(|node| node.has_tag_name("BehaviorReference"))
+            .filter_map(|reference| {
+                let name = reference.attribute("Name")?;
+                let action = actions.get(name).copied().unwrap_or(name);
+                Some((action.to_owned(), frequency(&reference)))
+            })
+            .collect::<Vec<_>>();
+
+        let animation = &mut pack.animations[index];
+        if animation.kind == AnimationKind::Ground {
+            animation.weight += weight;
+        }
+        animation.next.extend(next);
+    }
+}
+
+fn frequency(node: &Node) -> u32 {
+    node.attribute("Frequency")
+        .and_then(|frequency| frequency.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Parses a pair of numbers like `"-2,0"`.
+fn parse_pair(text: &str) -> Option<(f32, f32)> {
+    let mut parts = text.split(',');
+    let x = parts.next()?.trim().parse().ok()?;
+    let y = parts.next()?.trim().parse().ok()?;
+    Some((x, y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pack::files::fixture;
+
+    const ACTIONS: &str = r#"
+        <Mascot>
+          <ActionList>
+            <Action Name="Stand" Type="Stay">
+              <Animation>
+                <Pose Image="/shime1.png" ImageAnchor="64,128" Velocity="0,0" Duration="250" />
+              </Animation>
+            </Action>
+            <Action Name="Walk" Type="Move">
+              <Animation>
+                <Pose Image="/shime1.png" ImageAnchor="64,128" Velocity="-2,0" Duration="6" />
+                <Pose Image="/shime2.png" ImageAnchor="64,128" Velocity="-2,0" Duration="6" />
+              </Animation>
+            </Action>
+            <Action Name="Falling" Type="Embedded" Class="com.group_finity.mascot.action.Fall">
+              <Animation>
+                <Pose Image="/shime3.png" ImageAnchor="64,128" Velocity="0,0" Duration="250" />
+              </Animation>
+            </Action>
+            <Action Name="Pinched" Type="Embedded" Class="com.group_finity.mascot.action.Dragged">
+              <Animation>
+                <Pose Image="/shime3.png" ImageAnchor="64,128" Velocity="0,0" Duration="250" />
+              </Animation>
+            </Action>
+            <Action Name="Empty" Type="Stay" />
+          </ActionList>
+        </Mascot>
+    "#;
+
+    const BEHAVIORS: &str = r#"
+        <Mascot>
+          <BehaviorList>
+            <Behavior Name="StandUp" Action="Stand" Frequency="200" />
+            <Behavior Name="Walk" Frequency="50">
+              <NextBehaviorList Add="false">
+                <BehaviorReference Name="StandUp" Frequency="10" />
+              </NextBehaviorList>
+            </Behavior>
+            <Behavior Name="Falling" Frequency="30" />
+            <Behavior Name="Sit" Frequency="5" />
+          </BehaviorList>
+        </Mascot>
+    "#;
+
+    fn mascot(behaviors: bool) -> Files {
+        let mut files = vec![
+            ("conf/actions.xml", ACTIONS.as_bytes().to_vec()),
+            ("img/shime1.png", fixture::png(128, 128)),
+            ("img/shime2.png", fixture::png(128, 128)),
+            ("img/shime3.png", fixture::png(128, 128)),
+        ];
+        if behaviors { files.push(("conf/behaviors.xml", BEHAVIORS.as_bytes().to_vec())); }
+        fixture::files(&files)
+    }
+
+    #[test]
+    fn imports_supported_actions() {
+        let files = mascot(false);
+        assert!(detect(&files, &fixture::root()));
+        let pack = load("mascot", &files, &fixture::root()).unwrap();
+
+        let names = pack.animations.iter().map(|animation| animation.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, [ "Stand", "Walk", "Falling", "Pinched" ]);
+        assert_eq!(pack.animations[2].kind, AnimationKind::Fall);
+        assert_eq!(pack.animations[3].kind, AnimationKind::Drag);
+        assert_eq!(pack.frames.len(), 3, "images shared by actions are only loaded once");
+        assert_eq!(pack.frame_size, (128, 128));
+
+        let walk = &pack.animations[1];
+        assert_eq!(walk.frames[0].duration, 240);
+        assert_eq!(walk.frames[0].velocity, (50.0, 0.0));
+        assert_eq!(walk.frames[0].anchor, Some((64.0, 128.0)));
+        assert_eq!(walk.frames[1].frame, 1);
+
+        assert!(!pack.warnings.iter().any(|warning| warning.contains("Pinched")));
+        assert!(pack.warnings.iter().any(|warning| warning.contains("Empty")));
+    }
+
+    #[test]
+    fn reads_frequencies() {
+        let pack = load("mascot", &mascot(true), &fixture::root()).unwrap();
+
+        let weights = pack.animations.iter().map(|animation| animation.weight).collect::<Vec<_>>();
+        assert_eq!(weights, [ 200, 50, 0, 0 ], "only ground animations are picked at random");
+        assert_eq!(pack.animations[1].next, [ ("Stand".to_owned(), 10) ]);
+        assert!(pack.warnings.iter().any(|warning| warning.contains("\"Sit\"")));
+    }
+
+    #[test]
+    fn lists_unknown_elements_and_attributes() {
+        let files = fixture::files(&[
+            ("actions.xml", br#"
+                <Mascot>
+                  <ActionList>
+                    <Action Name="Stand" Type="Stay" BorderType="Floor">
+                      <Animation>
+                        <Pose Image="/shime1.png" Durration="250" />
+                      </Animation>
+                      <Animations />
+                    </Action>
+                  </ActionList>
+                </Mascot>
+            "#.to_vec()),
+            ("shime1.png", fixture::png(128, 128)),
+        ]);
+        let pack = load("mascot", &files, &fixture::root()).unwrap();
+        assert_eq!(pack.warnings, [
+            "Unknown attribute \"Durration\" on <Pose>, so it was ignored.",
+            "Unknown element <Animations> in <Action>, so it was ignored.",
+        ]);
+    }
+
+    #[test]
+    fn needs_an_action_that_can_be_imported() {
+        let files = fixture::files(&[
+            ("actions.xml", br#"<Mascot><ActionList><Action Name="Jump" Type="Jump" /></ActionList></Mascot>"#.to_vec()),
+        ]);
+        assert!(load("mascot", &files, &fixture::root()).is_err());
+    }
+}
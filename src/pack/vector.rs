@@ -0,0 +1,93 @@
+//! Reads SVG frames.
+//!
+//! Any frame or accessory image can be an SVG instead of a PNG, like
+//! `frame01.svg`. Instead of being decoded at a fixed size and then resized
+//! like other images, they're drawn straight at the size the companion is on
+//! screen, so they stay sharp at any size and on any display. Text isn't
+//! drawn, so it has to be turned into paths before the file is saved.
+
+use resvg;
+use tiny_skia;
+use usvg;
+
+use std::path::Path;
+
+use image::{ Rgba, RgbaImage };
+
+use crate::assets;
+
+use super::AssetError;
+
+/// Returns whether `file` is an SVG, going by its extension.
+pub fn is_svg(file: &Path) -> bool {
+    file.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("svg"))
+}
+
+/// Returns the size an SVG says it is, rounded up to whole pixels. Anchors,
+/// offsets and velocities are given in pixels of this size, the same as for
+/// other images.
+pub fn size(file: &Path, bytes: &[u8]) -> Result<(u32, u32), AssetError> {
+    Ok(tree_size(&parse(file, bytes)?))
+}
+
+/// Draws an SVG as big as it fits in `size`, keeping its aspect ratio. Like
+/// decoded images, it comes out with straight alpha.
+pub fn render(file: &Path, bytes: &[u8], size: (u32, u32)) -> Result<RgbaImage, AssetError> {
+    let tree = parse(file, bytes)?;
+    let (width, height) = assets::fit(tree_size(&tree), size);
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)
+        .ok_or_else(|| AssetError::decode(file, format!("It can't be drawn at {}x{}.", width, height)))?;
+    resvg::render(&tree, usvg::FitTo::Size(width, height), tiny_skia::Transform::default(), pixmap.as_mut())
+        .ok_or_else(|| AssetError::decode(file, "It doesn't have a size to draw it at."))?;
+
+    let mut image = RgbaImage::new(width, height);
+    for (pixel, color) in image.pixels_mut().zip(pixmap.pixels()) {
+        let color = color.demultiply();
+        *pixel = Rgba([ color.red(), color.green(), color.blue(), color.alpha() ]);
+    }
+    Ok(image)
+}
+
+fn parse(file: &Path, bytes: &[u8]) -> Result<usvg::Tree, AssetError> {
+    usvg::Tree::from_data(bytes, &usvg::Options::default().to_ref())
+        .map_err(|error| AssetError::decode(file, error))
+}
+
+fn tree_size(tree: &usvg::Tree) -> (u32, u32) {
+    let size = tree.svg_node().size;
+    (size.width().ceil().max(1.0) as u32, size.height().ceil().max(1.0) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SVG: &[u8] = br##"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10">
+        <rect width="10" height="10" fill="#ff0000"/>
+        <rect x="10" width="10" height="10" fill="#0000ff" fill-opacity="0.5"/>
+    </svg>"##;
+
+    #[test]
+    fn reads_the_size() {
+        assert_eq!(size(Path::new("frame01.svg"), SVG).unwrap(), (20, 10));
+        assert!(size(Path::new("frame01.svg"), b"not an svg").is_err());
+    }
+
+    #[test]
+    fn draws_at_the_size_it_fits() {
+        for &(bounds, drawn) in [ ((40, 40), (40, 20)), ((20, 10), (20, 10)), ((7, 100), (7, 3)) ].iter() {
+            let image = render(Path::new("frame01.svg"), SVG, bounds).unwrap();
+            assert_eq!(image.dimensions(), drawn);
+            assert_eq!(drawn, assets::fit((20, 10), bounds));
+        }
+
+        // It comes out with straight alpha, not premultiplied.
+        let image = render(Path::new("frame01.svg"), SVG, (40, 40)).unwrap();
+        assert_eq!(image.get_pixel(5, 10).0, [ 255, 0, 0, 255 ]);
+        let [ r, g, b, a ] = image.get_pixel(35, 10).0;
+        assert_eq!((r, g), (0, 0));
+        assert!(b >= 254 && (a as i32 - 128).abs() <= 1, "{:?}", (b, a));
+    }
+}
@@ -0,0 +1,226 @@
+//! Particles that packs can give off, like bubbles trailing behind a
+//! swimming shark.
+//!
+//! Packs describe what gives them off in their manifest (see
+//! `pack::Emitter`), and each companion keeps track of its own. They're
+//! drawn with the same pipeline as everything else, using images that are
+//! loaded along with the frames, and there can only be `MAX_PARTICLES` on
+//! screen at once, so that they stay cheap however many companions there
+//! are.
+
+use crate::misc::Rng;
+use crate::pack::Pack;
+
+/// The most particles there can be at once, across every companion. Emitters
+/// stop giving off new ones until some of them are gone.
+pub const MAX_PARTICLES: usize = 128;
+
+/// A single particle.
+#[derive(Debug, Clone)]
+pub struct Particle {
+    /// The index in `Pack::layers` of its image.
+    pub layer: usize,
+    /// Where its top left corner is, in pixels on screen.
+    pub pos: (f32, f32),
+    pub size: (i32, i32),
+    /// Whether its image is mirrored, because the frames were when it was
+    /// given off.
+    pub mirrored: bool,
+    /// How fast it's moving, in pixels on screen per second.
+    velocity: (f32, f32),
+    /// How fast it speeds up downwards, in pixels on screen per second per
+    /// second.
+    gravity: f32,
+    /// How long it's been around for, in seconds.
+    age: f32,
+    lifetime: f32,
+}
+
+/// The particles of a single companion.
+#[derive(Debug, Clone)]
+pub struct Particles {
+    pub list: Vec<Particle>,
+    /// For each of `Pack::emitters`, the part of a particle it's given off so
+    /// far, for emitters that give off less than one a tick.
+    carry: Vec<f32>,
+    rng: Rng,
+}
+
+/// Where the frame that's on screen is, which particles start out relative
+/// to.
+#[derive(Debug, Copy, Clone)]
+pub struct Placement {
+    /// Where the frame's top left corner is, in pixels on screen.
+    pub pos: (f32, f32),
+    /// How many pixels on screen a pixel of the original frames is.
+    pub scale: (f32, f32),
+    pub mirrored: bool,
+    pub go_right: bool,
+}
+
+impl Particle {
+    /// Returns how opaque the particle is. It fades out over the second half
+    /// of its life.
+    pub fn opacity(&self) -> f32 {
+        ((1.0 - self.age / self.lifetime) * 2.0).clamp(0.0, 1.0)
+    }
+}
+
+impl Particles {
+    pub fn new(rng: Rng) -> Self {
+        Particles {
+            list: Vec::new(),
+            carry: Vec::new(),
+            rng,
+        }
+    }
+
+    /// Moves every particle forward by `dt` seconds, and removes the ones
+    /// that have run out of time.
+    pub fn update(&mut self, dt: f32) {
+        for particle in self.list.iter_mut() {
+            particle.velocity.1 += particle.gravity * dt;
+            particle.pos.0 += particle.velocity.0 * dt;
+            particle.pos.1 += particle.velocity.1 * dt;
+            particle.age += dt;
+        }
+        self.list.retain(|particle| particle.age < particle.lifetime);
+    }
+
+    /// Gives off `count` particles from the emitter at `index` in
+    /// `pack.emitters`, from a frame at `placement`. Fractions of a particle
+    /// are kept until they add up to a whole one. `budget` is how many more
+    /// particles there can be, and goes down by as many as are given off.
+    pub fn emit(&mut self, pack: &Pack, index: usize, count: f32, placement: &Placement, budget: &mut usize) {
+        let emitter = &pack.emitters[index];
+        if emitter.lifetime <= 0.0 { return; }
+        if self.carry.len() < pack.emitters.len() { self.carry.resize(pack.emitters.len(), 0.0); }
+        self.carry[index] += count;
+        let whole = self.carry[index].floor();
+        self.carry[index] -= whole;
+
+        let (scale_x, scale_y) = placement.scale;
+        let (w, h) = pack.layers[emitter.layer].size;
+        let size = (
+            ((w as f32) * scale_x).round().max(1.0) as i32,
+            ((h as f32) * scale_y).round().max(1.0) as i32,
+        );
+        // The offset is on the frame, so it's mirrored along with it, and
+        // the velocity follows the way the companion is heading.
+        let offset_x = if placement.mirrored { pack.frame_size.0 as f32 - emitter.offset.0 } else { emitter.offset.0 };
+        let direction = if placement.go_right { 1.0 } else { -1.0 };
+
+        for _ in 0..(whole as usize).min(*budget) {
+            let mut spread = || self.rng.next_f32() * 2.0 - 1.0;
+            let velocity = (
+                (emitter.velocity.0 * direction + emitter.spread.0 * spread()) * scale_x,
+                (emitter.velocity.1 + emitter.spread.1 * spread()) * scale_y,
+            );
+            self.list.push(Particle {
+                layer: emitter.layer,
+                pos: (
+                    placement.pos.0 + offset_x * scale_x - size.0 as f32 / 2.0,
+                    placement.pos.1 + emitter.offset.1 * scale_y - size.1 as f32 / 2.0,
+                ),
+                size,
+                mirrored: placement.mirrored,
+                velocity,
+                gravity: emitter.gravity * scale_y,
+                age: 0.0,
+                lifetime: emitter.lifetime,
+            });
+            *budget -= 1;
+        }
+    }
+
+    /// Removes every particle, for when the pack changes and their images
+    /// might not be there anymore.
+    pub fn clear(&mut self) {
+        self.list.clear();
+        self.carry.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pack::{ Emitter, Trigger };
+
+    /// Returns the built-in pack with a single emitter whose particles go
+    /// straight right and last for `lifetime` seconds.
+    fn pack(lifetime: f32) -> Pack {
+        let mut pack = Pack::builtin();
+        let layer = pack.emitters[0].layer;
+        pack.emitters = vec![ Emitter {
+            layer,
+            trigger: Trigger::Moving,
+            rate: 1.0,
+            lifetime,
+            offset: (0.0, 0.0),
+            velocity: (10.0, 0.0),
+            spread: (0.0, 0.0),
+            gravity: 0.0,
+        } ];
+        pack
+    }
+
+    const PLACEMENT: Placement = Placement { pos: (0.0, 0.0), scale: (1.0, 1.0), mirrored: false, go_right: true };
+
+    #[test]
+    fn carries_fractions_of_a_particle() {
+        let pack = pack(1.0);
+        let mut particles = Particles::new(Rng::new(1));
+        let mut budget = MAX_PARTICLES;
+
+        particles.emit(&pack, 0, 0.4, &PLACEMENT, &mut budget);
+        particles.emit(&pack, 0, 0.4, &PLACEMENT, &mut budget);
+        assert_eq!(particles.list.len(), 0);
+        particles.emit(&pack, 0, 0.4, &PLACEMENT, &mut budget);
+        assert_eq!(particles.list.len(), 1);
+        particles.emit(&pack, 0, 2.8, &PLACEMENT, &mut budget);
+        assert_eq!(particles.list.len(), 4);
+        assert_eq!(budget, MAX_PARTICLES - 4);
+    }
+
+    #[test]
+    fn stops_at_the_budget() {
+        let pack = pack(1.0);
+        let mut particles = Particles::new(Rng::new(1));
+        let mut budget = 3;
+
+        particles.emit(&pack, 0, 5.0, &PLACEMENT, &mut budget);
+        assert_eq!(particles.list.len(), 3);
+        assert_eq!(budget, 0);
+        particles.emit(&pack, 0, 5.0, &PLACEMENT, &mut budget);
+        assert_eq!(particles.list.len(), 3);
+    }
+
+    #[test]
+    fn skips_emitters_without_a_lifetime() {
+        let pack = pack(0.0);
+        let mut particles = Particles::new(Rng::new(1));
+        let mut budget = MAX_PARTICLES;
+
+        particles.emit(&pack, 0, 5.0, &PLACEMENT, &mut budget);
+        assert!(particles.list.is_empty());
+        assert_eq!(budget, MAX_PARTICLES);
+    }
+
+    #[test]
+    fn fades_out_and_goes_away() {
+        let pack = pack(2.0);
+        let mut particles = Particles::new(Rng::new(1));
+        let mut budget = MAX_PARTICLES;
+        particles.emit(&pack, 0, 1.0, &PLACEMENT, &mut budget);
+        let start = particles.list[0].pos.0;
+
+        particles.update(0.5);
+        assert_eq!(particles.list[0].opacity(), 1.0);
+        assert_eq!(particles.list[0].pos.0, start + 5.0);
+        particles.update(1.0);
+        assert_eq!(particles.list[0].opacity(), 0.5);
+        particles.update(0.5);
+        assert!(particles.list.is_empty());
+    }
+}
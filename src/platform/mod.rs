@@ -1 +1,2 @@
 #[cfg(platform_windows)] pub mod windows;
+#[cfg(platform_unix)] pub mod unix;
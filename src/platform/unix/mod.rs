@@ -0,0 +1,53 @@
+use raw_window_handle;
+use glium;
+
+use std::os::raw::{ c_int, c_ulong, c_void };
+use raw_window_handle::*;
+use glium::Display;
+
+// `x11-dl` doesn't expose the shape extension, so we link against it
+// ourselves. Pretty much every X11 desktop ships libXext.
+#[link(name = "Xext")]
+extern "C" {
+    fn XShapeCombineRectangles(
+        display: *mut c_void,
+        window: c_ulong,
+        dest_kind: c_int,
+        x_off: c_int,
+        y_off: c_int,
+        rectangles: *mut c_void,
+        n_rects: c_int,
+        op: c_int,
+        ordering: c_int);
+}
+
+const SHAPE_SET: c_int = 0;
+const SHAPE_INPUT: c_int = 2;
+const UNSORTED: c_int = 0;
+
+/// Sets the attributes of the window associated with `display`.
+///
+/// In particular, this ensures that the window does not receive user input
+/// -- it instead passes down to whatever window is behind it. This matters
+/// most for `Config::overlay`, where the window covers the whole screen.
+///
+/// This is only possible on X11. On Wayland, the window will still catch
+/// clicks.
+pub fn configure_window(display: &Display) {
+    match display.gl_window().window().raw_window_handle() {
+        RawWindowHandle::Xlib(window) => unsafe {
+            // Setting the input shape of a window to an empty list of
+            // rectangles makes every click fall through it.
+            XShapeCombineRectangles(
+                window.display,
+                window.window,
+                SHAPE_INPUT,
+                0, 0,
+                std::ptr::null_mut(),
+                0,
+                SHAPE_SET,
+                UNSORTED);
+        },
+        _ => eprintln!("Click-through windows are only supported on X11."),
+    }
+}
@@ -61,3 +61,12 @@ pub fn configure_window(display: &Display) {
         _ => unreachable!(),
     }
 }
+
+/// Attaches to the console of the process that started us, if there is one,
+/// so that subcommands can print to it. Since desktop sharkie is a windowed
+/// program, Windows doesn't give it a console of its own.
+pub fn attach_console() {
+    unsafe {
+        winapi::um::wincon::AttachConsole(winapi::um::wincon::ATTACH_PARENT_PROCESS);
+    }
+}